@@ -0,0 +1,470 @@
+use std::cmp;
+
+use bits::{BitReader, BitWriter};
+use codec::{self, Capability, Codec, DecodeError, DecodeOutput, ErrorLocation, Report, Unit};
+use gf2m::{self, Field};
+
+// Binary BCH codes, which correct up to `t` flipped bits anywhere in each block
+// of `n = 2^m - 1` bits. Treating a block as a polynomial over GF(2) with the
+// first bit the highest degree coefficient, every block is a multiple of the
+// generator: the product of the distinct minimal polynomials of `a^1` to `a^2t`,
+// where `a` generates GF(2^m). Its degree is the number of parity bits, at most
+// `m * t`. Blocks are systematic: the data comes first, followed by the
+// remainder of the data times `x^parity` divided by the generator.
+//
+// Decoding divides the block by the generator the same way. A zero remainder
+// means the block is valid, which is by far the most common case. Otherwise the
+// syndromes `S_j = r(a^j)` are evaluated from the remainder, Berlekamp-Massey
+// finds the error locator polynomial, and a Chien search finds its roots, the
+// inverses of `a^i` for each error at degree `i`. If it doesn't have as many
+// roots as its degree, there are more errors than can be corrected.
+//
+// Remainders are computed a byte at a time with a table, like a CRC, and held
+// in words with the highest degree coefficient the most significant bit of the
+// first word.
+
+/// The most errors a block can correct.
+pub const MAX_ERRORS: usize = 64;
+
+// Multiplies two polynomials over GF(2) with the lowest degree coefficient first.
+fn binary_mul(a: &[bool], b: &[bool]) -> Vec<bool> {
+    let mut product = vec![false; a.len() + b.len() - 1];
+    for (i, _) in a.iter().enumerate().filter(|&(_, &x)| x) {
+        for (j, _) in b.iter().enumerate().filter(|&(_, &y)| y) {
+            product[i + j] ^= true;
+        }
+    }
+    product
+}
+
+// The generator polynomial for correcting `t` errors, lowest degree first.
+fn generator(field: &Field, t: usize) -> Vec<bool> {
+    let order = field.order();
+    let mut g = vec![true];
+    let mut done = vec![false; order];
+    for i in 1..=2 * t {
+        let i = i % order;
+        if done[i] {
+            continue;
+        }
+        // the minimal polynomial of a^i has a root for each of its conjugates a^(i * 2^k)
+        let mut minimal = vec![1];
+        let mut j = i;
+        loop {
+            done[j] = true;
+            let root = field.power(j);
+            let mut next = vec![0; minimal.len() + 1];
+            for (k, &c) in minimal.iter().enumerate() {
+                next[k] ^= field.mul(c, root);
+                next[k + 1] ^= c;
+            }
+            minimal = next;
+            j = j * 2 % order;
+            if j == i {
+                break;
+            }
+        }
+        let minimal: Vec<bool> = minimal.iter().map(|&c| c == 1).collect();
+        g = binary_mul(&g, &minimal);
+    }
+    g
+}
+
+// Shifts a left aligned register `n` bits towards the most significant end.
+fn shift(register: &mut [u64], n: usize) {
+    for i in 0..register.len() {
+        let next = register.get(i + 1).map_or(0, |&word| word >> (64 - n));
+        register[i] = register[i] << n | next;
+    }
+}
+
+fn xor(register: &mut [u64], other: &[u64]) {
+    for (word, &other) in register.iter_mut().zip(other) {
+        *word ^= other;
+    }
+}
+
+/// Binary BCH code over GF(2^m), correcting up to `t` bits in each block of `2^m - 1` bits.
+pub struct Bch {
+    field: Field,
+    t: usize,
+    /// The number of parity bits in a block, the degree of the generator.
+    parity: usize,
+    /// The generator without its leading term, left aligned.
+    generator: Vec<u64>,
+    /// The remainder of each byte times `x^parity`, if there are at least eight parity bits.
+    table: Vec<Vec<u64>>,
+}
+
+impl Bch {
+    /// # Panics
+    ///
+    /// Panics if `m` is not between 3 and 16, or if `t` is 0, more than
+    /// `MAX_ERRORS` or so large that a block has no room for data.
+    pub fn new(m: usize, t: usize) -> Bch {
+        Bch::try_new(m, t).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn build(parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+        match *parameters {
+            [m, t] => Ok(Box::new(Bch::try_new(m, t)?)),
+            _ => Err(format!("expected the field degree and the number of errors to correct, not {} parameters",
+                parameters.len())),
+        }
+    }
+
+    fn try_new(m: usize, t: usize) -> Result<Bch, String> {
+        if !(gf2m::MIN_DEGREE..=gf2m::MAX_DEGREE).contains(&m) {
+            return Err(format!("the field degree must be between {} and {}, not {}",
+                gf2m::MIN_DEGREE, gf2m::MAX_DEGREE, m));
+        }
+        if !(1..=MAX_ERRORS).contains(&t) {
+            return Err(format!("the number of errors to correct must be between 1 and {}, not {}", MAX_ERRORS, t));
+        }
+        let field = Field::new(m);
+        let g = generator(&field, t);
+        let parity = g.len() - 1;
+        if parity >= field.order() {
+            return Err(format!("blocks of {} bits can't correct {} errors", field.order(), t));
+        }
+        let mut generator = vec![0; parity.div_ceil(64)];
+        for q in 0..parity {
+            if g[parity - 1 - q] {
+                generator[q / 64] |= 1 << (63 - q % 64);
+            }
+        }
+        let mut bch = Bch { field, t, parity, generator, table: Vec::new() };
+        if parity >= 8 {
+            bch.table = (0..256).map(|byte| {
+                let mut register = vec![0; bch.generator.len()];
+                for bit in (0..8).rev() {
+                    bch.feed_bit(&mut register, byte >> bit & 1);
+                }
+                register
+            }).collect();
+        }
+        Ok(bch)
+    }
+
+    /// The length of a block in bits.
+    fn block_len(&self) -> usize {
+        self.field.order()
+    }
+
+    /// The number of data bits in a block.
+    fn data_len(&self) -> usize {
+        self.block_len() - self.parity
+    }
+
+    fn feed_bit(&self, register: &mut [u64], bit: u64) {
+        let feedback = register[0] >> 63 ^ bit;
+        shift(register, 1);
+        if feedback == 1 {
+            xor(register, &self.generator);
+        }
+    }
+
+    // The remainder of the data of a block times `x^parity`, divided by the generator.
+    fn remainder(&self, data: &[u64], register: &mut [u64]) {
+        for word in register.iter_mut() {
+            *word = 0;
+        }
+        let bytes = if self.table.is_empty() { 0 } else { self.data_len() / 8 };
+        for i in 0..bytes {
+            let byte = data[i / 8] >> (56 - 8 * (i % 8)) & 0xFF;
+            let index = (register[0] >> 56 ^ byte) as usize;
+            shift(register, 8);
+            xor(register, &self.table[index]);
+        }
+        for q in 8 * bytes..self.data_len() {
+            self.feed_bit(register, data[q / 64] >> (63 - q % 64) & 1);
+        }
+    }
+
+    // The positions in the block of the errors that give the remainder
+    // `remainder`, or `None` if there are too many to correct.
+    fn locate(&self, remainder: &[u64]) -> Option<Vec<usize>> {
+        let field = &self.field;
+        let order = field.order();
+        let checks = 2 * self.t;
+        // the syndromes, from S_1; S_2j is S_j squared
+        let mut syndromes = vec![0; checks + 1];
+        for q in (0..self.parity).filter(|&q| remainder[q / 64] >> (63 - q % 64) & 1 == 1) {
+            let degree = self.parity - 1 - q;
+            for j in (1..=checks).step_by(2) {
+                syndromes[j] ^= field.power(j * degree % order);
+            }
+        }
+        for j in (2..=checks).step_by(2) {
+            syndromes[j] = field.mul(syndromes[j / 2], syndromes[j / 2]);
+        }
+
+        // Berlekamp-Massey
+        let mut locator = vec![0; checks + 2];
+        locator[0] = 1;
+        let mut previous = locator.clone();
+        let (mut len, mut gap, mut last) = (0, 1, 1);
+        for r in 0..checks {
+            let mut discrepancy = syndromes[r + 1];
+            for i in 1..=len {
+                discrepancy ^= field.mul(locator[i], syndromes[r + 1 - i]);
+            }
+            if discrepancy == 0 {
+                gap += 1;
+                continue;
+            }
+            let scale = field.div(discrepancy, last);
+            let before = locator.clone();
+            for i in 0..locator.len() - gap {
+                locator[i + gap] ^= field.mul(scale, previous[i]);
+            }
+            if 2 * len <= r {
+                len = r + 1 - len;
+                previous = before;
+                last = discrepancy;
+                gap = 1;
+            } else {
+                gap += 1;
+            }
+        }
+        if len > self.t {
+            return None;
+        }
+
+        // Chien search: the error at degree i is a root at a^-i
+        let terms: Vec<(usize, usize)> = (0..=len).filter(|&j| locator[j] != 0)
+            .map(|j| (field.log(locator[j]), (order - j % order) % order))
+            .collect();
+        let mut exponents: Vec<usize> = terms.iter().map(|&(log, _)| log).collect();
+        let mut positions = Vec::with_capacity(len);
+        for degree in 0..order {
+            let mut value = 0;
+            for (exponent, &(_, step)) in exponents.iter_mut().zip(&terms) {
+                value ^= field.power(*exponent);
+                *exponent += step;
+                if *exponent >= order {
+                    *exponent -= order;
+                }
+            }
+            if value == 0 {
+                positions.push(order - 1 - degree);
+                if positions.len() == len {
+                    break;
+                }
+            }
+        }
+        if positions.len() == len { Some(positions) } else { None }
+    }
+
+    fn encode_blocks(&self, data: &[u8]) -> Vec<u8> {
+        let (len, data_len) = (self.block_len(), self.data_len());
+        let blocks = (data.len() * 8).div_ceil(data_len);
+        let mut reader = BitReader::new(data);
+        let mut writer = BitWriter::with_capacity(blocks * len);
+        let mut words = vec![0; data_len.div_ceil(64)];
+        let mut register = vec![0; self.generator.len()];
+        for _ in 0..blocks {
+            reader.read_words(data_len, &mut words);
+            self.remainder(&words, &mut register);
+            writer.write_words(&words, data_len);
+            writer.write_words(&register, self.parity);
+        }
+        writer.finish()
+    }
+
+    fn decode_blocks(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> {
+        let (len, data_len) = (self.block_len(), self.data_len());
+        // a partial block at the end can only be byte padding
+        let blocks = code.len() * 8 / len;
+        let mut reader = BitReader::new(code);
+        let mut writer = BitWriter::with_capacity(blocks * data_len);
+        let mut report = Report::new(code.len() * 8);
+        let mut words = vec![0; data_len.div_ceil(64)];
+        let mut received = vec![0; self.generator.len()];
+        let mut register = vec![0; self.generator.len()];
+        for block in 0..blocks {
+            let start = block * len;
+            reader.read_words(data_len, &mut words);
+            reader.read_words(self.parity, &mut received);
+            self.remainder(&words, &mut register);
+            xor(&mut register, &received);
+            if register.iter().all(|&word| word == 0) {
+                report.block(None);
+            } else {
+                match self.locate(&register) {
+                    Some(positions) => {
+                        for &position in positions.iter().filter(|&&position| position < data_len) {
+                            words[position / 64] ^= 1 << (63 - position % 64);
+                        }
+                        report.block(positions.iter().map(|&position| start + position));
+                    }
+                    // leave the block as it was
+                    None => report.uncorrectable_block(ErrorLocation { block, bit: start }),
+                }
+            }
+            writer.write_words(&words, data_len);
+        }
+
+        codec::decode_result(writer.finish(), report)
+    }
+
+    // Any eight blocks hold a whole number of bytes both before and after encoding.
+    fn chunk_blocks(&self) -> usize {
+        8 * cmp::max(1, codec::CHUNK_BYTES / self.block_len())
+    }
+}
+
+impl Codec for Bch {
+    fn name(&self) -> &'static str { "bch" }
+
+    fn parameters(&self) -> Vec<usize> { vec![self.field.degree(), self.t] }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { self.encode_blocks(data) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> { self.decode_blocks(code) }
+
+    fn chunk_len(&self) -> usize { self.chunk_blocks() * self.data_len() / 8 }
+
+    fn encoded_chunk_len(&self) -> usize { self.chunk_blocks() * self.block_len() / 8 }
+
+    fn rate(&self) -> f64 {
+        self.data_len() as f64 / self.block_len() as f64
+    }
+
+    fn correction_capability(&self) -> Capability {
+        Capability { errors: self.t, block: self.block_len(), unit: Unit::Bit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::Rng;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 256) as u8).collect()
+    }
+
+    fn flip(code: &mut [u8], bit: usize) {
+        code[bit / 8] ^= 0x80 >> (bit % 8);
+    }
+
+    #[test]
+    fn generators() {
+        // the usual tables, with the generator written as a number
+        let number = |m, t| generator(&Field::new(m), t).iter().rev().fold(0u64, |n, &c| n << 1 | c as u64);
+        assert_eq!(number(3, 1), 0b1011);
+        assert_eq!(number(4, 2), 0x1D1);
+        assert_eq!(number(4, 3), 0x537);
+        assert_eq!(Bch::new(8, 4).data_len(), 223);
+        assert_eq!(Bch::new(10, 4).data_len(), 983);
+        assert_eq!(Bch::new(5, 5).data_len(), 11);
+    }
+
+    #[test]
+    fn parameters() {
+        assert!(Bch::build(&[8, 4]).is_ok());
+        assert!(Bch::build(&[8]).is_err());
+        assert!(Bch::build(&[2, 1]).is_err());
+        assert!(Bch::build(&[8, 0]).is_err());
+        // every block would be parity
+        assert!(Bch::build(&[4, 4]).is_ok());
+        assert!(Bch::build(&[4, 8]).is_err());
+    }
+
+    #[test]
+    fn inverse() {
+        for &(m, t) in &[(3, 1), (4, 2), (5, 3), (8, 4), (10, 8)] {
+            let bch = Bch::new(m, t);
+            for &len in &[0, 1, 2, 27, 28, 100, 1000] {
+                let test = sample(len);
+                let code = bch.encode(&test);
+                assert!(code.len() * 8 / bch.block_len() * bch.data_len() >= len * 8);
+                // the data is followed by the zeros that padded the last block
+                let data = bch.decode(&code).unwrap().data;
+                assert_eq!(data[..len], test[..], "m = {}, t = {}, length = {}", m, t, len);
+                assert!(data[len..].iter().all(|&byte| byte == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let bch = Bch::new(8, 4);
+        let test = sample(bch.chunk_len() * 2 + 77);
+        let whole = bch.chunk_len() * 2;
+        let mut code = bch.encode_chunk(&test[..whole]);
+        assert_eq!(code.len(), bch.encoded_chunk_len() * 2);
+        code.extend_from_slice(&bch.encode(&test[whole..]));
+        assert_eq!(code, bch.encode(&test));
+    }
+
+    #[test]
+    fn error_correction() {
+        let mut rng = Rng::new(18);
+        for &(m, t) in &[(4, 2), (6, 3), (8, 4), (8, 8), (10, 5)] {
+            let bch = Bch::new(m, t);
+            let test = sample(500);
+            let code = bch.encode(&test);
+            let len = bch.block_len();
+            let blocks = code.len() * 8 / len;
+            // up to t errors in each block, anywhere
+            for errors in 1..=t {
+                let mut damaged = code.clone();
+                let mut flipped = Vec::new();
+                for block in 0..blocks {
+                    let mut bits: Vec<usize> = Vec::new();
+                    while bits.len() < errors {
+                        let bit = block * len + rng.below(len);
+                        if !bits.contains(&bit) {
+                            bits.push(bit);
+                        }
+                    }
+                    for &bit in &bits {
+                        flip(&mut damaged, bit);
+                    }
+                    bits.sort();
+                    flipped.extend(bits);
+                }
+                let output = bch.decode(&damaged).unwrap();
+                assert_eq!(output.data[..test.len()], test[..], "m = {}, t = {}, errors = {}", m, t, errors);
+                let mut corrected = output.report.corrected_bits.clone();
+                corrected.sort();
+                assert_eq!(corrected, flipped);
+                assert_eq!(output.report.corrected_blocks, blocks);
+            }
+        }
+    }
+
+    #[test]
+    fn error_detection() {
+        // t + 1 errors either can't be corrected or are miscorrected, but
+        // mostly the former
+        let bch = Bch::new(8, 4);
+        let test = sample(2000);
+        let code = bch.encode(&test);
+        let mut rng = Rng::new(5);
+        let mut detected = 0;
+        for _ in 0..100 {
+            let mut damaged = code.clone();
+            let mut bits: Vec<usize> = Vec::new();
+            while bits.len() < 5 {
+                let bit = 255 + rng.below(255);
+                if !bits.contains(&bit) {
+                    bits.push(bit);
+                }
+            }
+            for &bit in &bits {
+                flip(&mut damaged, bit);
+            }
+            match bch.decode(&damaged) {
+                Err(DecodeError::Uncorrectable { locations, .. }) => {
+                    assert_eq!(locations, vec![ErrorLocation { block: 1, bit: 255 }]);
+                    detected += 1;
+                }
+                Ok(output) => assert_ne!(output.data[..test.len()], test[..]),
+            }
+        }
+        assert!(detected > 80, "{}", detected);
+    }
+}
@@ -0,0 +1,118 @@
+use channel::{Channel, Noise};
+use codec::{Codec, DecodeError};
+use rng::Rng;
+
+// Measures how well a code does on a simulated channel, by encoding random
+// frames, damaging them and decoding them again, and counting what is left
+// wrong. Only the payload is simulated; the header of an encoded file is so
+// heavily protected that it is never the weak point.
+
+/// The outcome of sending frames through a channel.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Measurement {
+    pub frames: u64,
+    /// The number of frames that were decoded wrongly.
+    pub frame_errors: u64,
+    /// The number of frames where the codec found errors it couldn't correct.
+    pub detected: u64,
+    /// The number of data bits sent.
+    pub bits: u64,
+    /// The number of data bits that were decoded wrongly.
+    pub bit_errors: u64,
+    /// The number of encoded bits the channel flipped.
+    pub flipped: u64,
+}
+
+impl Measurement {
+    /// The fraction of data bits left wrong after decoding.
+    pub fn bit_error_rate(&self) -> f64 {
+        if self.bits == 0 { 0.0 } else { self.bit_errors as f64 / self.bits as f64 }
+    }
+
+    /// The fraction of frames with any data bit left wrong after decoding.
+    pub fn frame_error_rate(&self) -> f64 {
+        if self.frames == 0 { 0.0 } else { self.frame_errors as f64 / self.frames as f64 }
+    }
+
+    /// The fraction of frames where errors were found that couldn't be corrected.
+    pub fn detected_frame_rate(&self) -> f64 {
+        if self.frames == 0 { 0.0 } else { self.detected as f64 / self.frames as f64 }
+    }
+}
+
+/// Sends `frames` random frames of `frame_len` bytes encoded with `codec`
+/// through `channels`. The same seed always gives the same measurement.
+pub fn measure(codec: &dyn Codec, channels: &[Channel], frame_len: usize, frames: usize, seed: u64)
+    -> Result<Measurement, String>
+{
+    let mut rng = Rng::new(seed);
+    let mut noise = Noise::new(channels, rng.next_u64())?;
+    let mut measurement = Measurement::default();
+    let mut data = vec![0; frame_len];
+    for _ in 0..frames {
+        for byte in &mut data {
+            *byte = rng.next_u64() as u8;
+        }
+        let mut code = codec.encode(&data);
+        let len = noise.damage(&mut code);
+        code.truncate(len);
+        let decoded = match codec.decode(&code) {
+            Ok(output) => output.data,
+            Err(DecodeError::Uncorrectable { output, .. }) => {
+                measurement.detected += 1;
+                output.data
+            }
+        };
+
+        // anything missing from the end counts as wrong
+        let mut wrong: u64 = data.iter().zip(&decoded).map(|(a, b)| (a ^ b).count_ones() as u64).sum();
+        wrong += 8 * data.len().saturating_sub(decoded.len()) as u64;
+        measurement.frames += 1;
+        measurement.bits += 8 * data.len() as u64;
+        measurement.bit_errors += wrong;
+        if wrong > 0 {
+            measurement.frame_errors += 1;
+        }
+    }
+    measurement.flipped = noise.damage_done().flipped;
+    Ok(measurement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec;
+
+    fn build(name: &str, n: usize) -> Box<dyn Codec> {
+        (codec::by_name(name).unwrap().build)(&[n]).unwrap()
+    }
+
+    #[test]
+    fn clean_channel() {
+        let channels = [Channel::BinarySymmetric { p: 0.0 }];
+        let measurement = measure(build("hamming", 3).as_ref(), &channels, 100, 10, 1).unwrap();
+        assert_eq!(measurement, Measurement { frames: 10, bits: 8000, ..Measurement::default() });
+        assert_eq!(measurement.bit_error_rate(), 0.0);
+
+        // nothing sent, so nothing went wrong
+        let measurement = measure(build("hamming", 3).as_ref(), &channels, 100, 0, 1).unwrap();
+        assert_eq!(measurement.frame_error_rate(), 0.0);
+        assert_eq!(measurement.detected_frame_rate(), 0.0);
+    }
+
+    #[test]
+    fn stronger_codes_do_better() {
+        let channels = [Channel::BinarySymmetric { p: 0.01 }];
+        let rates: Vec<f64> = [("multiple", 1), ("multiple", 3), ("multiple", 7)].iter()
+            .map(|&(name, n)| measure(build(name, n).as_ref(), &channels, 256, 50, 9).unwrap().bit_error_rate())
+            .collect();
+        // without any redundancy, the errors are just those of the channel
+        assert!(rates[0] > 0.007 && rates[0] < 0.013, "{:?}", rates);
+        assert!(rates[1] < rates[0] / 10.0, "{:?}", rates);
+        assert!(rates[2] < rates[1], "{:?}", rates);
+
+        let measurement = measure(build("extended-hamming", 3).as_ref(), &channels, 256, 50, 9).unwrap();
+        assert!(measurement.detected > 0);
+        assert_eq!(measurement, measure(build("extended-hamming", 3).as_ref(), &channels, 256, 50, 9).unwrap());
+    }
+}
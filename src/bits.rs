@@ -0,0 +1,114 @@
+use std::cmp;
+use std::convert::TryInto;
+
+// Reading and writing runs of bits, for the codes that work on bits.
+
+/// The most bits `BitReader::read` and `BitWriter::write` handle at once.
+pub const PIECE: usize = 56;
+
+pub fn mask(n: usize) -> u64 {
+    if n >= 64 { !0 } else { (1 << n) - 1 }
+}
+
+/// Reads bits from bytes, most significant first. Past the end it reads zeros.
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+// The eight bytes from `start`, with zeros past the end.
+fn window(bytes: &[u8], start: usize) -> u64 {
+    match bytes.get(start..start + 8) {
+        Some(window) => u64::from_be_bytes(window.try_into().expect("eight bytes")),
+        None => {
+            let mut window = [0; 8];
+            for (i, byte) in bytes.iter().skip(start).enumerate() {
+                window[i] = *byte;
+            }
+            u64::from_be_bytes(window)
+        }
+    }
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, bit: 0 }
+    }
+
+    /// Reads between 1 and `PIECE` bits into the low bits of the result.
+    pub fn read(&mut self, n: usize) -> u64 {
+        let value = window(self.bytes, self.bit / 8) << (self.bit % 8) >> (64 - n);
+        self.bit += n;
+        value
+    }
+
+    /// Reads up to 64 bits.
+    pub fn read_word(&mut self, n: usize) -> u64 {
+        if n <= PIECE {
+            return self.read(n);
+        }
+        let high = self.read(n - 32);
+        high << 32 | self.read(32)
+    }
+
+    /// Reads `n` bits into `words`, from the most significant bit of the first.
+    pub fn read_words(&mut self, n: usize, words: &mut [u64]) {
+        for (i, word) in words.iter_mut().enumerate() {
+            let bits = cmp::min(64, n.saturating_sub(64 * i));
+            *word = if bits == 0 { 0 } else { self.read_word(bits) << (64 - bits) };
+        }
+    }
+}
+
+/// Writes bits to bytes, most significant first.
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits not yet written out, from the most significant.
+    acc: u64,
+    have: usize,
+}
+
+impl BitWriter {
+    pub fn with_capacity(bits: usize) -> BitWriter {
+        BitWriter { bytes: Vec::with_capacity(bits / 8 + 8), acc: 0, have: 0 }
+    }
+
+    /// Writes the low `n` bits of `value`, which must be the only bits set, up
+    /// to `PIECE` of them.
+    pub fn write(&mut self, value: u64, n: usize) {
+        let room = 64 - self.have;
+        if n <= room {
+            self.acc |= value << (room - n);
+            self.have += n;
+        } else {
+            let rest = n - room;
+            self.bytes.extend_from_slice(&(self.acc | value >> rest).to_be_bytes());
+            self.acc = value << (64 - rest);
+            self.have = rest;
+        }
+    }
+
+    /// Writes up to 64 bits.
+    pub fn write_word(&mut self, value: u64, n: usize) {
+        if n <= PIECE {
+            return self.write(value, n);
+        }
+        self.write(value >> 32, n - 32);
+        self.write(value & mask(32), 32);
+    }
+
+    /// Writes the first `n` bits of `words`, from the most significant bit of the first.
+    pub fn write_words(&mut self, words: &[u64], n: usize) {
+        for (i, &word) in words.iter().enumerate().take(n.div_ceil(64)) {
+            let bits = cmp::min(64, n - 64 * i);
+            self.write_word(word >> (64 - bits), bits);
+        }
+    }
+
+    /// Pads the last byte with zeros.
+    pub fn finish(mut self) -> Vec<u8> {
+        let bytes = self.acc.to_be_bytes();
+        self.bytes.extend_from_slice(&bytes[..self.have.div_ceil(8)]);
+        self.bytes
+    }
+}
@@ -117,8 +117,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     /// let mut bv = BitVec::new();
     /// ```
     
@@ -132,8 +131,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let bv = BitVec::from_elem(10, false);
     /// assert_eq!(bv.len(), 10);
@@ -173,8 +171,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let bv = BitVec::from_bytes(&[0b10100000, 0b00010010]);
     /// assert!(bv.eq_vec(&[true, false, true, false,
@@ -216,8 +213,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let bv = BitVec::from_fn(5, |i| { i % 2 == 0 });
     /// assert!(bv.eq_vec(&[true, false, true, false, true]));
@@ -235,8 +231,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let bv = BitVec::from_bytes(&[0b01100000]);
     /// assert_eq!(bv.get(0), Some(false));
@@ -268,8 +263,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(5, false);
     /// bv.set(3, true);
@@ -291,8 +285,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let before = 0b01100000;
     /// let after  = 0b11111111;
@@ -312,8 +305,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let before = 0b01100000;
     /// let after  = 0b10011111;
@@ -341,8 +333,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let a   = 0b01100100;
     /// let b   = 0b01011010;
@@ -372,8 +363,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let a   = 0b01100100;
     /// let b   = 0b01011010;
@@ -403,8 +393,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let a   = 0b01100100;
     /// let b   = 0b01011010;
@@ -433,8 +422,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(5, true);
     /// assert_eq!(bv.all(), true);
@@ -458,8 +446,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let bv = BitVec::from_bytes(&[0b01110100, 0b10010010]);
     /// assert_eq!(bv.iter().filter(|x| *x).count(), 7);
@@ -474,8 +461,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec, append)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut a = BitVec::from_bytes(&[0b10000000]);
     /// let mut b = BitVec::from_bytes(&[0b01100001]);
@@ -502,6 +488,10 @@ impl BitVec {
                 *(self.storage.last_mut().unwrap()) |= block << b;
                 self.storage.push(block >> (32 - b));
             }
+            // Remove additional block if the last shift did not overflow
+            if self.storage.len() > blocks_for_bits(self.nbits) {
+                self.storage.pop();
+            }
         }
     }
 
@@ -515,8 +505,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec, split_off)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     /// let mut a = BitVec::new();
     /// a.push(true);
     /// a.push(false);
@@ -574,8 +563,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(10, false);
     /// assert_eq!(bv.none(), true);
@@ -592,8 +580,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(10, false);
     /// assert_eq!(bv.any(), false);
@@ -614,8 +601,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(3, true);
     /// bv.set(1, false);
@@ -662,8 +648,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let bv = BitVec::from_bytes(&[0b10100000]);
     ///
@@ -683,8 +668,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_bytes(&[0b01001011]);
     /// bv.truncate(2);
@@ -709,8 +693,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(3, false);
     /// bv.reserve(10);
@@ -739,8 +722,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_elem(3, false);
     /// bv.reserve(10);
@@ -761,8 +743,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::new();
     /// bv.reserve(10);
@@ -782,8 +763,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_bytes(&[0b01001011]);
     /// bv.grow(2, true);
@@ -833,8 +813,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::from_bytes(&[0b01001001]);
     /// assert_eq!(bv.pop(), Some(true));
@@ -863,8 +842,7 @@ impl BitVec {
     /// # Examples
     ///
     /// ```
-    /// # #![feature(bitvec)]
-    /// use std::collections::BitVec;
+    /// use eccfile::BitVec;
     ///
     /// let mut bv = BitVec::new();
     /// bv.push(true);
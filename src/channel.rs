@@ -0,0 +1,263 @@
+use std::cmp;
+
+use rng::Rng;
+
+// Simulated damage, for trying codes out without waiting for real storage to
+// go bad. Channels are applied one after another, and each draws from its own
+// generator seeded from the one seed, so the damage depends only on the seed
+// and the data and not on how the data is split up when it is passed through.
+//
+// Rare independent events are found by drawing the gap to the next one from a
+// geometric distribution rather than drawing for every bit, which makes low
+// error rates cheap.
+
+/// A kind of damage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    /// A binary symmetric channel: every bit is flipped with probability `p`.
+    BinarySymmetric { p: f64 },
+    /// A Gilbert-Elliott channel, which switches between a good state and a
+    /// bad state where errors are much more likely, and so makes bursts of
+    /// errors. After each bit it goes bad with probability `enter` or recovers
+    /// with probability `leave`, and bits are flipped with probability `good`
+    /// or `bad` depending on the state. It starts in the good state.
+    GilbertElliott { enter: f64, leave: f64, good: f64, bad: f64 },
+    /// Every byte is erased with probability `p`, as unreadable sectors are,
+    /// and reads as zero.
+    Erasure { p: f64 },
+    /// Everything after the first `length` bytes is lost.
+    Truncate { length: u64 },
+}
+
+impl Channel {
+    fn probabilities(&self) -> Vec<f64> {
+        match *self {
+            Channel::BinarySymmetric { p } | Channel::Erasure { p } => vec![p],
+            Channel::GilbertElliott { enter, leave, good, bad } => vec![enter, leave, good, bad],
+            Channel::Truncate { .. } => vec![],
+        }
+    }
+}
+
+/// What a `Noise` has done so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Damage {
+    /// The number of bits that were flipped.
+    pub flipped: u64,
+    /// The number of bytes that were erased.
+    pub erased: u64,
+    /// The number of bytes that were cut off by truncation.
+    pub truncated: u64,
+}
+
+// A channel and where it is up to.
+struct Stage {
+    channel: Channel,
+    rng: Rng,
+    /// The number of units (bits or bytes) to pass over before the next error.
+    skip: u64,
+    /// Whether a Gilbert-Elliott channel is in the bad state.
+    bad: bool,
+    /// The number of bits left before a Gilbert-Elliott channel changes state.
+    remaining: u64,
+    /// The number of bytes that have reached this stage.
+    seen: u64,
+}
+
+/// Damages data passed through it with a series of channels.
+pub struct Noise {
+    stages: Vec<Stage>,
+    damage: Damage,
+}
+
+impl Noise {
+    /// Every probability must be between 0 and 1. The same seed always causes the same damage.
+    pub fn new(channels: &[Channel], seed: u64) -> Result<Noise, String> {
+        let mut seeds = Rng::new(seed);
+        let mut stages = Vec::with_capacity(channels.len());
+        for &channel in channels {
+            if channel.probabilities().iter().any(|p| !(0.0..=1.0).contains(p)) {
+                return Err("probabilities must be between 0 and 1".to_string());
+            }
+            let mut stage = Stage { channel, rng: Rng::new(seeds.next_u64()), skip: 0, bad: false, remaining: 0, seen: 0 };
+            match channel {
+                Channel::BinarySymmetric { p } | Channel::Erasure { p } => stage.skip = geometric(&mut stage.rng, p),
+                Channel::GilbertElliott { enter, good, .. } => {
+                    stage.remaining = duration(&mut stage.rng, enter);
+                    stage.skip = geometric(&mut stage.rng, good);
+                }
+                Channel::Truncate { .. } => {}
+            }
+            stages.push(stage);
+        }
+        Ok(Noise { stages, damage: Damage::default() })
+    }
+
+    /// Damages `data`, which follows whatever was damaged before, and returns
+    /// how much of it is left. Only truncation makes that less than all of it.
+    pub fn damage(&mut self, data: &mut [u8]) -> usize {
+        let mut len = data.len();
+        for stage in &mut self.stages {
+            let data = &mut data[..len];
+            stage.seen += len as u64;
+            match stage.channel {
+                Channel::BinarySymmetric { p } => {
+                    let flipped = &mut self.damage.flipped;
+                    hits(len as u64 * 8, &mut stage.skip, p, &mut stage.rng, |bit| {
+                        data[bit / 8] ^= 0x80 >> (bit % 8);
+                        *flipped += 1;
+                    });
+                }
+                Channel::GilbertElliott { enter, leave, good, bad } => {
+                    let bits = len as u64 * 8;
+                    let mut start = 0;
+                    while start < bits {
+                        let span = cmp::min(stage.remaining, bits - start);
+                        let p = if stage.bad { bad } else { good };
+                        let flipped = &mut self.damage.flipped;
+                        hits(span, &mut stage.skip, p, &mut stage.rng, |bit| {
+                            let bit = start as usize + bit;
+                            data[bit / 8] ^= 0x80 >> (bit % 8);
+                            *flipped += 1;
+                        });
+                        start += span;
+                        stage.remaining -= span;
+                        if stage.remaining == 0 {
+                            stage.bad = !stage.bad;
+                            let (switch, p) = if stage.bad { (leave, bad) } else { (enter, good) };
+                            stage.remaining = duration(&mut stage.rng, switch);
+                            stage.skip = geometric(&mut stage.rng, p);
+                        }
+                    }
+                }
+                Channel::Erasure { p } => {
+                    let erased = &mut self.damage.erased;
+                    hits(len as u64, &mut stage.skip, p, &mut stage.rng, |byte| {
+                        data[byte] = 0;
+                        *erased += 1;
+                    });
+                }
+                Channel::Truncate { length } => {
+                    let before = stage.seen - len as u64;
+                    let kept = cmp::min(len as u64, length.saturating_sub(before)) as usize;
+                    self.damage.truncated += (len - kept) as u64;
+                    len = kept;
+                }
+            }
+        }
+        len
+    }
+
+    pub fn damage_done(&self) -> &Damage {
+        &self.damage
+    }
+}
+
+// The number of units before the next one with probability `p` of being hit.
+fn geometric(rng: &mut Rng, p: f64) -> u64 {
+    if p <= 0.0 {
+        return u64::MAX;
+    }
+    if p >= 1.0 {
+        return 0;
+    }
+    // the cast saturates, which is as good as never for tiny probabilities
+    ((1.0 - rng.next_f64()).ln() / (1.0 - p).ln()) as u64
+}
+
+// The number of bits a Gilbert-Elliott channel stays in a state that it
+// leaves with probability `switch` after each bit.
+fn duration(rng: &mut Rng, switch: f64) -> u64 {
+    geometric(rng, switch).saturating_add(1)
+}
+
+// Calls `hit` with the offset of each of `units` units that is hit, given that
+// `skip` units are passed over before the next hit.
+fn hits<F: FnMut(usize)>(units: u64, skip: &mut u64, p: f64, rng: &mut Rng, mut hit: F) {
+    let mut i = 0;
+    while *skip < units - i {
+        i += *skip;
+        hit(i as usize);
+        i += 1;
+        *skip = geometric(rng, p);
+    }
+    *skip -= units - i;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn damaged(data: &[u8], channels: &[Channel], seed: u64, piece: usize) -> (Vec<u8>, Damage) {
+        let mut noise = Noise::new(channels, seed).unwrap();
+        let mut output = Vec::new();
+        for piece in data.chunks(piece) {
+            let mut piece = piece.to_vec();
+            let len = noise.damage(&mut piece);
+            output.extend_from_slice(&piece[..len]);
+        }
+        (output, noise.damage_done().clone())
+    }
+
+    fn differing_bits(a: &[u8], b: &[u8]) -> u64 {
+        a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones() as u64).sum()
+    }
+
+    #[test]
+    fn binary_symmetric() {
+        let data = vec![0x5A; 1_000_000];
+        let channels = [Channel::BinarySymmetric { p: 0.001 }];
+        let (output, damage) = damaged(&data, &channels, 1, 1 << 16);
+        assert_eq!(differing_bits(&data, &output), damage.flipped);
+        // 8000 expected, with a standard deviation of about 90
+        assert!(damage.flipped > 7500 && damage.flipped < 8500, "{}", damage.flipped);
+
+        assert_eq!(damaged(&data, &[Channel::BinarySymmetric { p: 0.0 }], 1, 1000).0, data);
+        let all = damaged(&data[..100], &[Channel::BinarySymmetric { p: 1.0 }], 1, 7).0;
+        assert!(all.iter().all(|&b| b == 0xA5));
+    }
+
+    #[test]
+    fn reproducible() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let channels = [
+            Channel::BinarySymmetric { p: 0.0005 },
+            Channel::GilbertElliott { enter: 0.0001, leave: 0.05, good: 0.0, bad: 0.5 },
+            Channel::Erasure { p: 0.001 },
+        ];
+        let whole = damaged(&data, &channels, 42, data.len());
+        // the damage doesn't depend on how the data is split up
+        for &piece in &[1, 1000, 4097] {
+            assert_eq!(damaged(&data, &channels, 42, piece), whole);
+        }
+        assert_ne!(damaged(&data, &channels, 43, data.len()).0, whole.0);
+    }
+
+    #[test]
+    fn bursts() {
+        let data = vec![0; 1_000_000];
+        let channels = [Channel::GilbertElliott { enter: 0.00001, leave: 0.01, good: 0.0, bad: 0.5 }];
+        let (output, damage) = damaged(&data, &channels, 7, 1 << 16);
+        assert_eq!(differing_bits(&data, &output), damage.flipped);
+        // about 80 bursts of 100 bits, with half of their bits flipped
+        assert!(damage.flipped > 1000 && damage.flipped < 10_000, "{}", damage.flipped);
+        let bits: Vec<usize> = (0..output.len() * 8).filter(|&i| output[i / 8] & (0x80 >> (i % 8)) != 0).collect();
+        let close = bits.windows(2).filter(|w| w[1] - w[0] < 20).count();
+        assert!(close * 10 > bits.len() * 8, "errors should mostly be next to other errors");
+    }
+
+    #[test]
+    fn erasure_and_truncation() {
+        let data = vec![0xFF; 100_000];
+        let channels = [Channel::Erasure { p: 0.01 }, Channel::Truncate { length: 54_321 }];
+        let (output, damage) = damaged(&data, &channels, 3, 1000);
+        assert_eq!(output.len(), 54_321);
+        assert_eq!(damage.truncated, 100_000 - 54_321);
+        assert!(output.iter().all(|&b| b == 0 || b == 0xFF));
+        // erasures after the truncation are counted too
+        assert!(damage.erased > 800 && damage.erased < 1200, "{}", damage.erased);
+        assert!(output.iter().filter(|&&b| b == 0).count() as u64 <= damage.erased);
+
+        assert!(Noise::new(&[Channel::Erasure { p: 1.5 }], 0).is_err());
+    }
+}
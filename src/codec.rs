@@ -0,0 +1,463 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use bch::Bch;
+use convolutional::{self, Convolutional};
+use hamming::{ExtendedHamming, Hamming};
+use multiple::Multiple;
+use reed_solomon::ReedSolomon;
+use soft;
+
+/// An error correcting code that can be applied to a whole byte string.
+/// Codecs are shared between threads to encode and decode chunks in parallel.
+pub trait Codec: Send + Sync {
+    /// The name the codec is registered under.
+    fn name(&self) -> &'static str;
+
+    /// The numerical parameters the codec was built with, in the order `build` takes them.
+    fn parameters(&self) -> Vec<usize>;
+
+    fn encode(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decodes `code`. The data can be followed by the zeros that padded the
+    /// last block, since the header is what records the length of the data.
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError>;
+
+    /// The fraction of the encoded output that carries data.
+    fn rate(&self) -> f64;
+
+    /// How many errors the code can correct.
+    fn correction_capability(&self) -> Capability;
+
+    /// The number of bytes of data in a chunk when streaming. Encoding whole
+    /// chunks with `encode_chunk` and then whatever is left with `encode` gives
+    /// the same output as encoding all of the data at once.
+    fn chunk_len(&self) -> usize;
+
+    /// The length of a chunk once it has been encoded.
+    fn encoded_chunk_len(&self) -> usize;
+
+    /// Encodes a whole number of chunks.
+    fn encode_chunk(&self, data: &[u8]) -> Vec<u8> {
+        self.encode(data)
+    }
+
+    /// Decodes a whole number of encoded chunks.
+    fn decode_chunk(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> {
+        self.decode(code)
+    }
+
+    /// Decodes code given as a soft bit for each bit (see `soft`). Codecs that
+    /// can't make use of soft bits decode the hard decisions.
+    fn decode_soft(&self, soft: &[i8]) -> Result<DecodeOutput, DecodeError> {
+        self.decode(&soft::hard_decisions(soft))
+    }
+
+    /// Decodes code some of whose bytes are known to be unreadable, given as
+    /// sorted ranges that don't overlap. Codecs that can't make use of erasures
+    /// otherwise decode them as soft bits that say nothing either way, which
+    /// codecs that can't make use of soft bits either take as zeros.
+    fn decode_with_erasures(&self, code: &[u8], erasures: &[Range<usize>]) -> Result<DecodeOutput, DecodeError> {
+        self.decode_soft(&soft::with_erasures(code, erasures))
+    }
+}
+
+/// Roughly how much encoded data each codec puts in a chunk when streaming.
+pub const CHUNK_BYTES: usize = 1 << 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeOutput {
+    pub data: Vec<u8>,
+    /// What was corrected along the way.
+    pub report: Report,
+}
+
+/// What a decoder found while correcting the data, so that media going bad can
+/// be noticed before errors stop being correctable.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The number of blocks decoded.
+    pub blocks: usize,
+    /// The number of blocks in which errors were corrected.
+    pub corrected_blocks: usize,
+    /// The offset in bits in the encoded data of every bit that was corrected.
+    pub corrected_bits: Vec<usize>,
+    /// The blocks with errors that couldn't be corrected.
+    pub uncorrectable: Vec<ErrorLocation>,
+    /// The length of the encoded data in bits.
+    pub code_bits: usize,
+}
+
+impl Report {
+    pub fn new(code_bits: usize) -> Report {
+        Report { code_bits, ..Report::default() }
+    }
+
+    /// Records a decoded block and the offsets of the bits corrected in it.
+    pub fn block<I: IntoIterator<Item = usize>>(&mut self, corrected: I) {
+        let before = self.corrected_bits.len();
+        self.corrected_bits.extend(corrected);
+        self.blocks += 1;
+        if self.corrected_bits.len() > before {
+            self.corrected_blocks += 1;
+        }
+    }
+
+    /// Records a decoded block that couldn't be corrected.
+    pub fn uncorrectable_block(&mut self, location: ErrorLocation) {
+        self.blocks += 1;
+        self.uncorrectable.push(location);
+    }
+
+    /// The fraction of the encoded bits that were corrected. Nothing is known
+    /// about how many errors there were in uncorrectable blocks, so if there
+    /// are any this underestimates the raw error rate.
+    pub fn bit_error_rate(&self) -> f64 {
+        if self.code_bits == 0 {
+            0.0
+        } else {
+            self.corrected_bits.len() as f64 / self.code_bits as f64
+        }
+    }
+
+    /// Adds the report for the code that followed this report's code.
+    pub fn append(&mut self, mut other: Report) {
+        other.offset(self.code_bits);
+        for location in &mut other.uncorrectable {
+            location.block += self.blocks;
+        }
+        self.blocks += other.blocks;
+        self.corrected_blocks += other.corrected_blocks;
+        self.corrected_bits.extend(other.corrected_bits);
+        self.uncorrectable.extend(other.uncorrectable);
+        self.code_bits += other.code_bits;
+    }
+
+    /// Moves every offset `bits` further into the encoded data, for when the
+    /// code was decoded from part of something larger.
+    pub fn offset(&mut self, bits: usize) {
+        for bit in &mut self.corrected_bits {
+            *bit += bits;
+        }
+        for location in &mut self.uncorrectable {
+            location.bit += bits;
+        }
+    }
+}
+
+/// Where a block with more errors than the code could correct was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// The index of the block.
+    pub block: usize,
+    /// The offset in bits of the start of the block in the encoded data.
+    pub bit: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Errors were detected that could not be corrected. `output` holds the
+    /// data as well as it could be decoded, with those blocks left as they were.
+    Uncorrectable {
+        locations: Vec<ErrorLocation>,
+        output: DecodeOutput,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Uncorrectable { ref locations, .. } => {
+                write!(f, "{} block{} could not be corrected", locations.len(),
+                    if locations.len() == 1 { "" } else { "s" })
+            }
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+/// Turns the output of a decoder into a result, failing if any blocks were uncorrectable.
+pub fn decode_result(data: Vec<u8>, report: Report) -> Result<DecodeOutput, DecodeError> {
+    if report.uncorrectable.is_empty() {
+        Ok(DecodeOutput { data, report })
+    } else {
+        let locations = report.uncorrectable.clone();
+        Err(DecodeError::Uncorrectable { locations, output: DecodeOutput { data, report } })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Bit,
+    Byte,
+}
+
+/// Up to `errors` damaged units can be corrected in each block of `block` units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capability {
+    pub errors: usize,
+    pub block: usize,
+    pub unit: Unit,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit {
+            Unit::Bit => "bit",
+            Unit::Byte => "byte",
+        };
+        write!(f, "{} {}{} in every {}", self.errors, unit, if self.errors == 1 { "" } else { "s" }, self.block)
+    }
+}
+
+/// Builds a codec from its parameters, or explains why they aren't valid.
+pub type Builder = fn(&[usize]) -> Result<Box<dyn Codec>, String>;
+
+pub struct Entry {
+    pub name: &'static str,
+    /// Stored in the header of encoded files. Never reuse an id.
+    pub id: u8,
+    pub description: &'static str,
+    pub build: Builder,
+    /// Packs the parameters into the parameter field of a header.
+    pub pack: fn(&[usize]) -> u32,
+    pub unpack: fn(u32) -> Vec<usize>,
+}
+
+pub static CODECS: &[Entry] = &[
+    Entry {
+        name: "multiple",
+        id: 1,
+        description: "each bit is repeated n times",
+        build: Multiple::build,
+        pack: pack_parameters,
+        unpack: unpack_parameters,
+    },
+    Entry {
+        name: "hamming",
+        id: 2,
+        description: "Hamming codes with n parity bits per block",
+        build: Hamming::build,
+        pack: pack_parameters,
+        unpack: unpack_parameters,
+    },
+    Entry {
+        name: "reed-solomon",
+        id: 3,
+        description: "Reed-Solomon codes with n parity bytes per 255 byte block",
+        build: ReedSolomon::build,
+        pack: pack_parameters,
+        unpack: unpack_parameters,
+    },
+    Entry {
+        name: "extended-hamming",
+        id: 4,
+        description: "Hamming codes with n parity bits and an extra bit per block that detects double errors",
+        build: ExtendedHamming::build,
+        pack: pack_parameters,
+        unpack: unpack_parameters,
+    },
+    Entry {
+        name: "bch",
+        id: 5,
+        description: "binary BCH codes over GF(2^n) correcting t bits in each block of 2^n - 1 bits",
+        build: Bch::build,
+        pack: pack_parameters,
+        unpack: unpack_parameters,
+    },
+    Entry {
+        name: "convolutional",
+        id: 6,
+        description: "rate 1/2 or 1/3 convolutional codes with constraint length n, decoded with the Viterbi algorithm",
+        build: Convolutional::build,
+        pack: convolutional::pack_parameters,
+        unpack: convolutional::unpack_parameters,
+    },
+];
+
+pub fn by_name(name: &str) -> Option<&'static Entry> {
+    CODECS.iter().find(|entry| entry.name == name)
+}
+
+pub fn by_id(id: u8) -> Option<&'static Entry> {
+    CODECS.iter().find(|entry| entry.id == id)
+}
+
+/// Looks up the codec with the given name and builds it.
+pub fn build(name: &str, parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+    match by_name(name) {
+        Some(entry) => (entry.build)(parameters),
+        None => Err(format!("there is no codec called '{}'", name)),
+    }
+}
+
+/// Packs the parameters of most codecs into the parameter field of a header: the
+/// first in the low 16 bits, and the second, if there is one, in the high 16 bits.
+pub fn pack_parameters(parameters: &[usize]) -> u32 {
+    parameters.iter().take(2).enumerate().fold(0, |packed, (i, &p)| packed | (p as u32 & 0xFFFF) << (16 * i))
+}
+
+/// The parameters packed by `pack_parameters`. A second parameter is never 0.
+pub fn unpack_parameters(packed: u32) -> Vec<usize> {
+    let (first, second) = ((packed & 0xFFFF) as usize, (packed >> 16) as usize);
+    if second == 0 { vec![first] } else { vec![first, second] }
+}
+
+/// Sorts ranges of erased bytes and merges the ones that overlap or touch, as
+/// `Codec::decode_with_erasures` expects.
+pub fn sort_erasures(mut erasures: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    erasures.retain(|range| range.start < range.end);
+    erasures.sort_by_key(|range| range.start);
+    let mut sorted: Vec<Range<usize>> = Vec::with_capacity(erasures.len());
+    for range in erasures {
+        match sorted.last_mut() {
+            Some(last) if range.start <= last.end => last.end = cmp::max(last.end, range.end),
+            _ => sorted.push(range),
+        }
+    }
+    sorted
+}
+
+/// The offsets from the start of `within` of the erased bytes inside it.
+pub fn erased_offsets(erasures: &[Range<usize>], within: Range<usize>) -> Vec<usize> {
+    let first = erasures.partition_point(|range| range.end <= within.start);
+    erasures[first..].iter()
+        .take_while(|range| range.start < within.end)
+        .flat_map(|range| cmp::max(range.start, within.start)..cmp::min(range.end, within.end))
+        .map(|offset| offset - within.start)
+        .collect()
+}
+
+/// Reads the only parameter of a codec that takes one, checking that it's within `min..=max`.
+pub fn single_parameter(parameters: &[usize], min: usize, max: usize) -> Result<usize, String> {
+    match *parameters {
+        [n] if n >= min && n <= max => Ok(n),
+        [n] => Err(format!("the parameter must be between {} and {}, not {}", min, max, n)),
+        _ => Err(format!("expected one parameter, not {}", parameters.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        for entry in CODECS {
+            assert_eq!(by_name(entry.name).unwrap().id, entry.id);
+            assert_eq!(by_id(entry.id).unwrap().name, entry.name);
+            assert_eq!(CODECS.iter().filter(|e| e.id == entry.id).count(), 1);
+        }
+        assert!(by_name("nonsense").is_none());
+        assert!(by_id(0).is_none());
+    }
+
+    #[test]
+    fn inverse() {
+        let test: Vec<u8> = vec![1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89];
+        for &(name, n) in &[("multiple", 3), ("hamming", 4), ("reed-solomon", 6), ("extended-hamming", 3)] {
+            let codec = build(name, &[n]).unwrap();
+            assert_eq!(codec.name(), name);
+            assert_eq!(codec.parameters(), vec![n]);
+            assert_eq!(codec.decode(&codec.encode(&test)).unwrap().data, test);
+        }
+    }
+
+    #[test]
+    fn report() {
+        let test: Vec<u8> = (0..100).collect();
+        for &(name, n) in &[("multiple", 3), ("hamming", 4), ("reed-solomon", 6), ("extended-hamming", 3)] {
+            let codec = build(name, &[n]).unwrap();
+            let mut code = codec.encode(&test);
+            let clean = codec.decode(&code).unwrap().report;
+            assert!(clean.blocks > 0);
+            assert_eq!(clean.corrected_blocks, 0);
+            assert_eq!(clean.bit_error_rate(), 0.0);
+            assert_eq!(clean.code_bits, code.len() * 8);
+
+            code[3] ^= 0x10;
+            code[50] ^= 0x01;
+            let report = codec.decode(&code).unwrap().report;
+            assert_eq!(report.blocks, clean.blocks, "{}", name);
+            assert_eq!(report.corrected_bits, vec![3 * 8 + 3, 50 * 8 + 7], "{}", name);
+            assert_eq!(report.corrected_blocks, if name == "reed-solomon" { 1 } else { 2 }, "{}", name);
+            assert_eq!(report.bit_error_rate(), 2.0 / (code.len() * 8) as f64);
+            assert!(report.uncorrectable.is_empty());
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        for &(name, n) in &[("multiple", 3), ("hamming", 3), ("hamming", 5), ("reed-solomon", 6), ("extended-hamming", 4)] {
+            let codec = build(name, &[n]).unwrap();
+            let len = codec.chunk_len();
+            let test: Vec<u8> = (0..len * 2 + 100).map(|i| (i * 13 % 256) as u8).collect();
+            let whole = codec.encode(&test);
+
+            let mut streamed = codec.encode_chunk(&test[..len]);
+            assert_eq!(streamed.len(), codec.encoded_chunk_len(), "{}", name);
+            streamed.extend(codec.encode_chunk(&test[len..len * 2]));
+            streamed.extend(codec.encode(&test[len * 2..]));
+            assert_eq!(streamed, whole, "{}", name);
+
+            let chunk = codec.encoded_chunk_len();
+            let mut decoded = codec.decode_chunk(&whole[..chunk * 2]).unwrap();
+            let rest = codec.decode(&whole[chunk * 2..]).unwrap();
+            decoded.data.extend(rest.data);
+            decoded.report.append(rest.report);
+            decoded.data.truncate(test.len());
+            assert_eq!(decoded.data, test, "{}", name);
+            assert_eq!(decoded.report, codec.decode(&whole).unwrap().report, "{}", name);
+        }
+    }
+
+    #[test]
+    fn erasures() {
+        let erasures = sort_erasures(vec![30..40, 5..10, 0..0, 8..12, 12..15, 50..51]);
+        assert_eq!(erasures, vec![5..15, 30..40, 50..51]);
+        assert_eq!(erased_offsets(&erasures, 10..35), vec![0, 1, 2, 3, 4, 20, 21, 22, 23, 24]);
+        assert_eq!(erased_offsets(&erasures, 40..50), Vec::<usize>::new());
+        assert_eq!(erased_offsets(&erasures, 50..60), vec![0]);
+    }
+
+    #[test]
+    fn packed_parameters() {
+        // a single parameter packs to itself, as it did before there were two
+        assert_eq!(pack_parameters(&[200]), 200);
+        assert_eq!(unpack_parameters(200), vec![200]);
+        assert_eq!(unpack_parameters(pack_parameters(&[8, 4])), vec![8, 4]);
+        let bch = build("bch", &[10, 3]).unwrap();
+        assert_eq!(unpack_parameters(pack_parameters(&bch.parameters())), bch.parameters());
+    }
+
+    #[test]
+    fn bad_parameters() {
+        assert!(build("hamming", &[1]).is_err());
+        assert!(build("multiple", &[]).is_err());
+        assert!(build("reed-solomon", &[255]).is_err());
+        assert!(build("reed-solomon", &[4, 4]).is_err());
+        assert!(build("nonsense", &[3]).is_err());
+    }
+
+    #[test]
+    fn properties() {
+        let hamming = build("hamming", &[3]).unwrap();
+        assert_eq!(hamming.rate(), 4.0 / 7.0);
+        assert_eq!(hamming.correction_capability().to_string(), "1 bit in every 7");
+
+        let multiple = build("multiple", &[5]).unwrap();
+        assert_eq!(multiple.rate(), 0.2);
+        assert_eq!(multiple.correction_capability().to_string(), "2 bits in every 5");
+
+        let extended = build("extended-hamming", &[3]).unwrap();
+        assert_eq!(extended.rate(), 0.5);
+        assert_eq!(extended.correction_capability().to_string(), "1 bit in every 8");
+
+        let reed = build("reed-solomon", &[32]).unwrap();
+        assert_eq!(reed.rate(), 223.0 / 255.0);
+        assert_eq!(reed.correction_capability().to_string(), "16 bytes in every 255");
+    }
+}
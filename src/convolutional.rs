@@ -0,0 +1,464 @@
+use std::cmp;
+use std::mem;
+
+use bits::{BitReader, BitWriter};
+use codec::{self, Capability, Codec, DecodeError, DecodeOutput, Report, Unit};
+
+// Convolutional codes of rate 1/2 or 1/3. The encoder is a shift register
+// holding the last `K` data bits (the constraint length), and for each data bit
+// it writes one bit per generator polynomial: the parity of the register bits
+// the generator taps. The most significant bit of a generator taps the newest
+// data bit, so generators are written the usual way, in octal, like the NASA
+// standard K = 7 code with generators 171 and 133.
+//
+// The data is split into frames of `FRAME_BYTES` bytes, each encoded and
+// decoded on its own so that decoding needs little memory and can be streamed.
+// A frame either starts with the register clear and ends with `K - 1` zero bits
+// to clear it again (zero termination), or starts with the register holding the
+// end of the frame, so that it ends where it started (tail-biting), which costs
+// nothing but makes decoding harder. The last frame is shortened rather than
+// padded; its length can be worked out from the length of the code.
+//
+// Decoding uses the Viterbi algorithm with hard decisions: for every state of
+// the register it keeps the data most likely to have led to it, the path whose
+// output differs from what was received in the fewest bits, and at the end
+// traces back the decisions from the best final state. Tail-biting frames are
+// decoded as if they were circular, wrapping `WRAP` constraint lengths of the
+// frame on either side, so that the decoder settles on the right states by the
+// time it reaches the frame itself.
+//
+// There is no way to tell that errors were too many to correct, so decoding
+// never fails. The report counts the code bits that differ from the
+// re-encoded output.
+
+/// The number of data bytes in a frame.
+pub const FRAME_BYTES: usize = 256;
+pub const MIN_CONSTRAINT: usize = 3;
+pub const MAX_CONSTRAINT: usize = 9;
+
+// The number of constraint lengths wrapped on either side of a tail-biting frame.
+const WRAP: usize = 6;
+
+/// The generators of the usual rate 1/2 code for each constraint length, with
+/// the largest free distance, for when no generators are given.
+pub fn standard_generators(constraint: usize) -> Option<[u32; 2]> {
+    let generators = match constraint {
+        3 => [0o7, 0o5],
+        4 => [0o17, 0o15],
+        5 => [0o23, 0o35],
+        6 => [0o53, 0o75],
+        7 => [0o171, 0o133],
+        8 => [0o247, 0o371],
+        9 => [0o561, 0o753],
+        _ => return None,
+    };
+    Some(generators)
+}
+
+/// Packs the parameters into the parameter field of a header: whether frames
+/// are tail-biting in bit 0, whether there are three generators in bit 1, the
+/// constraint length less 3 in bits 2 to 4, and then the generators, the first
+/// in the lowest bits, each `K` bits long.
+pub fn pack_parameters(parameters: &[usize]) -> u32 {
+    let constraint = parameters[0];
+    let mut packed = (parameters[1] & 1) as u32
+        | ((parameters.len() == 5) as u32) << 1
+        | ((constraint - MIN_CONSTRAINT) as u32) << 2;
+    for (i, &generator) in parameters[2..].iter().enumerate() {
+        packed |= (generator as u32) << (5 + i * constraint);
+    }
+    packed
+}
+
+/// The parameters packed by `pack_parameters`.
+pub fn unpack_parameters(packed: u32) -> Vec<usize> {
+    let constraint = (packed >> 2 & 7) as usize + MIN_CONSTRAINT;
+    let rate = if packed & 2 != 0 { 3 } else { 2 };
+    let mut parameters = vec![constraint, (packed & 1) as usize];
+    for i in 0..rate {
+        parameters.push((packed as u64 >> (5 + i * constraint)) as usize & ((1 << constraint) - 1));
+    }
+    parameters
+}
+
+/// Convolutional code of rate 1/2 or 1/3, with hard-decision Viterbi decoding.
+pub struct Convolutional {
+    constraint: usize,
+    generators: Vec<u32>,
+    tail_biting: bool,
+    /// The output bits for each value of the register, the first generator's the most significant.
+    outputs: Vec<u8>,
+}
+
+impl Convolutional {
+    /// # Panics
+    ///
+    /// Panics if the constraint length is not between 3 and 9, if there aren't
+    /// two or three generators, or if the generators don't fit the constraint
+    /// length or don't tap both ends of the register.
+    pub fn new(constraint: usize, generators: &[u32], tail_biting: bool) -> Convolutional {
+        Convolutional::try_new(constraint, generators, tail_biting).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Takes the constraint length, then optionally 1 for tail-biting or 0 for
+    /// zero termination (the default), then optionally the generators. Without
+    /// them, the standard rate 1/2 code is used.
+    pub fn build(parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+        let (constraint, tail_biting, generators) = match *parameters {
+            [constraint] => (constraint, 0, &[][..]),
+            [constraint, tail_biting] => (constraint, tail_biting, &[][..]),
+            [constraint, tail_biting, ref generators @ ..] if generators.len() >= 2 => (constraint, tail_biting, generators),
+            _ => return Err("expected the constraint length, the termination and two or three generators".to_string()),
+        };
+        if tail_biting > 1 {
+            return Err(format!("the termination must be 0 for zero termination or 1 for tail-biting, not {}", tail_biting));
+        }
+        let generators: Vec<u32> = if generators.is_empty() {
+            match standard_generators(constraint) {
+                Some(generators) => generators.to_vec(),
+                None => return Err(constraint_error(constraint)),
+            }
+        } else {
+            generators.iter().map(|&g| cmp::min(g, u32::MAX as usize) as u32).collect()
+        };
+        Ok(Box::new(Convolutional::try_new(constraint, &generators, tail_biting == 1)?))
+    }
+
+    fn try_new(constraint: usize, generators: &[u32], tail_biting: bool) -> Result<Convolutional, String> {
+        if !(MIN_CONSTRAINT..=MAX_CONSTRAINT).contains(&constraint) {
+            return Err(constraint_error(constraint));
+        }
+        if generators.len() != 2 && generators.len() != 3 {
+            return Err(format!("expected two or three generators, not {}", generators.len()));
+        }
+        if let Some(&g) = generators.iter().find(|&&g| g == 0 || g >> constraint != 0) {
+            return Err(format!("the generator {:o} doesn't fit a constraint length of {}", g, constraint));
+        }
+        let taps = generators.iter().fold(0, |taps, &g| taps | g);
+        if taps & 1 == 0 || taps >> (constraint - 1) == 0 {
+            return Err(format!("the generators must tap both ends of a register of {} bits", constraint));
+        }
+        let outputs = (0..1u32 << constraint).map(|register| {
+            generators.iter().fold(0, |output, &g| output << 1 | (register & g).count_ones() as u8 & 1)
+        }).collect();
+        Ok(Convolutional { constraint, generators: generators.to_vec(), tail_biting, outputs })
+    }
+
+    fn rate_inverse(&self) -> usize {
+        self.generators.len()
+    }
+
+    fn states(&self) -> usize {
+        1 << (self.constraint - 1)
+    }
+
+    // The number of encoded bits for a frame of `bytes` bytes.
+    fn frame_code_bits(&self, bytes: usize) -> usize {
+        let steps = if bytes == 0 { 0 } else if self.tail_biting { bytes * 8 } else { bytes * 8 + self.constraint - 1 };
+        steps * self.rate_inverse()
+    }
+
+    // The number of data bytes in a last frame whose code is `bits` bits long,
+    // including up to 7 bits of padding.
+    fn frame_bytes(&self, bits: usize) -> usize {
+        let steps = bits / self.rate_inverse();
+        let tail = if self.tail_biting { 0 } else { self.constraint - 1 };
+        cmp::min(FRAME_BYTES, steps.saturating_sub(tail) / 8)
+    }
+
+    // The register state holding the last bits of `bits`, newest in the top bit.
+    fn tail_state(&self, bits: &[u8]) -> usize {
+        let memory = self.constraint - 1;
+        bits[bits.len() - memory..].iter().fold(0, |state, &bit| state >> 1 | (bit as usize) << (memory - 1))
+    }
+
+    // Encodes the data bits of a frame, returning the output of each step.
+    fn encode_frame(&self, bits: &[u8]) -> Vec<u8> {
+        let mut state = if self.tail_biting { self.tail_state(bits) } else { 0 };
+        let tail = if self.tail_biting { 0 } else { self.constraint - 1 };
+        bits.iter().cloned().chain((0..tail).map(|_| 0)).map(|bit| {
+            let register = (bit as usize) << (self.constraint - 1) | state;
+            state = register >> 1;
+            self.outputs[register]
+        }).collect()
+    }
+
+    // The most likely inputs for the received outputs of each step. Without a
+    // start state every state is equally likely to begin with, and without an
+    // end state the trace starts from the best one.
+    fn viterbi(&self, symbols: &[u8], start: Option<usize>, end: Option<usize>) -> Vec<u8> {
+        let states = self.states();
+        let half = states / 2;
+        // the distance of each possible output from each received symbol
+        let outputs = 1 << self.rate_inverse();
+        let distances: Vec<Vec<u32>> = (0..outputs).map(|symbol| {
+            self.outputs.iter().map(|&output| (output ^ symbol as u8).count_ones()).collect()
+        }).collect();
+
+        let unreachable = u32::MAX / 2;
+        let mut metrics = vec![if start.is_some() { unreachable } else { 0 }; states];
+        if let Some(start) = start {
+            metrics[start] = 0;
+        }
+        let mut next = vec![0; states];
+        // whether each state was reached from the predecessor with its oldest bit set
+        let mut decisions = vec![false; symbols.len() * states];
+        for (&symbol, decisions) in symbols.iter().zip(decisions.chunks_exact_mut(states)) {
+            // a pair of states 2j and 2j + 1 leads to j with a zero and to j + half with a one
+            let (zeros, ones) = distances[symbol as usize].split_at(states);
+            let (low, high) = next.split_at_mut(half);
+            let (low_decisions, high_decisions) = decisions.split_at_mut(half);
+            let butterflies = metrics.chunks_exact(2).zip(zeros.chunks_exact(2)).zip(ones.chunks_exact(2));
+            for ((((pair, zero), one), (low, high)), (low_decision, high_decision)) in butterflies
+                .zip(low.iter_mut().zip(high.iter_mut()))
+                .zip(low_decisions.iter_mut().zip(high_decisions.iter_mut()))
+            {
+                let (a, b) = (pair[0] + zero[0], pair[1] + zero[1]);
+                *low_decision = b < a;
+                *low = if b < a { b } else { a };
+                let (a, b) = (pair[0] + one[0], pair[1] + one[1]);
+                *high_decision = b < a;
+                *high = if b < a { b } else { a };
+            }
+            mem::swap(&mut metrics, &mut next);
+        }
+
+        let mut state = end.unwrap_or_else(|| {
+            (0..states).min_by_key(|&state| metrics[state]).expect("there is always a state")
+        });
+        let mut bits = vec![0; symbols.len()];
+        for (bit, decisions) in bits.iter_mut().zip(decisions.chunks_exact(states)).rev() {
+            *bit = (state >= half) as u8;
+            state = (state << 1) & (states - 1) | decisions[state] as usize;
+        }
+        bits
+    }
+
+    // Decodes the received outputs of a frame, returning the data bits.
+    fn decode_frame(&self, symbols: &[u8], bytes: usize) -> Vec<u8> {
+        let len = bytes * 8;
+        if !self.tail_biting {
+            let mut bits = self.viterbi(symbols, Some(0), Some(0));
+            bits.truncate(len);
+            return bits;
+        }
+        let wrap = WRAP * self.constraint;
+        let circular: Vec<u8> = (0..len + 2 * wrap).map(|i| symbols[(i + len * wrap - wrap) % len]).collect();
+        self.viterbi(&circular, None, None)[wrap..wrap + len].to_vec()
+    }
+
+    fn encode_frames(&self, data: &[u8]) -> Vec<u8> {
+        let n = self.rate_inverse();
+        let bits: usize = data.chunks(FRAME_BYTES).map(|frame| self.frame_code_bits(frame.len())).sum();
+        let mut writer = BitWriter::with_capacity(bits);
+        for frame in data.chunks(FRAME_BYTES) {
+            let bits = unpack_bits(frame);
+            for output in self.encode_frame(&bits) {
+                writer.write(u64::from(output), n);
+            }
+        }
+        writer.finish()
+    }
+
+    fn decode_frames(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> {
+        let n = self.rate_inverse();
+        let mut reader = BitReader::new(code);
+        let mut report = Report::new(code.len() * 8);
+        let mut data = Vec::with_capacity(code.len() / n);
+        let (mut start, total) = (0, code.len() * 8);
+        let whole = self.frame_code_bits(FRAME_BYTES);
+        while start < total {
+            let bytes = if total - start >= whole { FRAME_BYTES } else { self.frame_bytes(total - start) };
+            if bytes == 0 {
+                // just padding
+                break;
+            }
+            let steps = self.frame_code_bits(bytes) / n;
+            let symbols: Vec<u8> = (0..steps).map(|_| reader.read(n) as u8).collect();
+            let bits = self.decode_frame(&symbols, bytes);
+            let expected = self.encode_frame(&bits);
+            report.block(symbols.iter().zip(&expected).enumerate().flat_map(|(step, (&got, &wanted))| {
+                let diff = got ^ wanted;
+                (0..n).filter(move |&j| diff >> (n - 1 - j) & 1 == 1).map(move |j| start + step * n + j)
+            }));
+            data.extend(bits.chunks(8).map(|byte| byte.iter().fold(0, |b, &bit| b << 1 | bit)));
+            start += steps * n;
+        }
+        codec::decode_result(data, report)
+    }
+
+    // The smallest number of output bits by which two sequences of data can
+    // differ, found by searching the paths that leave the zero state and
+    // return to it.
+    fn free_distance(&self) -> usize {
+        let states = self.states();
+        let mut distance = vec![usize::MAX; states];
+        let mut done = vec![false; states];
+        // leaving the zero state with a one
+        let first = 1 << (self.constraint - 1);
+        distance[first >> 1] = self.outputs[first].count_ones() as usize;
+        let mut best = usize::MAX;
+        while let Some(state) = (1..states).filter(|&s| !done[s] && distance[s] < usize::MAX).min_by_key(|&s| distance[s]) {
+            done[state] = true;
+            for bit in 0..2 {
+                let register = bit << (self.constraint - 1) | state;
+                let weight = distance[state] + self.outputs[register].count_ones() as usize;
+                let next = register >> 1;
+                if next == 0 {
+                    best = cmp::min(best, weight);
+                } else if weight < distance[next] {
+                    distance[next] = weight;
+                }
+            }
+        }
+        best
+    }
+
+    // The number of frames in a chunk: any eight frames encode to a whole number of bytes.
+    fn chunk_frames(&self) -> usize {
+        8 * cmp::max(1, codec::CHUNK_BYTES * 8 / self.frame_code_bits(FRAME_BYTES) / 8)
+    }
+}
+
+fn constraint_error(constraint: usize) -> String {
+    format!("the constraint length must be between {} and {}, not {}", MIN_CONSTRAINT, MAX_CONSTRAINT, constraint)
+}
+
+// The bits of some bytes, one per byte, most significant first.
+fn unpack_bits(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|&byte| (0..8).rev().map(move |i| byte >> i & 1)).collect()
+}
+
+impl Codec for Convolutional {
+    fn name(&self) -> &'static str { "convolutional" }
+
+    fn parameters(&self) -> Vec<usize> {
+        let mut parameters = vec![self.constraint, self.tail_biting as usize];
+        parameters.extend(self.generators.iter().map(|&g| g as usize));
+        parameters
+    }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { self.encode_frames(data) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> { self.decode_frames(code) }
+
+    fn chunk_len(&self) -> usize { self.chunk_frames() * FRAME_BYTES }
+
+    fn encoded_chunk_len(&self) -> usize { self.chunk_frames() * self.frame_code_bits(FRAME_BYTES) / 8 }
+
+    fn rate(&self) -> f64 {
+        (FRAME_BYTES * 8) as f64 / self.frame_code_bits(FRAME_BYTES) as f64
+    }
+
+    /// Errors are corrected if there are no more than this many within about
+    /// five constraint lengths of each other.
+    fn correction_capability(&self) -> Capability {
+        let block = 5 * self.constraint * self.rate_inverse();
+        Capability { errors: (self.free_distance() - 1) / 2, block, unit: Unit::Bit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::Rng;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 256) as u8).collect()
+    }
+
+    fn nasa(tail_biting: bool) -> Convolutional {
+        Convolutional::new(7, &[0o171, 0o133], tail_biting)
+    }
+
+    #[test]
+    fn free_distances() {
+        assert_eq!(nasa(false).free_distance(), 10);
+        assert_eq!(Convolutional::new(3, &[0o7, 0o5], false).free_distance(), 5);
+        assert_eq!(Convolutional::new(3, &[0o7, 0o7, 0o5], false).free_distance(), 8);
+        assert_eq!(nasa(false).correction_capability().to_string(), "4 bits in every 70");
+    }
+
+    #[test]
+    fn encoding() {
+        // a single one shows the generators, zero terminated
+        let code = Convolutional::new(3, &[0o7, 0o5], false).encode(&[0x80]);
+        // outputs 11 10 11 then 00 for the other seven bits and the tail
+        assert_eq!(code, vec![0b1110_1100, 0, 0]);
+        // four frames, each with a tail of six bits
+        assert_eq!(nasa(false).encode(&sample(1000)).len(), 2 * (1000 * 8 + 4 * 6) / 8);
+        assert_eq!(nasa(true).encode(&sample(1000)).len(), 2000);
+    }
+
+    #[test]
+    fn parameters() {
+        for codec in &[nasa(false), nasa(true), Convolutional::new(9, &[0o557, 0o663, 0o711], true)] {
+            let parameters = codec.parameters();
+            assert_eq!(unpack_parameters(pack_parameters(&parameters)), parameters);
+        }
+        assert_eq!(Convolutional::build(&[7]).unwrap().parameters(), vec![7, 0, 0o171, 0o133]);
+        assert!(Convolutional::build(&[10]).is_err());
+        assert!(Convolutional::build(&[7, 2]).is_err());
+        assert!(Convolutional::build(&[7, 0, 0o171]).is_err());
+        assert!(Convolutional::build(&[3, 0, 0o17, 0o5]).is_err());
+        // neither taps the oldest bit
+        assert!(Convolutional::build(&[3, 0, 0o6, 0o4]).is_err());
+    }
+
+    #[test]
+    fn inverse() {
+        let codecs = [nasa(false), nasa(true), Convolutional::new(3, &[0o7, 0o5], true),
+                      Convolutional::new(5, &[0o25, 0o33, 0o37], false)];
+        for codec in &codecs {
+            for &len in &[0, 1, 2, 100, FRAME_BYTES, FRAME_BYTES + 1, 1000] {
+                let test = sample(len);
+                let output = codec.decode(&codec.encode(&test)).unwrap();
+                assert_eq!(output.data, test, "{:?} with {} bytes", codec.parameters(), len);
+                assert!(output.report.corrected_bits.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let codec = nasa(false);
+        let test = sample(codec.chunk_len() + 300);
+        let mut code = codec.encode_chunk(&test[..codec.chunk_len()]);
+        assert_eq!(code.len(), codec.encoded_chunk_len());
+        code.extend(codec.encode(&test[codec.chunk_len()..]));
+        assert_eq!(code, codec.encode(&test));
+    }
+
+    #[test]
+    fn error_correction() {
+        let mut rng = Rng::new(19);
+        for &tail_biting in &[false, true] {
+            let codec = nasa(tail_biting);
+            let test = sample(2000);
+            let code = codec.encode(&test);
+            // errors spread out well enough, including at the ends of frames
+            let mut damaged = code.clone();
+            let mut flipped = Vec::new();
+            let mut bit = rng.below(20);
+            while bit < code.len() * 8 - 8 {
+                damaged[bit / 8] ^= 0x80 >> (bit % 8);
+                flipped.push(bit);
+                bit += 30 + rng.below(40);
+            }
+            let output = codec.decode(&damaged).unwrap();
+            assert_eq!(output.data, test, "tail-biting: {}", tail_biting);
+            assert_eq!(output.report.corrected_bits, flipped);
+        }
+    }
+
+    #[test]
+    fn bursts_are_too_much() {
+        let codec = nasa(false);
+        let test = sample(100);
+        let mut code = codec.encode(&test);
+        for byte in &mut code[20..24] {
+            *byte ^= 0xFF;
+        }
+        assert_ne!(codec.decode(&code).unwrap().data, test);
+    }
+}
@@ -0,0 +1,54 @@
+// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320), as used by zip and png.
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u32; 256] = make_table();
+
+/// Feeds `bytes` into a running checksum. Start with `0` and pass the previous
+/// result back in to checksum data that arrives in pieces.
+pub fn update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc = TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Computes the checksum of `bytes` in one go.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    update(0, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn incremental() {
+        let whole = crc32(b"hello, world");
+        let pieces = update(update(0, b"hello"), b", world");
+        assert_eq!(whole, pieces);
+    }
+}
@@ -0,0 +1,183 @@
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use codec;
+
+// GNU ddrescue records what it managed to read of a device in a mapfile. After
+// comment lines starting with '#', the first line gives the position and state
+// of the rescue, and every line after it is a block of the image: its position,
+// its size and a status character, with the numbers written like C integers
+// (hexadecimal after "0x", octal after a leading 0). Only finished blocks, with
+// status '+', were read; everything else is zeros in the image, and so is
+// decoded as erasures rather than as data.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// '?': not read yet.
+    NonTried,
+    /// '*': failed while reading large blocks, and not trimmed yet.
+    NonTrimmed,
+    /// '/': trimmed, but not scraped sector by sector yet.
+    NonScraped,
+    /// '-': a sector that couldn't be read.
+    BadSector,
+    /// '+': read.
+    Finished,
+}
+
+impl Status {
+    pub fn from_char(c: char) -> Option<Status> {
+        match c {
+            '?' => Some(Status::NonTried),
+            '*' => Some(Status::NonTrimmed),
+            '/' => Some(Status::NonScraped),
+            '-' => Some(Status::BadSector),
+            '+' => Some(Status::Finished),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub pos: u64,
+    pub size: u64,
+    pub status: Status,
+}
+
+/// A parsed mapfile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Map {
+    /// Where the rescue had got to.
+    pub current_pos: u64,
+    pub blocks: Vec<Block>,
+}
+
+impl Map {
+    /// The byte ranges of the image that weren't read, sorted and merged, for
+    /// `recover_with_erasures`.
+    pub fn erasures(&self) -> Vec<Range<usize>> {
+        codec::sort_erasures(self.blocks.iter()
+            .filter(|block| block.status != Status::Finished)
+            .map(|block| block.pos as usize..(block.pos + block.size) as usize)
+            .collect())
+    }
+}
+
+/// A line of a mapfile that doesn't make sense.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapError {
+    /// Counting from 1.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} of the mapfile: {}", self.line, self.reason)
+    }
+}
+
+impl error::Error for MapError {}
+
+// Parses an integer written like a C literal.
+fn number(text: &str) -> Option<u64> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if text.len() > 1 && text.starts_with('0') {
+        u64::from_str_radix(&text[1..], 8).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn status(text: &str) -> Option<Status> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Status::from_char(c),
+        _ => None,
+    }
+}
+
+pub fn parse(text: &str) -> Result<Map, MapError> {
+    let mut current_pos = None;
+    let mut blocks: Vec<Block> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |reason: &str| MapError { line: i + 1, reason: reason.to_string() };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if current_pos.is_none() {
+            // the current position, the current status and, since version 1.20, the pass
+            if fields.len() < 2 || fields.len() > 3 {
+                return Err(error("expected the current position and status"));
+            }
+            current_pos = Some(number(fields[0]).ok_or_else(|| error("the current position isn't a number"))?);
+            continue;
+        }
+        if fields.len() != 3 {
+            return Err(error("expected the position, size and status of a block"));
+        }
+        let pos = number(fields[0]).ok_or_else(|| error("the position isn't a number"))?;
+        let size = number(fields[1]).ok_or_else(|| error("the size isn't a number"))?;
+        let status = status(fields[2]).ok_or_else(|| error("the status isn't one of '?*/-+'"))?;
+        if pos.checked_add(size).is_none() {
+            return Err(error("the block ends past the largest possible position"));
+        }
+        if let Some(last) = blocks.last() {
+            if pos < last.pos + last.size {
+                return Err(error("the block overlaps the one before it"));
+            }
+        }
+        blocks.push(Block { pos, size, status });
+    }
+    match current_pos {
+        Some(current_pos) => Ok(Map { current_pos, blocks }),
+        None => Err(MapError { line: text.lines().count(), reason: "the mapfile is empty".to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+# Mapfile. Created by GNU ddrescue version 1.27
+# Command line: ddrescue /dev/sdb image.ecc rescue.map
+# Start time:   2024-01-01 12:00:00
+# current_pos  current_status  current_pass
+0x00120000     ?               1
+#      pos        size  status
+0x00000000  0x00117000  +
+0x00117000  0x00000200  -
+0x00117200  0x00001000  /
+0x00118200  0x00007E00  +
+0x00120000  0x00010000  ?
+";
+
+    #[test]
+    fn parsing() {
+        let map = parse(MAP).unwrap();
+        assert_eq!(map.current_pos, 0x120000);
+        assert_eq!(map.blocks.len(), 5);
+        assert_eq!(map.blocks[1], Block { pos: 0x117000, size: 0x200, status: Status::BadSector });
+        assert_eq!(map.erasures(), vec![0x117000..0x118200, 0x120000..0x130000]);
+
+        // older versions don't write the pass, and numbers can be decimal or octal
+        let map = parse("0 +\n0 10 +\n10 010 *\n").unwrap();
+        assert_eq!(map.blocks[1], Block { pos: 10, size: 8, status: Status::NonTrimmed });
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("# nothing\n").unwrap_err().reason, "the mapfile is empty");
+        assert_eq!(parse("0 +\n0 0x10 x\n").unwrap_err(),
+            MapError { line: 2, reason: "the status isn't one of '?*/-+'".to_string() });
+        assert_eq!(parse("0 +\n0 10 +\nfoo 10 -\n").unwrap_err().line, 3);
+        assert_eq!(parse("0 +\n0 10 +\n5 10 -\n").unwrap_err().reason, "the block overlaps the one before it");
+        assert_eq!(parse("0 +\n0 10\n").unwrap_err().reason, "expected the position, size and status of a block");
+    }
+}
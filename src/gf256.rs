@@ -0,0 +1,159 @@
+// Arithmetic in GF(2^8), using the field polynomial x^8 + x^4 + x^3 + x^2 + 1
+// (0x11D) and generator 2. Addition is xor; multiplication goes through log
+// and antilog tables.
+//
+// Polynomials are stored as byte slices with the highest degree coefficient first.
+
+const PRIMITIVE: usize = 0x11D;
+
+const fn make_exp() -> [u8; 512] {
+    let mut exp = [0u8; 512];
+    let mut x: usize = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    // doubled so that adding two logs never has to be reduced
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    exp
+}
+
+const fn make_log() -> [u8; 256] {
+    let exp = make_exp();
+    let mut log = [0u8; 256];
+    let mut i = 0;
+    while i < 255 {
+        log[exp[i] as usize] = i as u8;
+        i += 1;
+    }
+    log
+}
+
+static EXP: [u8; 512] = make_exp();
+static LOG: [u8; 256] = make_log();
+
+pub fn mul(x: u8, y: u8) -> u8 {
+    if x == 0 || y == 0 {
+        return 0;
+    }
+    EXP[LOG[x as usize] as usize + LOG[y as usize] as usize]
+}
+
+/// # Panics
+///
+/// Panics if `y` is zero.
+pub fn div(x: u8, y: u8) -> u8 {
+    assert!(y != 0, "division by zero");
+    if x == 0 {
+        return 0;
+    }
+    EXP[(LOG[x as usize] as usize + 255 - LOG[y as usize] as usize) % 255]
+}
+
+pub fn inverse(x: u8) -> u8 {
+    div(1, x)
+}
+
+/// The generator raised to `power`.
+pub fn exp(power: usize) -> u8 {
+    EXP[power % 255]
+}
+
+pub fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, x)).collect()
+}
+
+pub fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut result = vec![0; len];
+    for (i, &c) in p.iter().enumerate() {
+        result[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        result[i + len - q.len()] ^= c;
+    }
+    result
+}
+
+pub fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; p.len() + q.len() - 1];
+    for (j, &b) in q.iter().enumerate() {
+        for (i, &a) in p.iter().enumerate() {
+            result[i + j] ^= mul(a, b);
+        }
+    }
+    result
+}
+
+/// Evaluates the polynomial at `x` with Horner's method.
+pub fn poly_eval(p: &[u8], x: u8) -> u8 {
+    let mut y = p[0];
+    for &c in &p[1..] {
+        y = mul(y, x) ^ c;
+    }
+    y
+}
+
+/// Divides by a monic polynomial with synthetic division, returning the
+/// quotient and the remainder.
+pub fn poly_div(dividend: &[u8], divisor: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut out = dividend.to_vec();
+    let separator = dividend.len() - (divisor.len() - 1);
+    for i in 0..separator {
+        let coef = out[i];
+        if coef != 0 {
+            for j in 1..divisor.len() {
+                out[i + j] ^= mul(divisor[j], coef);
+            }
+        }
+    }
+    let remainder = out.split_off(separator);
+    (out, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables() {
+        assert_eq!(exp(0), 1);
+        assert_eq!(exp(8), 0x1D);
+        // every non-zero element appears exactly once
+        let mut seen = [false; 256];
+        for i in 0..255 {
+            assert!(!seen[exp(i) as usize]);
+            seen[exp(i) as usize] = true;
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        for x in 1..=255u8 {
+            assert_eq!(mul(x, inverse(x)), 1);
+            assert_eq!(div(mul(x, 0x53), 0x53), x);
+        }
+        assert_eq!(mul(0x53, 0xCA), mul(0xCA, 0x53));
+        assert_eq!(mul(0, 7), 0);
+    }
+
+    #[test]
+    fn polynomials() {
+        // (x + 1)(x + 2) = x^2 + 3x + 2
+        let product = poly_mul(&[1, 1], &[1, 2]);
+        assert_eq!(product, vec![1, 3, 2]);
+        assert_eq!(poly_eval(&product, 1), 0);
+        assert_eq!(poly_eval(&product, 2), 0);
+        assert_eq!(poly_div(&product, &[1, 2]), (vec![1, 1], vec![0]));
+        assert_eq!(poly_add(&[1, 0, 0], &[5, 7]), vec![1, 5, 7]);
+        assert_eq!(poly_scale(&[1, 2], 2), vec![2, 4]);
+    }
+}
@@ -0,0 +1,119 @@
+// Arithmetic in GF(2^m) for `m` from 3 to 16, for codes whose block length
+// depends on the size of the field. Unlike `gf256` the tables are built when a
+// field is needed. Elements are numbers below `2^m`; addition is xor, and
+// multiplication goes through log and antilog tables.
+
+/// The smallest supported `m`.
+pub const MIN_DEGREE: usize = 3;
+/// The largest supported `m`.
+pub const MAX_DEGREE: usize = 16;
+
+// A primitive polynomial of each degree from 3 to 16, so that `x` generates
+// the whole multiplicative group. These are part of the file format.
+const PRIMITIVE: [u32; 14] = [
+    0xB, 0x13, 0x25, 0x43, 0x83, 0x11D, 0x211, 0x409, 0x805, 0x1053, 0x201B, 0x402B, 0x8003, 0x1002D,
+];
+
+pub struct Field {
+    m: usize,
+    /// The powers of the generator, doubled so that adding two logs never has to be reduced.
+    exp: Vec<u32>,
+    log: Vec<u32>,
+}
+
+impl Field {
+    /// # Panics
+    ///
+    /// Panics if `m` is not between `MIN_DEGREE` and `MAX_DEGREE`.
+    pub fn new(m: usize) -> Field {
+        assert!((MIN_DEGREE..=MAX_DEGREE).contains(&m), "the field degree must be between 3 and 16");
+        let order = (1 << m) - 1;
+        let primitive = PRIMITIVE[m - MIN_DEGREE];
+        let mut exp = vec![0; 2 * order];
+        let mut log = vec![0; order + 1];
+        let mut x = 1;
+        for i in 0..order {
+            exp[i] = x;
+            exp[i + order] = x;
+            log[x as usize] = i as u32;
+            x <<= 1;
+            if x >> m != 0 {
+                x ^= primitive;
+            }
+        }
+        Field { m, exp, log }
+    }
+
+    pub fn degree(&self) -> usize {
+        self.m
+    }
+
+    /// The number of non-zero elements, which is the order of the generator.
+    pub fn order(&self) -> usize {
+        (1 << self.m) - 1
+    }
+
+    /// The generator raised to the power `i`.
+    pub fn power(&self, i: usize) -> u32 {
+        self.exp[i % self.order()]
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `x` is zero.
+    pub fn log(&self, x: u32) -> usize {
+        assert!(x != 0, "zero has no logarithm");
+        self.log[x as usize] as usize
+    }
+
+    pub fn mul(&self, x: u32, y: u32) -> u32 {
+        if x == 0 || y == 0 {
+            return 0;
+        }
+        self.exp[self.log[x as usize] as usize + self.log[y as usize] as usize]
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `y` is zero.
+    pub fn div(&self, x: u32, y: u32) -> u32 {
+        assert!(y != 0, "division by zero");
+        if x == 0 {
+            return 0;
+        }
+        self.exp[self.log[x as usize] as usize + self.order() - self.log[y as usize] as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generator_is_primitive() {
+        for m in MIN_DEGREE..=MAX_DEGREE {
+            let field = Field::new(m);
+            // every non-zero element is a distinct power of the generator
+            let mut seen = vec![false; field.order() + 1];
+            for i in 0..field.order() {
+                let x = field.power(i) as usize;
+                assert!(x != 0 && !seen[x], "m = {}", m);
+                seen[x] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let field = Field::new(5);
+        for x in 1..32 {
+            assert_eq!(field.mul(x, field.div(1, x)), 1);
+            for y in 1..32 {
+                assert_eq!(field.div(field.mul(x, y), y), x);
+                assert_eq!(field.mul(x, y), field.mul(y, x));
+            }
+        }
+        assert_eq!(field.mul(0, 7), 0);
+        assert_eq!(field.power(field.order()), 1);
+    }
+}
@@ -1,149 +1,744 @@
-use bitvec::BitVec;
+use std::cmp;
 
-fn is_power_of_two(n: usize) -> bool {
-    (n & (n - 1)) == 0
+use bits::{self, BitReader, BitWriter, PIECE};
+use codec::{self, Capability, Codec, DecodeError, DecodeOutput, ErrorLocation, Report, Unit};
+use soft;
+
+// The bits of a block of `2^p - 1` bits are numbered from 1. The bits at powers
+// of two are parity bits and the rest hold data, in order. Parity bit `2^n`
+// covers every position with bit `n` set, so xoring together the positions of
+// every set bit gives all of the parity checks at once: this syndrome is zero
+// for a valid block, and otherwise it's the position of the bit that needs to
+// be flipped.
+//
+// Extended Hamming codes add a bit to the end of each block holding the parity
+// of the whole block. A single error always changes the overall parity and a
+// double error never does, so a non-zero syndrome with matching overall parity
+// means there are (at least) two errors.
+//
+// Blocks that fit in a word are encoded and decoded a whole block at a time,
+// with tables built for each byte of the data or the block. The code is
+// linear, so the entries for each byte can simply be xored together. Longer
+// blocks are held as arrays of words and filled and emptied a run of bits at
+// a time.
+//
+// Soft bits are decoded with the Chase algorithm: the least reliable bits of a
+// block are flipped every possible way, each try is decoded as usual, and the
+// codeword that needs the least certain bits changed wins. An extended block
+// is uncorrectable if there's no winner or a tie between different codewords.
+
+// The shape of a code: `p` parity bits, and an overall parity bit if it's extended.
+#[derive(Clone, Copy)]
+struct Shape {
+    p: usize,
+    extended: bool,
 }
 
-fn append(myself: BitVec, other: BitVec) -> BitVec {
-    let mut result = BitVec::from_elem(myself.len() + other.len(), false);
-    for i in 0..myself.len() {
-        result.set(i, myself[i]);
+impl Shape {
+    // The length of a block in bits.
+    fn len(self) -> usize {
+        if self.extended { 1 << self.p } else { (1 << self.p) - 1 }
+    }
+
+    // The number of data bits in a block.
+    fn data_len(self) -> usize {
+        (1 << self.p) - self.p - 1
     }
-    for i in 0..other.len() {
-        result.set(myself.len() + i, other[i]);
+
+    // The positions of the data bits, in order.
+    fn data_positions(self) -> impl Iterator<Item = usize> {
+        (3..1 << self.p).filter(|i: &usize| !i.is_power_of_two())
+    }
+
+    // The runs of data bits, as the position and length of pieces of at most `PIECE` bits.
+    fn data_pieces(self) -> Vec<(usize, usize)> {
+        let mut pieces = Vec::new();
+        for n in 1..self.p {
+            pieces.extend(split((1 << n) + 1, (1 << n) - 1));
+        }
+        pieces
     }
-    result
 }
 
-fn check(mut block: BitVec, p: usize) -> BitVec {
-    let mut errors: Vec<usize> = Vec::new();
-    // for each parity bit
-    for n in 0..p {
-        // the index is 2 ^ n
-        let pindex = 1 << n;
-        let mut parity = false;
-        // we don't need to look at any indicies lower than pindex
-        for i in (pindex)..block.len() {
-            // if this index has the nth bit set
-            if pindex & (i+1) != 0 {
-                // xor its value with the parity value
-                parity = block[i] ^ parity;
+// The number of least reliable bits of a block the Chase decoder tries flipping.
+const CHASE_BITS: usize = 4;
+
+// Splits `len` bits from `start` into pieces of at most `PIECE` bits.
+fn split(start: usize, len: usize) -> Vec<(usize, usize)> {
+    (0..len).step_by(PIECE).map(|offset| (start + offset, cmp::min(PIECE, len - offset))).collect()
+}
+
+// What decoding found in a block, given its syndrome and whether its overall
+// parity is odd.
+enum Outcome {
+    Valid,
+    /// The bit at this position was wrong. It is one past the end of a plain
+    /// block for the overall parity bit of an extended block.
+    Corrected(usize),
+    Uncorrectable,
+}
+
+fn outcome(shape: Shape, syndrome: usize, odd: bool) -> Outcome {
+    match (syndrome, shape.extended, odd) {
+        (0, false, _) | (0, true, false) => Outcome::Valid,
+        (0, true, true) => Outcome::Corrected(shape.len()),
+        (_, false, _) | (_, true, true) => Outcome::Corrected(syndrome),
+        (_, true, false) => Outcome::Uncorrectable,
+    }
+}
+
+// Records the outcome for the block starting at bit `start` of the code.
+fn record(report: &mut Report, outcome: &Outcome, block: usize, start: usize) {
+    match *outcome {
+        Outcome::Valid => report.block(None),
+        Outcome::Corrected(position) => report.block(Some(start + position - 1)),
+        Outcome::Uncorrectable => report.uncorrectable_block(ErrorLocation { block, bit: start }),
+    }
+}
+
+// Tables for blocks of up to 64 bits. As many blocks as fit in `PIECE` bits
+// are handled together as a superblock, held in the low bits of a word with the
+// first bit of the first block the most significant, and so is their data.
+struct Tables {
+    /// The number of blocks in a superblock.
+    blocks: usize,
+    /// The superblock each byte of the data contributes, from the least significant byte.
+    encode: Vec<[u64; 256]>,
+    /// The data each byte of a superblock holds and its contribution to the
+    /// checks, which hold the syndrome of each block in `p + 1` bits from the
+    /// last block, with the overall parity above it for extended codes.
+    decode: Vec<[(u64, u64); 256]>,
+    /// The data bit to flip to correct each syndrome.
+    flip: Vec<u64>,
+}
+
+impl Tables {
+    fn new(shape: Shape) -> Tables {
+        let (len, data_len) = (shape.len(), shape.data_len());
+        let blocks = cmp::max(1, PIECE / len);
+        // the block holding just the `i`th data bit, which is bit `data_len - 1 - i` of the data
+        let columns: Vec<u64> = shape.data_positions().map(|position| {
+            let mut block = 1 << (len - position);
+            for n in 0..shape.p {
+                if position & 1 << n != 0 {
+                    block |= 1 << (len - (1 << n));
+                }
+            }
+            if shape.extended && (position.count_ones() + 1) % 2 == 1 {
+                block |= 1;
             }
+            block
+        }).collect();
+        let mut flip = vec![0; 1 << shape.p];
+        for (i, position) in shape.data_positions().enumerate() {
+            flip[position] = 1 << (data_len - 1 - i);
         }
-        // if the computed parity doesn't match the recorded parity, put it as an error
-        if parity != block[pindex-1] {
-            errors.push(pindex);
+
+        let encode = (0..(blocks * data_len).div_ceil(8)).map(|byte| {
+            let mut table = [0; 256];
+            for value in 1..256 {
+                let bit = 8 * byte + (value as u32).trailing_zeros() as usize;
+                let (block, bit) = (bit / data_len, bit % data_len);
+                let column = if block < blocks { columns[data_len - 1 - bit] << (block * len) } else { 0 };
+                table[value] = table[value & (value - 1)] ^ column;
+            }
+            table
+        }).collect();
+        let decode = (0..(blocks * len).div_ceil(8)).map(|byte| {
+            let mut table = [(0, 0); 256];
+            for value in 1..256 {
+                let bit = 8 * byte + (value as u32).trailing_zeros() as usize;
+                let (block, bit) = (bit / len, bit % len);
+                let entry = if block >= blocks {
+                    (0, 0)
+                } else {
+                    let position = len - bit;
+                    // the overall parity bit isn't part of the syndrome
+                    let mut checks = if position < 1 << shape.p { position as u64 } else { 0 };
+                    if shape.extended {
+                        checks |= 1 << shape.p;
+                    }
+                    let data = flip.get(position).cloned().unwrap_or(0);
+                    (data << (block * data_len), checks << (block * (shape.p + 1)))
+                };
+                let rest = table[value & (value - 1)];
+                table[value] = (rest.0 ^ entry.0, rest.1 ^ entry.1);
+            }
+            table
+        }).collect();
+        Tables { blocks, encode, decode, flip }
+    }
+}
+
+// A block longer than a word, as an array of words with bit 0 (which is
+// never used) the most significant bit of the first.
+struct LongBlock {
+    words: Vec<u64>,
+}
+
+impl LongBlock {
+    fn new(shape: Shape) -> LongBlock {
+        // an extra word so that a piece can always straddle two
+        LongBlock { words: vec![0; (shape.len() + 1) / 64 + 2] }
+    }
+
+    fn clear(&mut self) {
+        for word in &mut self.words {
+            *word = 0;
         }
     }
-        
-    // if there's one error, then the parity bit was wrong
-    if errors.len() == 1 {
-        let fix = ! block[errors[0]-1];
-        block.set(errors[0]-1, fix);
-    } else if errors.len() > 1 {
-    // otherwise, add the parity indexes to get the value where they overlap, and flip it
-        let mut errindex = 0;
-        for i in errors {
-            errindex += i;
-        }
-        let fix = ! block[errindex-1];
-        block.set(errindex-1, fix);
+
+    // Sets `n` bits from `position`, which must be clear, to `value`.
+    fn put(&mut self, position: usize, value: u64, n: usize) {
+        let (word, offset) = (position / 64, position % 64);
+        let pair = u128::from(self.words[word]) << 64 | u128::from(self.words[word + 1]);
+        let pair = pair | u128::from(value) << (128 - offset - n);
+        self.words[word] = (pair >> 64) as u64;
+        self.words[word + 1] = pair as u64;
+    }
+
+    fn get(&self, position: usize, n: usize) -> u64 {
+        let (word, offset) = (position / 64, position % 64);
+        let pair = u128::from(self.words[word]) << 64 | u128::from(self.words[word + 1]);
+        (pair >> (128 - offset - n)) as u64 & bits::mask(n)
+    }
+
+    fn flip(&mut self, position: usize) {
+        self.words[position / 64] ^= 1 << (63 - position % 64);
+    }
+
+    // The syndrome of the bits below `2^p`, and whether the number of set bits is odd.
+    fn syndrome(&self, p: usize) -> (usize, bool) {
+        let mut syndrome = 0;
+        let mut ones = 0;
+        for (i, &word) in self.words.iter().enumerate() {
+            ones += word.count_ones();
+            let mut rest = word;
+            while rest != 0 {
+                let bit = rest.leading_zeros() as usize;
+                syndrome ^= 64 * i + bit;
+                rest ^= 1 << (63 - bit);
+            }
+        }
+        (syndrome & ((1 << p) - 1), ones % 2 == 1)
     }
-    
-    block
 }
 
+// The last block is padded with zeros, and decoding returns them along with
+// the data; the header records the length of the data, and `recover` and
+// `DecodeReader` cut the output to it. Whole chunks fill a whole number of
+// blocks exactly, so encoding them one at a time when streaming gives the same
+// output as encoding all of the data at once.
 
+fn encode_blocks(v: &[u8], p: usize, extended: bool) -> Vec<u8> {
+    let shape = Shape { p, extended };
+    let (len, data_len) = (shape.len(), shape.data_len());
+    let blocks = (v.len() * 8).div_ceil(data_len);
+    let mut reader = BitReader::new(v);
+    let mut writer = BitWriter::with_capacity(blocks * len);
 
-fn assemble(block: BitVec, p: usize) -> BitVec {
-    let mut result = BitVec::with_capacity((1 << p) - p - 1);
-    for i in 0..(1 << p) {
-        // if it's a power of two, this is a parity bit
-        if is_power_of_two(i+1) {
-            ;
-        } else {
-            result.push(block[i]);
+    if len <= 64 {
+        let tables = Tables::new(shape);
+        let mut block = 0;
+        while block < blocks {
+            let count = cmp::min(tables.blocks, blocks - block);
+            let spare = tables.blocks - count;
+            let data = reader.read_word(count * data_len) << (spare * data_len);
+            let mut code = 0;
+            for (byte, table) in tables.encode.iter().enumerate() {
+                code ^= table[(data >> (8 * byte)) as u8 as usize];
+            }
+            writer.write_word(code >> (spare * len), count * len);
+            block += count;
+        }
+        return writer.finish();
+    }
+
+    let mut block = LongBlock::new(shape);
+    let data_pieces = shape.data_pieces();
+    let block_pieces = split(1, (1 << p) - 1);
+    for _ in 0..blocks {
+        block.clear();
+        for &(position, n) in &data_pieces {
+            block.put(position, reader.read(n), n);
+        }
+        let (syndrome, odd) = block.syndrome(p);
+        for n in 0..p {
+            if syndrome & 1 << n != 0 {
+                block.flip(1 << n);
+            }
+        }
+        for &(position, n) in &block_pieces {
+            writer.write(block.get(position, n), n);
+        }
+        if extended {
+            let overall = odd != (syndrome.count_ones() % 2 == 1);
+            writer.write(overall as u64, 1);
+        }
+    }
+    writer.finish()
+}
+
+fn decode_blocks(v: &[u8], p: usize, extended: bool) -> Result<DecodeOutput, DecodeError> {
+    let shape = Shape { p, extended };
+    let (len, data_len) = (shape.len(), shape.data_len());
+    // a partial block at the end can only be byte padding
+    let blocks = v.len() * 8 / len;
+    let mut reader = BitReader::new(v);
+    let mut writer = BitWriter::with_capacity(blocks * data_len);
+    let mut report = Report::new(v.len() * 8);
+
+    if len <= 64 {
+        let tables = Tables::new(shape);
+        let field = shape.p + 1;
+        let mut block = 0;
+        while block < blocks {
+            let count = cmp::min(tables.blocks, blocks - block);
+            let spare = tables.blocks - count;
+            let code = reader.read_word(count * len) << (spare * len);
+            let (mut data, mut checks) = (0, 0);
+            for (byte, table) in tables.decode.iter().enumerate() {
+                let entry = table[(code >> (8 * byte)) as u8 as usize];
+                data ^= entry.0;
+                checks ^= entry.1;
+            }
+            if checks == 0 {
+                report.blocks += count;
+            } else {
+                for i in 0..count {
+                    let shift = tables.blocks - 1 - i;
+                    let checks = (checks >> (shift * field)) as usize;
+                    let outcome = outcome(shape, checks & ((1 << shape.p) - 1), checks & 1 << shape.p != 0);
+                    if let Outcome::Corrected(position) = outcome {
+                        data ^= tables.flip.get(position).cloned().unwrap_or(0) << (shift * data_len);
+                    }
+                    record(&mut report, &outcome, block + i, (block + i) * len);
+                }
+            }
+            writer.write_word(data >> (spare * data_len), count * data_len);
+            block += count;
+        }
+    } else {
+        let mut block = LongBlock::new(shape);
+        let data_pieces = shape.data_pieces();
+        let block_pieces = split(1, len);
+        for i in 0..blocks {
+            block.clear();
+            for &(position, n) in &block_pieces {
+                block.put(position, reader.read(n), n);
+            }
+            let (syndrome, odd) = block.syndrome(p);
+            let outcome = outcome(shape, syndrome, odd);
+            if let Outcome::Corrected(position) = outcome {
+                block.flip(position);
+            }
+            record(&mut report, &outcome, i, i * len);
+            for &(position, n) in &data_pieces {
+                writer.write(block.get(position, n), n);
+            }
         }
     }
-    result
+
+    codec::decode_result(writer.finish(), report)
 }
 
+// Decodes a block of soft bits, returning the bits of the codeword and which
+// of them had to be changed, or `None` if it's uncorrectable.
+fn decode_soft_block(shape: Shape, soft: &[i8]) -> Option<(Vec<bool>, Vec<usize>)> {
+    let hard: Vec<bool> = soft.iter().map(|&llr| llr < 0).collect();
+    // positions are numbered from 1, and the overall parity bit is one past the plain block
+    let (mut syndrome, mut odd) = (0, false);
+    for (i, _) in hard.iter().enumerate().filter(|&(_, &bit)| bit) {
+        if i + 1 < 1 << shape.p {
+            syndrome ^= i + 1;
+        }
+        odd = !odd;
+    }
+    let mut order: Vec<usize> = (0..soft.len()).collect();
+    order.sort_by_key(|&i| soft::reliability(soft[i]));
+    order.truncate(CHASE_BITS);
 
-fn parity(mut block: BitVec, p: usize) -> BitVec {
-    // for each parity bit
-    for n in 0..p {
-        // calculate 2^n
-        let pindex = 1 << n;
-        let mut parity = false;
-        // we don't need to look at any indicies lower than pindex
-        for i in (pindex)..block.len() {
-            // if this index has the nth bit set
-            if pindex & (i+1) != 0 {
-                // xor its value with the parity value
-                parity = block[i] ^ parity;
+    let mut best: Option<(u32, Vec<usize>)> = None;
+    let mut tied = false;
+    for pattern in 0..1usize << order.len() {
+        let mut changed: Vec<usize> = (0..order.len()).filter(|&j| pattern >> j & 1 == 1).map(|j| order[j]).collect();
+        let (mut syndrome, mut odd) = (syndrome, odd);
+        for &i in &changed {
+            if i + 1 < 1 << shape.p {
+                syndrome ^= i + 1;
+            }
+            odd = !odd;
+        }
+        match outcome(shape, syndrome, odd) {
+            Outcome::Valid => {}
+            Outcome::Corrected(position) => match changed.iter().position(|&i| i == position - 1) {
+                Some(j) => { changed.swap_remove(j); }
+                None => changed.push(position - 1),
+            },
+            Outcome::Uncorrectable => continue,
+        }
+        changed.sort_unstable();
+        let cost = changed.iter().map(|&i| soft::reliability(soft[i])).sum();
+        match best {
+            Some((least, ref codeword)) if cost == least => tied |= *codeword != changed,
+            Some((least, _)) if cost > least => {}
+            _ => {
+                best = Some((cost, changed));
+                tied = false;
+            }
+        }
+    }
+    match best {
+        Some(_) if tied && shape.extended => None,
+        Some((_, changed)) => {
+            let mut bits = hard;
+            for &i in &changed {
+                bits[i] = !bits[i];
             }
+            Some((bits, changed))
         }
-        // set the 2^nth bit to the parity
-        block.set(pindex-1, parity);
+        None => None,
     }
-    block
 }
 
-fn arrange(plain: &BitVec, start: usize, p: usize) -> (usize, BitVec) {
-    let mut block = BitVec::with_capacity((1 << p)-1);
-    let mut index = start;
-    // for each item in the block
-    for i in 0..((1 << p)-1) {
-        // if it's a power of two, reserve the bit for parity
-        // and if we're at the end of the plaintext, just push 0.
-        if is_power_of_two(i+1) || index > plain.len() {
-            block.push(false);
-        } else {
-            // otherwise, push the next plaintext value
-            block.push( plain[index] );
-            index += 1;
+fn decode_soft_blocks(soft: &[i8], p: usize, extended: bool) -> Result<DecodeOutput, DecodeError> {
+    let shape = Shape { p, extended };
+    let (len, data_len) = (shape.len(), shape.data_len());
+    let mut writer = BitWriter::with_capacity(soft.len() / len * data_len);
+    let mut report = Report::new(soft.len());
+
+    for (block, soft) in soft.chunks_exact(len).enumerate() {
+        let start = block * len;
+        let bits = match decode_soft_block(shape, soft) {
+            Some((bits, changed)) => {
+                report.block(changed.into_iter().map(|i| start + i));
+                bits
+            }
+            None => {
+                report.uncorrectable_block(ErrorLocation { block, bit: start });
+                soft.iter().map(|&llr| llr < 0).collect()
+            }
+        };
+        for position in shape.data_positions() {
+            writer.write(bits[position - 1] as u64, 1);
         }
     }
-    (index,block)
+
+    codec::decode_result(writer.finish(), report)
+}
+
+pub fn encode(v: &[u8], p: usize) -> Vec<u8> {
+    encode_blocks(v, p, false)
 }
 
-pub fn encode(v: &Vec<u8>, p: usize) -> Vec<u8> {
-    let plain = BitVec::from_bytes(&v);
-    let mut code = BitVec::with_capacity(2 * plain.len());
-    let mut index = 0;
-    while index < plain.len() {
-        let (new_index, temp_block) = arrange(&plain, index, p);
-        let block = parity(temp_block, p);
-        code = append(code, block);
-        index = new_index;
-    }
-    code.to_bytes()
+/// Decodes the `len` bytes of data that `encode` encoded into `v`. The length
+/// has to be given since the last block is padded with zeros, which would
+/// otherwise be taken for data.
+///
+/// Plain Hamming codes can't tell a single error from a larger one, so this
+/// never fails; blocks with several errors are silently miscorrected.
+pub fn decode(v: &[u8], p: usize, len: usize) -> Result<DecodeOutput, DecodeError> {
+    cut(decode_blocks(v, p, false), len)
+}
+
+/// Encodes with an extended Hamming code, which has an extra parity bit in
+/// each block so that double errors can be detected.
+pub fn encode_extended(v: &[u8], p: usize) -> Vec<u8> {
+    encode_blocks(v, p, true)
+}
+
+/// Decodes the `len` bytes of data that `encode_extended` encoded into `v`,
+/// correcting single errors and reporting blocks with double errors.
+pub fn decode_extended(v: &[u8], p: usize, len: usize) -> Result<DecodeOutput, DecodeError> {
+    cut(decode_blocks(v, p, true), len)
+}
+
+// Cuts decoded data down to its first `len` bytes, leaving out the padding.
+fn cut(result: Result<DecodeOutput, DecodeError>, len: usize) -> Result<DecodeOutput, DecodeError> {
+    match result {
+        Ok(mut output) => {
+            output.data.truncate(len);
+            Ok(output)
+        }
+        Err(DecodeError::Uncorrectable { locations, mut output }) => {
+            output.data.truncate(len);
+            Err(DecodeError::Uncorrectable { locations, output })
+        }
+    }
+}
+
+// Any eight blocks hold a whole number of bytes both before and after encoding.
+fn chunk_blocks(block_len: usize) -> usize {
+    8 * cmp::max(1, codec::CHUNK_BYTES / block_len)
+}
+
+/// Hamming code with `p` parity bits in each block of `2^p - 1` bits.
+pub struct Hamming {
+    p: usize,
+}
+
+impl Hamming {
+    /// # Panics
+    ///
+    /// Panics if `p` is not between 2 and 16.
+    pub fn new(p: usize) -> Hamming {
+        assert!((2..=16).contains(&p), "the number of parity bits must be between 2 and 16");
+        Hamming { p }
+    }
+
+    pub fn build(parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+        let p = codec::single_parameter(parameters, 2, 16)?;
+        Ok(Box::new(Hamming::new(p)))
+    }
+
+    fn block_len(&self) -> usize {
+        (1 << self.p) - 1
+    }
+
+    fn chunk_blocks(&self) -> usize {
+        chunk_blocks(self.block_len())
+    }
+}
+
+impl Codec for Hamming {
+    fn name(&self) -> &'static str { "hamming" }
+
+    fn parameters(&self) -> Vec<usize> { vec![self.p] }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { encode(data, self.p) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> { decode_blocks(code, self.p, false) }
+
+    fn chunk_len(&self) -> usize { self.chunk_blocks() * (self.block_len() - self.p) / 8 }
+
+    fn encoded_chunk_len(&self) -> usize { self.chunk_blocks() * self.block_len() / 8 }
+
+    fn decode_soft(&self, soft: &[i8]) -> Result<DecodeOutput, DecodeError> {
+        decode_soft_blocks(soft, self.p, false)
+    }
+
+    fn rate(&self) -> f64 {
+        (self.block_len() - self.p) as f64 / self.block_len() as f64
+    }
+
+    fn correction_capability(&self) -> Capability {
+        Capability { errors: 1, block: self.block_len(), unit: Unit::Bit }
+    }
 }
 
-pub fn decode(v: &Vec<u8>, p: usize) -> Vec<u8> {
-    let code = BitVec::from_bytes(&v);
-    let mut plain = BitVec::with_capacity(code.len() / 2);
-    let mut index = 0;
-    let length = (1 << p) - 1;
-    while index < code.len() {
-        if index + length >= code.len() {
-            break;
-        }
-        let mut block = BitVec::with_capacity(length);
-        for i in index..(index+length) {
-            block.push(code[i]);
-        }
-        block = check(block, p);
-        block = assemble(block, p);
-        plain = append(plain, block);
-        index = index+length;
-    }
-    plain.to_bytes()
+/// Extended Hamming code (SECDED) with `p` parity bits and an overall parity
+/// bit in each block of `2^p` bits.
+pub struct ExtendedHamming {
+    p: usize,
+}
+
+impl ExtendedHamming {
+    /// # Panics
+    ///
+    /// Panics if `p` is not between 2 and 16.
+    pub fn new(p: usize) -> ExtendedHamming {
+        assert!((2..=16).contains(&p), "the number of parity bits must be between 2 and 16");
+        ExtendedHamming { p }
+    }
+
+    pub fn build(parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+        let p = codec::single_parameter(parameters, 2, 16)?;
+        Ok(Box::new(ExtendedHamming::new(p)))
+    }
+
+    fn block_len(&self) -> usize {
+        1 << self.p
+    }
+
+    fn chunk_blocks(&self) -> usize {
+        chunk_blocks(self.block_len())
+    }
+}
+
+impl Codec for ExtendedHamming {
+    fn name(&self) -> &'static str { "extended-hamming" }
+
+    fn parameters(&self) -> Vec<usize> { vec![self.p] }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { encode_extended(data, self.p) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> { decode_blocks(code, self.p, true) }
+
+    fn chunk_len(&self) -> usize { self.chunk_blocks() * (self.block_len() - 1 - self.p) / 8 }
+
+    fn encoded_chunk_len(&self) -> usize { self.chunk_blocks() * self.block_len() / 8 }
+
+    fn decode_soft(&self, soft: &[i8]) -> Result<DecodeOutput, DecodeError> {
+        decode_soft_blocks(soft, self.p, true)
+    }
+
+    fn rate(&self) -> f64 {
+        (self.block_len() - 1 - self.p) as f64 / self.block_len() as f64
+    }
+
+    fn correction_capability(&self) -> Capability {
+        Capability { errors: 1, block: self.block_len(), unit: Unit::Bit }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitvec::BitVec;
+
+    // The original bit at a time implementation, to check the tables against.
+
+    fn is_power_of_two(n: usize) -> bool {
+        (n & (n - 1)) == 0
+    }
+
+    fn append(mut myself: BitVec, mut other: BitVec) -> BitVec {
+        myself.append(&mut other);
+        myself
+    }
+
+    // xor together the (one-based) positions of every set bit. Each parity bit
+    // covers the positions with its bit set, so this gives all of the parity checks
+    // at once: it is zero for a valid block, and otherwise it's the position of
+    // the bit that needs to be flipped.
+    fn syndrome(block: &BitVec) -> usize {
+        let mut syndrome = 0;
+        for (i, bit) in block.iter().enumerate() {
+            if bit {
+                syndrome ^= i + 1;
+            }
+        }
+        syndrome
+    }
+
+    fn check(mut block: BitVec) -> BitVec {
+        let errindex = syndrome(&block);
+        // flip the bit the parity checks point at
+        if errindex != 0 && errindex <= block.len() {
+            let fix = ! block[errindex-1];
+            block.set(errindex-1, fix);
+        }
+        block
+    }
+
+    // Extended Hamming codes add a bit to the end of each block holding the parity
+    // of the whole block. A single error always changes the overall parity and a
+    // double error never does, so a non-zero syndrome with matching overall parity
+    // means there are (at least) two errors. Returns the block without the overall
+    // parity bit and the position of the bit that was corrected, if any, or `None`
+    // if the errors can't be corrected.
+    fn check_extended(mut block: BitVec) -> Option<(BitVec, Option<usize>)> {
+        let overall = block.pop().expect("blocks are never empty");
+        let odd = block.iter().filter(|&bit| bit).count() % 2 == 1;
+        let parity_error = odd != overall;
+        let errindex = syndrome(&block);
+        if errindex == 0 {
+            // either there's no error, or it's in the overall parity bit
+            let corrected = if parity_error { Some(block.len()) } else { None };
+            Some((block, corrected))
+        } else if parity_error {
+            Some((check(block), Some(errindex - 1)))
+        } else {
+            None
+        }
+    }
+
+    fn assemble(block: BitVec, p: usize) -> BitVec {
+        let mut result = BitVec::with_capacity((1 << p) - p - 1);
+        for i in 0..(1 << p) {
+            // if it's a power of two, this is a parity bit
+            if !is_power_of_two(i+1) {
+                result.push(block[i]);
+            }
+        }
+        result
+    }
+
+
+    fn parity(mut block: BitVec, p: usize) -> BitVec {
+        // parity bits don't cover themselves, so clear them before computing the checks
+        for n in 0..p {
+            block.set((1 << n) - 1, false);
+        }
+        let checks = syndrome(&block);
+        // set the 2^nth bit to the nth parity check
+        for n in 0..p {
+            block.set((1 << n) - 1, checks & (1 << n) != 0);
+        }
+        block
+    }
+
+    fn arrange(plain: &BitVec, start: usize, p: usize) -> (usize, BitVec) {
+        let mut block = BitVec::with_capacity((1 << p)-1);
+        let mut index = start;
+        // for each item in the block
+        for i in 0..((1 << p)-1) {
+            // if it's a power of two, reserve the bit for parity
+            // and if we're at the end of the plaintext, just push 0.
+            if is_power_of_two(i+1) || index >= plain.len() {
+                block.push(false);
+            } else {
+                // otherwise, push the next plaintext value
+                block.push( plain[index] );
+                index += 1;
+            }
+        }
+        (index,block)
+    }
+
+    fn reference_encode(v: &[u8], p: usize, extended: bool) -> Vec<u8> {
+        let plain = BitVec::from_bytes(v);
+        let mut code = BitVec::with_capacity(2 * plain.len());
+        let mut index = 0;
+        while index < plain.len() {
+            let (new_index, temp_block) = arrange(&plain, index, p);
+            let mut block = parity(temp_block, p);
+            if extended {
+                let odd = block.iter().filter(|&bit| bit).count() % 2 == 1;
+                block.push(odd);
+            }
+            code = append(code, block);
+            index = new_index;
+        }
+        code.to_bytes()
+    }
+
+    fn reference_decode(v: &[u8], p: usize, extended: bool) -> Result<DecodeOutput, DecodeError> {
+        let code = BitVec::from_bytes(v);
+        let mut plain = BitVec::with_capacity(code.len() / 2);
+        let mut report = Report::new(code.len());
+        let mut index = 0;
+        let length = if extended { 1 << p } else { (1 << p) - 1 };
+        // a partial block at the end can only be byte padding
+        while index + length <= code.len() {
+            let mut block = BitVec::with_capacity(length);
+            for i in index..(index+length) {
+                block.push(code[i]);
+            }
+            block = if extended {
+                match check_extended(block.clone()) {
+                    Some((block, corrected)) => {
+                        report.block(corrected.map(|i| index + i));
+                        block
+                    }
+                    None => {
+                        // leave the block as it was
+                        report.uncorrectable_block(ErrorLocation { block: index / length, bit: index });
+                        block.pop();
+                        block
+                    }
+                }
+            } else {
+                let errindex = syndrome(&block);
+                report.block(if errindex == 0 { None } else { Some(index + errindex - 1) });
+                check(block)
+            };
+            block = assemble(block, p);
+            plain = append(plain, block);
+            index += length;
+        }
+        codec::decode_result(plain.to_bytes(), report)
+    }
     
     #[test]
     fn power_of_two_works() {
@@ -191,7 +786,7 @@ mod tests {
         corrupt1.push(false); // d 6
         corrupt1.push(false); // d 7
         
-        assert_eq!(check(corrupt1, 3), perfect1);
+        assert_eq!(check(corrupt1), perfect1);
     }
 
     #[test]
@@ -214,7 +809,7 @@ mod tests {
         corrupt.push(true); // d 6
         corrupt.push(true); // d 7
         
-        assert_eq!(check(corrupt, 3), perfect);
+        assert_eq!(check(corrupt), perfect);
     }
 
     #[test]
@@ -237,7 +832,7 @@ mod tests {
         corrupt.push(false); // d 6
         corrupt.push(false); // d 7
         
-        assert_eq!(check(corrupt, 3), perfect);
+        assert_eq!(check(corrupt), perfect);
     }
     
     #[test]
@@ -260,7 +855,7 @@ mod tests {
         corrupt.push(false); // d 6
         corrupt.push(false); // d 7
         
-        assert_eq!(check(corrupt, 3), perfect);
+        assert_eq!(check(corrupt), perfect);
         
         perfect = BitVec::new();
         perfect.push(true); // p 1
@@ -280,7 +875,7 @@ mod tests {
         corrupt.push(false); // d 6
         corrupt.push(false); // d 7
         
-        assert_eq!(check(corrupt, 3), perfect);
+        assert_eq!(check(corrupt), perfect);
     }
     
     #[test]
@@ -374,7 +969,7 @@ mod tests {
         arranged.push(true); // d 6
         arranged.push(false); // d 7
         
-        let (i, result) = arrange(&plain, i, 3);
+        let (_, result) = arrange(&plain, i, 3);
         
         assert_eq!(result, arranged);
     }
@@ -387,10 +982,216 @@ mod tests {
         let test4: Vec<u8> = vec![0,0,0];
         let test5: Vec<u8> = vec![128,32,2];
         
-        assert_eq!(decode(&encode(&test1, 3), 3), test1);
-        assert_eq!(decode(&encode(&test2, 3), 3), test2);
-        assert_eq!(decode(&encode(&test3, 3), 3), test3);
-        assert_eq!(decode(&encode(&test4, 3), 3), test4);
-        assert_eq!(decode(&encode(&test5, 3), 3), test5);
+        for test in &[test1, test2, test3, test4, test5] {
+            assert_eq!(decode(&encode(test, 3), 3, test.len()).unwrap().data, *test);
+        }
+    }
+    
+    // Checks that what follows the first `len` bytes of decoded blocks is only
+    // the padding of the last block, and cuts it off as the header's length would.
+    fn trim(mut data: Vec<u8>, len: usize, p: usize) -> Vec<u8> {
+        assert!(data.len() >= len, "{} bytes decoded, {} encoded", data.len(), len);
+        assert!((data.len() - len) * 8 < (1 << p) + 8, "{} bytes of padding", data.len() - len);
+        assert!(data[len..].iter().all(|&byte| byte == 0));
+        data.truncate(len);
+        data
+    }
+    
+    #[test]
+    fn exact_length() {
+        // every length of input, for every size of block
+        let data: Vec<u8> = (0..4096).map(|i: usize| (i * 157 % 251) as u8).collect();
+        for p in 2..=8 {
+            let config = ::Config::new("hamming", &[p]).unwrap();
+            for len in 0..data.len() {
+                let plain = &data[..len];
+                assert_eq!(decode(&encode(plain, p), p, len).unwrap().data, plain, "p = {}, length = {}", p, len);
+                assert_eq!(::recover(&::protect(plain, &config)).unwrap().data, plain, "p = {}, length = {}", p, len);
+            }
+        }
+    }
+    
+    #[test]
+    fn exact_length_with_errors() {
+        let plain: Vec<u8> = vec![0, 0, 0, 0, 0];
+        for p in 2..=8 {
+            let config = ::Config::new("hamming", &[p]).unwrap();
+            let mut encoded = ::protect(&plain, &config);
+            // flip one bit in the last block, which is partly padding
+            let header = encoded.len() - encode(&plain, p).len();
+            let last = (5 * 8 - 1) / ((1 << p) - p - 1);
+            let bit = header * 8 + last * ((1 << p) - 1) + 2;
+            encoded[bit / 8] ^= 0x80 >> (bit % 8);
+            assert_eq!(::recover(&encoded).unwrap().data, plain, "p = {}", p);
+        }
+    }
+    
+    #[test]
+    fn extended_inverse() {
+        let data: Vec<u8> = (0..300).map(|i: usize| (i * 157 % 251) as u8).collect();
+        for p in 2..=8 {
+            for len in 0..40 {
+                let plain = &data[..len * 7];
+                let decoded = decode_extended(&encode_extended(plain, p), p, plain.len()).unwrap().data;
+                assert_eq!(decoded, plain);
+            }
+        }
+    }
+    
+    #[test]
+    fn extended_error_correction() {
+        let plain: Vec<u8> = vec![1,1,2,3,5,8,13,21,34];
+        for p in 2..=6 {
+            let length = 1 << p;
+            let code = encode_extended(&plain, p);
+            // one error in each block, including the overall parity bits
+            for offset in 0..length {
+                let mut damaged = code.clone();
+                let mut bit = offset;
+                while bit < code.len() * 8 {
+                    damaged[bit / 8] ^= 0x80 >> (bit % 8);
+                    bit += length;
+                }
+                let decoded = decode_extended(&damaged, p, plain.len()).unwrap().data;
+                assert_eq!(decoded, plain, "p = {}, offset = {}", p, offset);
+            }
+        }
+    }
+    
+    #[test]
+    fn extended_error_detection() {
+        let plain: Vec<u8> = vec![1,1,2,3,5,8,13,21,34];
+        for p in 2..=6 {
+            let length = 1 << p;
+            let code = encode_extended(&plain, p);
+            // every pair of errors in the second block
+            for first in 0..length {
+                for second in (first + 1)..length {
+                    let mut damaged = code.clone();
+                    for &bit in &[length + first, length + second] {
+                        damaged[bit / 8] ^= 0x80 >> (bit % 8);
+                    }
+                    match decode_extended(&damaged, p, plain.len()) {
+                        Err(DecodeError::Uncorrectable { locations, .. }) => {
+                            assert_eq!(locations, vec![ErrorLocation { block: 1, bit: length }]);
+                        }
+                        other => panic!("p = {}, errors at {} and {}: {:?}", p, first, second, other),
+                    }
+                }
+            }
+        }
+    }
+    
+    #[test]
+    fn plain_miscorrects_double_errors() {
+        // the problem extended codes solve: two errors look like one
+        let plain: Vec<u8> = vec![0];
+        let mut code = encode(&plain, 3);
+        code[0] ^= 0b0110_0000;
+        assert_ne!(decode(&code, 3, plain.len()).unwrap().data, plain);
+    }
+    
+    #[test]
+    fn same_as_reference() {
+        let mut rng = ::rng::Rng::new(16);
+        for p in 2..=9 {
+            for &extended in &[false, true] {
+                for &len in &[0, 1, 2, 7, 64, 255, 1000] {
+                    let plain: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+                    let code = encode_blocks(&plain, p, extended);
+                    assert_eq!(code, reference_encode(&plain, p, extended), "p = {}, length = {}", p, len);
+                    // some blocks with one error, some with two and some with more
+                    let mut damaged = code.clone();
+                    for _ in 0..rng.below(code.len() / 4 + 2) {
+                        if !damaged.is_empty() {
+                            let bit = rng.below(damaged.len() * 8);
+                            damaged[bit / 8] ^= 0x80 >> (bit % 8);
+                        }
+                    }
+                    for code in &[&code, &damaged] {
+                        assert_eq!(decode_blocks(code, p, extended), reference_decode(code, p, extended),
+                            "p = {}, extended = {}, length = {}", p, extended, len);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn soft_decoding_of_hard_bits() {
+        // certain bits are no different from hard ones
+        let mut rng = ::rng::Rng::new(20);
+        for p in 2..=6 {
+            for &extended in &[false, true] {
+                let plain: Vec<u8> = (0..200).map(|_| rng.next_u64() as u8).collect();
+                let mut code = encode_blocks(&plain, p, extended);
+                for _ in 0..code.len() / 4 {
+                    let bit = rng.below(code.len() * 8);
+                    code[bit / 8] ^= 0x80 >> (bit % 8);
+                }
+                let soft = soft::from_hard(&code);
+                assert_eq!(decode_soft_blocks(&soft, p, extended), decode_blocks(&code, p, extended),
+                    "p = {}, extended = {}", p, extended);
+            }
+        }
+    }
+
+    #[test]
+    fn soft_decoding_corrects_more() {
+        let plain: Vec<u8> = (0..100).collect();
+        for &(extended, p) in &[(false, 3), (true, 3), (true, 5)] {
+            let code = encode_blocks(&plain, p, extended);
+            let mut soft = soft::from_hard(&code);
+            // two errors in every block, but ones the demodulator wasn't sure of
+            let len = Shape { p, extended }.len();
+            for block in 0..soft.len() / len {
+                for &i in &[1, len - 2] {
+                    let bit = &mut soft[block * len + i];
+                    *bit = -bit.signum() * 5;
+                }
+            }
+            let output = decode_soft_blocks(&soft, p, extended).unwrap();
+            assert_eq!(trim(output.data, plain.len(), p), plain, "p = {}, extended = {}", p, extended);
+            assert_eq!(output.report.corrected_bits.len(), 2 * (soft.len() / len));
+            let hard = decode_blocks(&soft::hard_decisions(&soft), p, extended);
+            assert!(hard.map(|output| output.data[..plain.len()].to_vec()) != Ok(plain.clone()));
+        }
+    }
+
+    #[test]
+    fn long_blocks_correct_errors() {
+        let plain: Vec<u8> = (0..2000).map(|i: usize| (i * 157 % 251) as u8).collect();
+        for p in 7..=10 {
+            let length = (1 << p) - 1;
+            let code = encode(&plain, p);
+            for &offset in &[0, 1, 2, 63, 64, 65, length / 2, length - 1] {
+                let blocks = code.len() * 8 / length;
+                let mut damaged = code.clone();
+                for bit in (0..blocks).map(|block| block * length + offset) {
+                    damaged[bit / 8] ^= 0x80 >> (bit % 8);
+                }
+                let output = decode(&damaged, p, plain.len()).unwrap();
+                assert_eq!(output.report.corrected_bits.len(), blocks);
+                assert_eq!(output.data, plain, "p = {}, offset = {}", p, offset);
+            }
+        }
+    }
+
+    // cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn throughput() {
+        use std::time::Instant;
+        let plain: Vec<u8> = (0..64 << 20).map(|i: usize| (i * 157 % 251) as u8).collect();
+        for p in 3..=4 {
+            let start = Instant::now();
+            let code = encode(&plain, p);
+            let encoding = plain.len() as f64 / start.elapsed().as_secs_f64() / 1e6;
+            let start = Instant::now();
+            assert_eq!(decode(&code, p, plain.len()).unwrap().data, plain);
+            let decoding = plain.len() as f64 / start.elapsed().as_secs_f64() / 1e6;
+            assert!(encoding >= 100.0 && decoding >= 100.0,
+                "p = {}: encoding at {:.0} MB/s, decoding at {:.0} MB/s", p, encoding, decoding);
+        }
     }
 }
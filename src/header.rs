@@ -0,0 +1,355 @@
+use std::fmt;
+
+use codec;
+use crc::crc32;
+use interleave::Kind;
+use multiple;
+
+// Every encoded file starts with this header, so that decoding doesn't need to
+// be told which code was used. The header is small, so it is protected with a
+// heavy repetition code rather than the (possibly weak) code chosen for the data.
+
+pub const MAGIC: [u8; 4] = *b"ECCF";
+/// Version 2 added interleaving and version 3 the checksum of the data.
+/// Older headers can still be read.
+pub const VERSION: u8 = 3;
+
+/// Number of times each header bit is repeated on disk.
+pub const REPETITION: usize = 7;
+/// Size of the header before repetition coding.
+pub const RAW_LEN: usize = 35;
+/// Size of the header as it is stored at the start of an encoded file.
+pub const ENCODED_LEN: usize = RAW_LEN * REPETITION;
+/// Size of a version 1 header before repetition coding.
+pub const RAW_LEN_V1: usize = 26;
+/// Size of a version 2 header before repetition coding.
+pub const RAW_LEN_V2: usize = 31;
+/// The magic bytes and the version, which say how long the rest of the header is.
+pub const PREFIX_LEN: usize = 5 * REPETITION;
+
+// When data is streamed its length isn't known until it has all been encoded,
+// so the header's length is set to `STREAMED` and the length and checksums are
+// written in a trailer at the end of the file instead, protected the same way.
+// Files with a version 2 header have a trailer without the checksum of the data.
+
+/// The length in the header of a streamed file.
+pub const STREAMED: u64 = u64::MAX;
+pub const TRAILER_MAGIC: [u8; 4] = *b"ECCT";
+/// Size of the trailer before repetition coding.
+pub const TRAILER_RAW_LEN: usize = 24;
+/// Size of the trailer as it is stored at the end of a streamed file.
+pub const TRAILER_LEN: usize = TRAILER_RAW_LEN * REPETITION;
+/// Size of the trailer of a file with a version 2 header.
+pub const TRAILER_LEN_V2: usize = 20 * REPETITION;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The registry id of the codec used for the payload.
+    pub codec: u8,
+    /// The numerical parameters of the codec (`-n`, and `-t` for BCH codes, on
+    /// the command line), packed by the `pack` function of its registry entry.
+    pub parameter: u32,
+    /// Length in bytes of the original, unencoded data.
+    pub length: u64,
+    /// CRC-32 of the encoded payload following the header.
+    pub checksum: u32,
+    /// The id of the interleaver (see `interleave::Kind`), or 0 for none.
+    pub interleaver: u8,
+    /// The interleaving depth, or 0 for none.
+    pub depth: u32,
+    /// CRC-32 of the original data, so that errors that were corrected wrongly
+    /// are noticed. It is `None` for headers older than version 3, which are
+    /// written back the way they were read.
+    pub data_checksum: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    /// Length in bytes of the original, unencoded data.
+    pub length: u64,
+    /// CRC-32 of the encoded payload between the header and the trailer.
+    pub checksum: u32,
+    /// CRC-32 of the original data, or `None` after a version 2 header.
+    pub data_checksum: Option<u32>,
+}
+
+/// Errors reading a header, or the trailer of a streamed file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The input is shorter than a header.
+    Truncated,
+    /// The input doesn't start with the magic bytes, so it probably isn't an encoded file.
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownCodec(u8),
+    UnknownInterleaver(u8),
+    /// The header had more errors than the repetition code could correct.
+    Corrupt,
+    /// A streamed file doesn't end with a trailer, so it has probably been cut short.
+    MissingTrailer,
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::Truncated => write!(f, "the input is too short to contain a header"),
+            HeaderError::BadMagic => write!(f, "the input is not an encoded file"),
+            HeaderError::UnsupportedVersion(v) => write!(f, "unsupported header version {}", v),
+            HeaderError::UnknownCodec(c) => write!(f, "unknown codec {}", c),
+            HeaderError::UnknownInterleaver(i) => write!(f, "unknown interleaver {}", i),
+            HeaderError::Corrupt => write!(f, "the header is damaged beyond repair"),
+            HeaderError::MissingTrailer => write!(f, "the end of the streamed data is missing"),
+        }
+    }
+}
+
+impl Header {
+    /// Serializes the header and applies the repetition code to it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(RAW_LEN);
+        raw.extend_from_slice(&MAGIC);
+        raw.push(if self.data_checksum.is_some() { VERSION } else { 2 });
+        raw.push(self.codec);
+        raw.extend_from_slice(&self.parameter.to_be_bytes());
+        raw.extend_from_slice(&self.length.to_be_bytes());
+        raw.extend_from_slice(&self.checksum.to_be_bytes());
+        raw.push(self.interleaver);
+        raw.extend_from_slice(&self.depth.to_be_bytes());
+        if let Some(data_checksum) = self.data_checksum {
+            raw.extend_from_slice(&data_checksum.to_be_bytes());
+        }
+        let crc = crc32(&raw);
+        raw.extend_from_slice(&crc.to_be_bytes());
+        multiple::encode(&raw, REPETITION)
+    }
+
+    /// Reads the header from the start of `bytes`. The payload begins at `encoded_len(bytes)`.
+    pub fn parse(bytes: &[u8]) -> Result<Header, HeaderError> {
+        let len = encoded_len(bytes)?;
+        if bytes.len() < len {
+            return Err(HeaderError::Truncated);
+        }
+        let raw = match multiple::decode(&bytes[..len], REPETITION) {
+            Ok(output) => output.data,
+            Err(_) => return Err(HeaderError::Corrupt),
+        };
+
+        let raw_len = raw.len();
+        if crc32(&raw[..raw_len - 4]) != be_u32(&raw[raw_len - 4..]) {
+            return Err(HeaderError::Corrupt);
+        }
+        if codec::by_id(raw[5]).is_none() {
+            return Err(HeaderError::UnknownCodec(raw[5]));
+        }
+        let (interleaver, depth) = if raw[4] == 1 { (0, 0) } else { (raw[22], be_u32(&raw[23..27])) };
+        if interleaver != 0 && Kind::from_id(interleaver).is_none() {
+            return Err(HeaderError::UnknownInterleaver(interleaver));
+        }
+
+        Ok(Header {
+            codec: raw[5],
+            parameter: be_u32(&raw[6..10]),
+            length: be_u64(&raw[10..18]),
+            checksum: be_u32(&raw[18..22]),
+            interleaver,
+            depth,
+            data_checksum: if raw[4] == VERSION { Some(be_u32(&raw[27..31])) } else { None },
+        })
+    }
+
+    /// Whether the length and checksums are in a trailer rather than the header.
+    pub fn is_streamed(&self) -> bool {
+        self.length == STREAMED
+    }
+
+    /// The size of the trailer of a streamed file with this header.
+    pub fn trailer_len(&self) -> usize {
+        if self.data_checksum.is_some() { TRAILER_LEN } else { TRAILER_LEN_V2 }
+    }
+
+    /// Fills in the length and checksums of a streamed file from its trailer.
+    pub fn complete(&mut self, trailer: &Trailer) {
+        self.length = trailer.length;
+        self.checksum = trailer.checksum;
+        self.data_checksum = trailer.data_checksum;
+    }
+}
+
+impl Trailer {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(TRAILER_RAW_LEN);
+        raw.extend_from_slice(&TRAILER_MAGIC);
+        raw.extend_from_slice(&self.length.to_be_bytes());
+        raw.extend_from_slice(&self.checksum.to_be_bytes());
+        if let Some(data_checksum) = self.data_checksum {
+            raw.extend_from_slice(&data_checksum.to_be_bytes());
+        }
+        let crc = crc32(&raw);
+        raw.extend_from_slice(&crc.to_be_bytes());
+        multiple::encode(&raw, REPETITION)
+    }
+
+    /// Reads the trailer from `bytes`, which must be the last `trailer_len`
+    /// bytes of the file, as its header gives it.
+    pub fn parse(bytes: &[u8]) -> Result<Trailer, HeaderError> {
+        if bytes.len() != TRAILER_LEN && bytes.len() != TRAILER_LEN_V2 {
+            return Err(HeaderError::MissingTrailer);
+        }
+        let raw = match multiple::decode(bytes, REPETITION) {
+            Ok(output) => output.data,
+            Err(_) => return Err(HeaderError::Corrupt),
+        };
+
+        if raw[0..4] != TRAILER_MAGIC {
+            return Err(HeaderError::MissingTrailer);
+        }
+        let raw_len = raw.len();
+        if crc32(&raw[..raw_len - 4]) != be_u32(&raw[raw_len - 4..]) {
+            return Err(HeaderError::Corrupt);
+        }
+
+        Ok(Trailer {
+            length: be_u64(&raw[4..12]),
+            checksum: be_u32(&raw[12..16]),
+            data_checksum: if bytes.len() == TRAILER_LEN { Some(be_u32(&raw[16..20])) } else { None },
+        })
+    }
+}
+
+/// The length of the header at the start of `bytes`, which depends on its
+/// version. Only the first `PREFIX_LEN` bytes are needed.
+pub fn encoded_len(bytes: &[u8]) -> Result<usize, HeaderError> {
+    if bytes.len() < PREFIX_LEN {
+        return Err(HeaderError::Truncated);
+    }
+    let raw = match multiple::decode(&bytes[..PREFIX_LEN], REPETITION) {
+        Ok(output) => output.data,
+        Err(_) => return Err(HeaderError::Corrupt),
+    };
+    if raw[0..4] != MAGIC {
+        return Err(HeaderError::BadMagic);
+    }
+    match raw[4] {
+        1 => Ok(RAW_LEN_V1 * REPETITION),
+        2 => Ok(RAW_LEN_V2 * REPETITION),
+        VERSION => Ok(ENCODED_LEN),
+        version => Err(HeaderError::UnsupportedVersion(version)),
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_be_bytes(buf)
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Header {
+        Header {
+            codec: 2,
+            parameter: 3,
+            length: 123_456_789_012,
+            checksum: 0xDEAD_BEEF,
+            interleaver: 1,
+            depth: 12,
+            data_checksum: Some(0x0BAD_F00D),
+        }
+    }
+
+    #[test]
+    fn inverse() {
+        let bytes = sample().to_bytes();
+        assert_eq!(bytes.len(), ENCODED_LEN);
+        assert_eq!(Header::parse(&bytes), Ok(sample()));
+    }
+
+    #[test]
+    fn payload_is_ignored() {
+        let mut bytes = sample().to_bytes();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(Header::parse(&bytes), Ok(sample()));
+    }
+
+    #[test]
+    fn error_correction() {
+        let mut bytes = sample().to_bytes();
+        // flip the first bit of every repetition group, and then a few more
+        for i in 0..RAW_LEN * 8 {
+            bytes[i * REPETITION / 8] ^= 0x80 >> (i * REPETITION % 8);
+        }
+        bytes[100] ^= 0x0F;
+        assert_eq!(Header::parse(&bytes), Ok(sample()));
+    }
+
+    #[test]
+    fn error_detection() {
+        assert_eq!(Header::parse(&[0; 10]), Err(HeaderError::Truncated));
+        assert_eq!(Header::parse(&[0; ENCODED_LEN]), Err(HeaderError::BadMagic));
+
+        // destroy the encoded parameter field entirely
+        let mut bytes = sample().to_bytes();
+        for b in &mut bytes[6 * REPETITION..10 * REPETITION] {
+            *b = !*b;
+        }
+        assert_eq!(Header::parse(&bytes), Err(HeaderError::Corrupt));
+    }
+
+    #[test]
+    fn version_1() {
+        let mut raw = MAGIC.to_vec();
+        raw.extend_from_slice(&[1, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 9, 0xAB, 0xCD, 0xEF, 0x01]);
+        let crc = crc32(&raw);
+        raw.extend_from_slice(&crc.to_be_bytes());
+        let bytes = multiple::encode(&raw, REPETITION);
+        assert_eq!(encoded_len(&bytes), Ok(182));
+        let header = Header::parse(&bytes).unwrap();
+        assert_eq!(header, Header {
+            codec: 3,
+            parameter: 4,
+            length: 9,
+            checksum: 0xABCD_EF01,
+            interleaver: 0,
+            depth: 0,
+            data_checksum: None,
+        });
+
+        raw[4] = 9;
+        let bytes = multiple::encode(&raw, REPETITION);
+        assert_eq!(Header::parse(&bytes), Err(HeaderError::UnsupportedVersion(9)));
+    }
+
+    #[test]
+    fn version_2() {
+        // a header without the checksum of the data is written back as it was read
+        let header = Header { data_checksum: None, ..sample() };
+        let bytes = header.to_bytes();
+        assert_eq!(encoded_len(&bytes), Ok(RAW_LEN_V2 * REPETITION));
+        assert_eq!(Header::parse(&bytes), Ok(header.clone()));
+        assert_eq!(header.trailer_len(), TRAILER_LEN_V2);
+    }
+
+    #[test]
+    fn trailer() {
+        let trailer = Trailer { length: 42, checksum: 0x1234_5678, data_checksum: Some(7) };
+        let mut bytes = trailer.to_bytes();
+        assert_eq!(bytes.len(), TRAILER_LEN);
+        bytes[30] ^= 0x81;
+        assert_eq!(Trailer::parse(&bytes), Ok(trailer));
+        assert_eq!(Trailer::parse(&sample().to_bytes()[..TRAILER_LEN]), Err(HeaderError::MissingTrailer));
+        assert_eq!(Trailer::parse(&bytes[1..]), Err(HeaderError::MissingTrailer));
+
+        let trailer = Trailer { length: 42, checksum: 0x1234_5678, data_checksum: None };
+        let bytes = trailer.to_bytes();
+        assert_eq!(bytes.len(), TRAILER_LEN_V2);
+        assert_eq!(Trailer::parse(&bytes), Ok(trailer));
+    }
+}
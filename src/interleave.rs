@@ -0,0 +1,444 @@
+use std::cmp;
+use std::ops::Range;
+
+use codec::{self, Capability, Codec, DecodeError, DecodeOutput, Report, Unit};
+use rng::Rng;
+use soft;
+
+// Every code here corrects a few errors in each codeword, so a burst of errors
+// that lands in one codeword is fatal even if the rest are clean. Interleaving
+// shuffles the encoded units (bits, or bytes for codes that correct bytes) so
+// that neighbouring units on disk come from different codewords.
+//
+// The encoded data is split into frames of `depth` codewords. A block
+// interleaver writes the codewords of a frame as rows and reads them out by
+// columns, so any burst of up to `depth` units hits `depth` different
+// codewords. A random interleaver shuffles each frame with a fixed seed, which
+// doesn't guarantee that but also doesn't have a regular structure for periodic
+// errors to line up with. If the last frame is short, only its whole codewords
+// are interleaved, and anything after them is left where it is.
+
+/// The largest interleaving depth.
+pub const MAX_DEPTH: usize = 4096;
+/// The most units in a frame of random interleaving, since its shuffle is
+/// held in memory. Block interleaving has no such limit.
+pub const MAX_SHUFFLE: usize = 1 << 22;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Block,
+    Random,
+}
+
+impl Kind {
+    /// The id stored in the header. Never reuse an id.
+    pub fn id(self) -> u8 {
+        match self {
+            Kind::Block => 1,
+            Kind::Random => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Kind> {
+        match id {
+            1 => Some(Kind::Block),
+            2 => Some(Kind::Random),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Block => "block",
+            Kind::Random => "random",
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<Kind> {
+        [Kind::Block, Kind::Random].iter().cloned().find(|kind| kind.name() == name)
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// The shuffle of a frame of `len` units. It depends only on the length, so
+// that it can be recomputed when decoding.
+fn shuffle(len: usize) -> Vec<usize> {
+    let mut rng = Rng::new(len as u64);
+    let mut permutation: Vec<usize> = (0..len).collect();
+    for i in (1..len).rev() {
+        permutation.swap(i, rng.below(i + 1));
+    }
+    permutation
+}
+
+/// Wraps a codec, interleaving its codewords `depth` deep.
+pub struct Interleaved {
+    inner: Box<dyn Codec>,
+    kind: Kind,
+    depth: usize,
+    /// The shuffle of a whole frame, for random interleaving.
+    permutation: Vec<usize>,
+}
+
+// Where every unit of some encoded data goes.
+struct Layout<'a> {
+    interleaved: &'a Interleaved,
+    /// Units in a codeword.
+    width: usize,
+    /// Units in the whole frames.
+    whole: usize,
+    /// Codewords in the last, short frame.
+    rows: usize,
+    /// The shuffle of the last frame, for random interleaving.
+    permutation: Vec<usize>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(interleaved: &'a Interleaved, units: usize) -> Layout<'a> {
+        let width = interleaved.codeword_units();
+        let frame = width * interleaved.depth;
+        let whole = units / frame * frame;
+        let rows = (units - whole) / width;
+        let permutation = match interleaved.kind {
+            Kind::Random if rows > 0 => shuffle(rows * width),
+            _ => Vec::new(),
+        };
+        Layout { interleaved, width, whole, rows, permutation }
+    }
+
+    /// The position of the unit at `index` of the code once it is interleaved.
+    fn position(&self, index: usize) -> usize {
+        let (start, rows, permutation) = if index < self.whole {
+            let frame = self.width * self.interleaved.depth;
+            (index / frame * frame, self.interleaved.depth, &self.interleaved.permutation)
+        } else if index < self.whole + self.rows * self.width {
+            (self.whole, self.rows, &self.permutation)
+        } else {
+            return index;
+        };
+        let offset = index - start;
+        start + match self.interleaved.kind {
+            Kind::Block => (offset % self.width) * rows + offset / self.width,
+            Kind::Random => permutation[offset],
+        }
+    }
+}
+
+impl Interleaved {
+    /// # Panics
+    ///
+    /// Panics if `depth` is not between 1 and `MAX_DEPTH`, or if random
+    /// interleaving would shuffle more than `MAX_SHUFFLE` units at a time.
+    pub fn new(inner: Box<dyn Codec>, kind: Kind, depth: usize) -> Interleaved {
+        assert!((1..=MAX_DEPTH).contains(&depth), "the interleaving depth must be between 1 and {}", MAX_DEPTH);
+        assert!(kind != Kind::Random || inner.correction_capability().block * depth <= MAX_SHUFFLE,
+            "random interleaving can shuffle at most {} units at a time", MAX_SHUFFLE);
+        let mut interleaved = Interleaved { inner, kind, depth, permutation: Vec::new() };
+        if kind == Kind::Random {
+            interleaved.permutation = shuffle(interleaved.codeword_units() * depth);
+        }
+        interleaved
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Bits in each interleaved unit.
+    fn unit_bits(&self) -> usize {
+        match self.inner.correction_capability().unit {
+            Unit::Bit => 1,
+            Unit::Byte => 8,
+        }
+    }
+
+    fn codeword_units(&self) -> usize {
+        self.inner.correction_capability().block
+    }
+
+    // Moves every unit to its interleaved position, or back again.
+    fn permute(&self, code: &[u8], forward: bool) -> Vec<u8> {
+        let mut output = vec![0; code.len()];
+        if self.unit_bits() == 8 {
+            let layout = Layout::new(self, code.len());
+            for i in 0..code.len() {
+                let j = layout.position(i);
+                if forward {
+                    output[j] = code[i];
+                } else {
+                    output[i] = code[j];
+                }
+            }
+        } else {
+            let layout = Layout::new(self, code.len() * 8);
+            let bit = |bytes: &[u8], i: usize| bytes[i / 8] & (0x80 >> (i % 8)) != 0;
+            for i in 0..code.len() * 8 {
+                let j = layout.position(i);
+                let (from, to) = if forward { (i, j) } else { (j, i) };
+                if bit(code, from) {
+                    output[to / 8] |= 0x80 >> (to % 8);
+                }
+            }
+        }
+        output
+    }
+
+    // Moves every soft bit back to where it was before interleaving.
+    fn deinterleave_soft(&self, soft: &[i8]) -> Vec<i8> {
+        let unit = self.unit_bits();
+        let units = soft.len() / unit;
+        let layout = Layout::new(self, units);
+        let mut output = soft.to_vec();
+        for i in 0..units {
+            let j = layout.position(i);
+            output[i * unit..(i + 1) * unit].copy_from_slice(&soft[j * unit..(j + 1) * unit]);
+        }
+        output
+    }
+
+    // Moves erased bytes back to where they were before interleaving, as ranges.
+    fn deinterleave_erasures(&self, code_len: usize, erasures: &[Range<usize>]) -> Vec<Range<usize>> {
+        let mut erased = vec![false; code_len];
+        for range in erasures {
+            for e in &mut erased[cmp::min(range.start, code_len)..cmp::min(range.end, code_len)] {
+                *e = true;
+            }
+        }
+        let layout = Layout::new(self, code_len);
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for i in (0..code_len).filter(|&i| erased[layout.position(i)]) {
+            match ranges.last_mut() {
+                Some(range) if range.end == i => range.end += 1,
+                _ => ranges.push(i..i + 1),
+            }
+        }
+        ranges
+    }
+
+    // Moves the locations in a report on the deinterleaved code to where those
+    // bits are in the interleaved code.
+    fn locate(&self, report: &mut Report, code_len: usize) {
+        let unit = self.unit_bits();
+        let layout = Layout::new(self, code_len * 8 / unit);
+        let position = |bit: usize| layout.position(bit / unit) * unit + bit % unit;
+        for bit in &mut report.corrected_bits {
+            *bit = position(*bit);
+        }
+        report.corrected_bits.sort_unstable();
+        for location in &mut report.uncorrectable {
+            location.bit = position(location.bit);
+        }
+    }
+
+    fn finish(&self, result: Result<DecodeOutput, DecodeError>, code_len: usize) -> Result<DecodeOutput, DecodeError> {
+        match result {
+            Ok(mut output) => {
+                self.locate(&mut output.report, code_len);
+                Ok(output)
+            }
+            Err(DecodeError::Uncorrectable { mut output, .. }) => {
+                self.locate(&mut output.report, code_len);
+                codec::decode_result(output.data, output.report)
+            }
+        }
+    }
+
+    // The number of inner chunks in a chunk, so that it holds whole frames.
+    fn chunk_factor(&self) -> usize {
+        let codewords = self.inner.encoded_chunk_len() * 8 / self.unit_bits() / self.codeword_units();
+        self.depth / gcd(codewords, self.depth)
+    }
+}
+
+impl Codec for Interleaved {
+    fn name(&self) -> &'static str { self.inner.name() }
+
+    fn parameters(&self) -> Vec<usize> { self.inner.parameters() }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { self.permute(&self.inner.encode(data), true) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> {
+        let result = self.inner.decode(&self.permute(code, false));
+        self.finish(result, code.len())
+    }
+
+    fn decode_soft(&self, soft: &[i8]) -> Result<DecodeOutput, DecodeError> {
+        let result = self.inner.decode_soft(&self.deinterleave_soft(soft));
+        self.finish(result, soft.len() / 8)
+    }
+
+    fn decode_with_erasures(&self, code: &[u8], erasures: &[Range<usize>]) -> Result<DecodeOutput, DecodeError> {
+        if self.unit_bits() != 8 {
+            return self.decode_soft(&soft::with_erasures(code, erasures));
+        }
+        let erasures = self.deinterleave_erasures(code.len(), erasures);
+        let result = self.inner.decode_with_erasures(&self.permute(code, false), &erasures);
+        self.finish(result, code.len())
+    }
+
+    fn rate(&self) -> f64 { self.inner.rate() }
+
+    fn correction_capability(&self) -> Capability { self.inner.correction_capability() }
+
+    fn chunk_len(&self) -> usize { self.inner.chunk_len() * self.chunk_factor() }
+
+    fn encoded_chunk_len(&self) -> usize { self.inner.encoded_chunk_len() * self.chunk_factor() }
+
+    fn encode_chunk(&self, data: &[u8]) -> Vec<u8> { self.permute(&self.inner.encode_chunk(data), true) }
+
+    fn decode_chunk(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> {
+        let result = self.inner.decode_chunk(&self.permute(code, false));
+        self.finish(result, code.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::build;
+    use soft;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 % 256) as u8).collect()
+    }
+
+    fn interleaved(name: &str, n: usize, kind: Kind, depth: usize) -> Interleaved {
+        Interleaved::new(build(name, &[n]).unwrap(), kind, depth)
+    }
+
+    #[test]
+    fn inverse() {
+        for &kind in &[Kind::Block, Kind::Random] {
+            for &(name, n) in &[("multiple", 3), ("hamming", 3), ("reed-solomon", 8), ("extended-hamming", 4)] {
+                for &depth in &[1, 2, 7, 64] {
+                    let codec = interleaved(name, n, kind, depth);
+                    for &len in &[0, 1, 100, 3000] {
+                        let test = sample(len);
+                        let code = codec.encode(&test);
+                        assert_eq!(code.len(), build(name, &[n]).unwrap().encode(&test).len());
+                        let mut decoded = codec.decode(&code).unwrap().data;
+                        decoded.truncate(len);
+                        assert_eq!(decoded, test, "{} {:?} {}", name, kind, depth);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn block_layout() {
+        // two codewords of seven bits, abcdefg and hijklmn, become ahbicjdkelfmgn
+        let codec = interleaved("hamming", 3, Kind::Block, 2);
+        let layout = Layout::new(&codec, 14);
+        let positions: Vec<usize> = (0..14).map(|i| layout.position(i)).collect();
+        assert_eq!(positions, vec![0, 2, 4, 6, 8, 10, 12, 1, 3, 5, 7, 9, 11, 13]);
+        // a short last frame of one codeword, and some padding
+        let layout = Layout::new(&codec, 25);
+        assert_eq!(layout.position(15), 15);
+        assert_eq!(layout.position(24), 24);
+    }
+
+    #[test]
+    fn bursts() {
+        let test = sample(5000);
+        for &depth in &[8, 16, 100] {
+            let codec = interleaved("hamming", 4, Kind::Block, depth);
+            let mut code = codec.encode(&test);
+            // a burst of `depth` bits, spanning a byte boundary
+            for bit in 1003..1003 + depth {
+                code[bit / 8] ^= 0x80 >> (bit % 8);
+            }
+            let output = codec.decode(&code).unwrap();
+            assert_eq!(output.data[..test.len()], test[..]);
+            let expected: Vec<usize> = (1003..1003 + depth).collect();
+            assert_eq!(output.report.corrected_bits, expected);
+        }
+
+        // without interleaving the same burst is fatal
+        let plain = build("hamming", &[4]).unwrap();
+        let mut code = plain.encode(&test);
+        code[125] ^= 0xFF;
+        assert_ne!(plain.decode(&code).unwrap().data[..test.len()], test[..]);
+
+        // Reed-Solomon is interleaved by bytes
+        let codec = interleaved("reed-solomon", 2, Kind::Block, 16);
+        let mut code = codec.encode(&test);
+        for b in &mut code[500..516] {
+            *b ^= 0xA5;
+        }
+        assert_eq!(codec.decode(&code).unwrap().data, test);
+    }
+
+    #[test]
+    fn random() {
+        let codec = interleaved("extended-hamming", 3, Kind::Random, 32);
+        let test = sample(1000);
+        let mut code = codec.encode(&test);
+        // the shuffle is different from a block interleaver's, but still a permutation
+        assert_ne!(code, interleaved("extended-hamming", 3, Kind::Block, 32).encode(&test));
+        let mut sorted = codec.permutation.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..256).collect::<Vec<_>>());
+
+        // a short burst is very likely to be spread out, and with this seed it is
+        code[40] ^= 0x3F;
+        let output = codec.decode(&code).unwrap();
+        assert_eq!(output.data, test);
+        assert_eq!(output.report.corrected_bits, (322..328).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn soft_decoding() {
+        let test = sample(3000);
+        for &(name, n, kind) in &[("hamming", 3, Kind::Block), ("multiple", 3, Kind::Random), ("reed-solomon", 4, Kind::Block)] {
+            let codec = interleaved(name, n, kind, 12);
+            let mut code = codec.encode(&test);
+            for b in &mut code[100..103] {
+                *b ^= 0x5A;
+            }
+            assert_eq!(codec.decode_soft(&soft::from_hard(&code)), codec.decode(&code), "{}", name);
+        }
+    }
+
+    #[test]
+    fn erasures() {
+        let test = sample(5000);
+        // a run of unreadable bytes is spread across the codewords, four bytes each
+        let codec = interleaved("reed-solomon", 4, Kind::Block, 16);
+        let mut code = codec.encode(&test);
+        for b in &mut code[1000..1064] {
+            *b = 0;
+        }
+        assert!(codec.decode(&code).is_err());
+        let burst = 1000..1064;
+        assert_eq!(codec.decode_with_erasures(&code, &[burst]).unwrap().data, test);
+
+        // and for bit codes two bits each
+        let codec = interleaved("hamming", 3, Kind::Block, 64);
+        let mut code = codec.encode(&test);
+        for b in &mut code[200..216] {
+            *b = 0;
+        }
+        let burst = 200..216;
+        let output = codec.decode_with_erasures(&code, &[burst]);
+        assert_eq!(output.unwrap().data, test);
+    }
+
+    #[test]
+    fn chunks() {
+        let codec = interleaved("hamming", 3, Kind::Block, 10);
+        let len = codec.chunk_len();
+        let test = sample(len * 2 + 77);
+        let mut streamed = codec.encode_chunk(&test[..len * 2]);
+        assert_eq!(streamed.len(), codec.encoded_chunk_len() * 2);
+        streamed.extend(codec.encode(&test[len * 2..]));
+        assert_eq!(streamed, codec.encode(&test));
+    }
+}
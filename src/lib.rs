@@ -0,0 +1,452 @@
+//! Error correcting codes for whole files.
+//!
+//! `protect` expands data with an error correcting code and prefixes it with a
+//! header describing the code, and `recover` reverses it, correcting whatever
+//! errors it can along the way:
+//!
+//! ```
+//! use eccfile::{protect, recover, Config};
+//!
+//! let config = Config::new("hamming", &[3]).unwrap();
+//! let mut encoded = protect(b"some important data", &config);
+//! encoded[200] ^= 0x10;
+//! assert_eq!(recover(&encoded).unwrap().data, b"some important data");
+//! ```
+//!
+//! Large files can be encoded and decoded a chunk at a time with
+//! `EncodeWriter` and `DecodeReader`, which write and read the same format, and
+//! `stream::scrub` corrects the errors in encoded data in place.
+//!
+//! The codecs can also be used on their own through the `Codec` trait.
+
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+pub mod bch;
+pub mod ber;
+// Kept as it was copied from the standard library.
+#[allow(deprecated, dead_code, mismatched_lifetime_syntaxes, clippy::all)]
+pub mod bitvec;
+pub mod channel;
+pub mod codec;
+pub mod convolutional;
+pub mod ddrescue;
+pub mod hamming;
+pub mod header;
+pub mod interleave;
+pub mod lt;
+pub mod multiple;
+pub mod raptorq;
+pub mod reed_solomon;
+pub mod shard;
+pub mod soft;
+pub mod stream;
+
+mod bits;
+mod crc;
+mod gf256;
+mod gf2m;
+mod parallel;
+mod rfc6330;
+mod rng;
+
+pub use bitvec::BitVec;
+pub use codec::{Codec, DecodeError, DecodeOutput, Report};
+pub use header::{Header, HeaderError};
+pub use interleave::Interleaved;
+pub use stream::{DecodeReader, EncodeWriter};
+
+/// The code `protect` should use.
+pub struct Config {
+    codec: Box<dyn Codec>,
+    id: u8,
+    interleave: Option<(interleave::Kind, usize)>,
+    threads: usize,
+}
+
+impl Config {
+    /// Chooses a codec from the registry in `codec::CODECS`.
+    pub fn new(name: &str, parameters: &[usize]) -> Result<Config, Error> {
+        let entry = match codec::by_name(name) {
+            Some(entry) => entry,
+            None => return Err(Error::UnknownCodec(name.to_string())),
+        };
+        let codec = (entry.build)(parameters).map_err(Error::Parameters)?;
+        Ok(Config { codec, id: entry.id, interleave: None, threads: 1 })
+    }
+
+    /// Interleaves the codewords `depth` deep, so that bursts of errors are
+    /// spread across several of them.
+    pub fn interleave(mut self, kind: interleave::Kind, depth: usize) -> Result<Config, Error> {
+        if self.interleave.is_some() {
+            return Err(Error::Parameters("the codec is already interleaved".to_string()));
+        }
+        if !(1..=interleave::MAX_DEPTH).contains(&depth) {
+            return Err(Error::Parameters(format!("the interleaving depth must be between 1 and {}, not {}",
+                interleave::MAX_DEPTH, depth)));
+        }
+        // the shuffle is checked before it is made, since a damaged header could ask for gigabytes
+        let units = self.codec.correction_capability().block.saturating_mul(depth);
+        if kind == interleave::Kind::Random && units > interleave::MAX_SHUFFLE {
+            return Err(Error::Parameters(format!("random interleaving can shuffle at most {} units at a time, \
+                not {} codewords of {}", interleave::MAX_SHUFFLE, depth, self.codec.correction_capability().block)));
+        }
+        self.codec = Box::new(Interleaved::new(self.codec, kind, depth));
+        self.interleave = Some((kind, depth));
+        Ok(self)
+    }
+
+    /// The config a header says the payload was encoded with.
+    pub fn from_header(header: &Header) -> Result<Config, Error> {
+        let entry = codec::by_id(header.codec).expect("the header only accepts registered codecs");
+        let mut config = Config {
+            codec: (entry.build)(&(entry.unpack)(header.parameter)).map_err(Error::Parameters)?,
+            id: entry.id,
+            interleave: None,
+            threads: 1,
+        };
+        if let Some(kind) = interleave::Kind::from_id(header.interleaver) {
+            config = config.interleave(kind, header.depth as usize)?;
+        }
+        Ok(config)
+    }
+
+    /// Encodes whole chunks on up to `threads` threads (at least one). The
+    /// output is the same whatever the number of threads.
+    pub fn threads(mut self, threads: usize) -> Config {
+        self.threads = cmp::max(1, threads);
+        self
+    }
+
+    pub fn codec(&self) -> &dyn Codec {
+        self.codec.as_ref()
+    }
+
+    /// How the codewords are interleaved, if they are.
+    pub fn interleaving(&self) -> Option<(interleave::Kind, usize)> {
+        self.interleave
+    }
+
+    fn header(&self, length: u64, checksum: u32, data_checksum: u32) -> Header {
+        let (interleaver, depth) = match self.interleave {
+            Some((kind, depth)) => (kind.id(), depth as u32),
+            None => (0, 0),
+        };
+        let entry = codec::by_id(self.id).expect("configs only use registered codecs");
+        Header {
+            codec: self.id,
+            parameter: (entry.pack)(&self.codec.parameters()),
+            length,
+            checksum,
+            interleaver,
+            depth,
+            data_checksum: Some(data_checksum),
+        }
+    }
+}
+
+impl Default for Config {
+    /// Hamming(7,4)
+    fn default() -> Config {
+        Config::new("hamming", &[3]).expect("hamming is always registered")
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No codec is registered under this name.
+    UnknownCodec(String),
+    /// The parameters aren't valid for the codec.
+    Parameters(String),
+    /// The header of the encoded data couldn't be read.
+    Header(HeaderError),
+    /// The data had errors that couldn't be corrected.
+    Decode(DecodeError),
+    /// No errors were found that couldn't be corrected, but the decoded data
+    /// doesn't match the checksum of the original, so some were corrected
+    /// wrongly. The output holds the data as it was decoded.
+    Miscorrected(DecodeOutput),
+    /// Reading or writing a stream failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownCodec(ref name) => write!(f, "there is no codec called '{}'", name),
+            Error::Parameters(ref err) => write!(f, "invalid codec parameters: {}", err),
+            Error::Header(HeaderError::MissingTrailer) => write!(f, "{}", HeaderError::MissingTrailer),
+            Error::Header(ref err) => write!(f, "could not read the header: {}", err),
+            Error::Decode(ref err) => write!(f, "could not decode: {}", err),
+            Error::Miscorrected(_) => write!(f, "the decoded data doesn't match its checksum"),
+            Error::Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<HeaderError> for Error {
+    fn from(err: HeaderError) -> Error {
+        Error::Header(err)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Error {
+        Error::Decode(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// What the header of some encoded data says about it.
+pub struct Info {
+    /// For streamed data, the length and checksums are filled in from the trailer.
+    pub header: Header,
+    /// The codec the data was encoded with.
+    pub codec: Box<dyn Codec>,
+    /// Whether the encoded data has changed since it was written. Errors might
+    /// still be correctable.
+    pub damaged: bool,
+}
+
+/// Encodes `data` and prefixes it with a header, so that `recover` can decode it
+/// without being told how it was encoded.
+pub fn protect(data: &[u8], config: &Config) -> Vec<u8> {
+    let payload = if config.threads > 1 {
+        // encoding the whole chunks separately gives the same output
+        let whole = data.len() / config.codec.chunk_len() * config.codec.chunk_len();
+        let mut payload = parallel::encode_chunks(config.codec(), &data[..whole], config.threads);
+        payload.extend_from_slice(&config.codec.encode(&data[whole..]));
+        payload
+    } else {
+        config.codec.encode(data)
+    };
+
+    let header = config.header(data.len() as u64, crc::crc32(&payload), crc::crc32(data));
+    let mut output = header.to_bytes();
+    output.extend_from_slice(&payload);
+    output
+}
+
+// Reads the header and the trailer if there is one, and returns the payload between them.
+fn open(encoded: &[u8]) -> Result<(Info, &[u8]), Error> {
+    let mut header = Header::parse(encoded)?;
+    let start = header::encoded_len(encoded)?;
+    let mut end = encoded.len();
+    if header.is_streamed() {
+        if end < start + header.trailer_len() {
+            return Err(Error::Header(HeaderError::MissingTrailer));
+        }
+        end -= header.trailer_len();
+        let trailer = header::Trailer::parse(&encoded[end..])?;
+        header.complete(&trailer);
+    }
+    let payload = &encoded[start..end];
+    let codec = Config::from_header(&header)?.codec;
+    let damaged = crc::crc32(payload) != header.checksum;
+    Ok((Info { header, codec, damaged }, payload))
+}
+
+/// Reads the header of data produced by `protect` or an `EncodeWriter`.
+pub fn inspect(encoded: &[u8]) -> Result<Info, Error> {
+    open(encoded).map(|(info, _)| info)
+}
+
+// The offset in bits of the payload in `encoded`, which has a valid header.
+fn payload_bits(encoded: &[u8]) -> usize {
+    header::encoded_len(encoded).expect("the header has already been read") * 8
+}
+
+/// Decodes data produced by `protect` or an `EncodeWriter`, correcting errors.
+/// The locations of any errors, corrected or not, are relative to the start of `encoded`.
+pub fn recover(encoded: &[u8]) -> Result<DecodeOutput, Error> {
+    let (info, payload) = open(encoded)?;
+    finish_recovery(info.codec.decode(payload), &info.header, payload_bits(encoded))
+}
+
+/// Like `recover`, but for encoded data received as a soft bit for each bit
+/// (see `soft`). The header is read from the hard decisions, and the payload is
+/// decoded from the soft bits by codecs that can make use of them.
+pub fn recover_soft(soft: &[i8]) -> Result<DecodeOutput, Error> {
+    let encoded = soft::hard_decisions(soft);
+    let (info, payload) = open(&encoded)?;
+    let start = payload_bits(&encoded);
+    let end = cmp::min(soft.len(), start + payload.len() * 8);
+    finish_recovery(info.codec.decode_soft(&soft[start..end]), &info.header, start)
+}
+
+/// Like `recover`, but with the byte ranges of `encoded` known to be unreadable,
+/// sorted and not overlapping, so that codecs that can make use of erasures
+/// don't have to find those errors for themselves. The header has to be readable.
+pub fn recover_with_erasures(encoded: &[u8], erasures: &[Range<usize>]) -> Result<DecodeOutput, Error> {
+    let (info, payload) = open(encoded)?;
+    let start = payload_bits(encoded) / 8;
+    let end = start + payload.len();
+    let erasures: Vec<Range<usize>> = erasures.iter()
+        .filter(|range| range.start < end && range.end > start)
+        .map(|range| cmp::max(range.start, start) - start..cmp::min(range.end, end) - start)
+        .collect();
+    finish_recovery(info.codec.decode_with_erasures(payload, &erasures), &info.header, start * 8)
+}
+
+// Cuts the output of decoding a payload at `start` bits down to the length of
+// the data, moves the locations in the report to match, and checks the data
+// against its checksum.
+fn finish_recovery(result: Result<DecodeOutput, DecodeError>, header: &Header, start: usize) -> Result<DecodeOutput, Error> {
+    let length = header.length as usize;
+    match result {
+        Ok(mut output) => {
+            output.data.truncate(length);
+            output.report.offset(start);
+            if header.data_checksum.is_some_and(|checksum| crc::crc32(&output.data) != checksum) {
+                return Err(Error::Miscorrected(output));
+            }
+            Ok(output)
+        }
+        Err(DecodeError::Uncorrectable { mut locations, mut output }) => {
+            for location in &mut locations {
+                location.bit += start;
+            }
+            output.data.truncate(length);
+            output.report.offset(start);
+            Err(Error::Decode(DecodeError::Uncorrectable { locations, output }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse() {
+        let test: Vec<u8> = (0..1000).map(|i| (i * 7 % 256) as u8).collect();
+        for &(name, n) in &[("multiple", 3), ("hamming", 5), ("reed-solomon", 16)] {
+            let config = Config::new(name, &[n]).unwrap();
+            let encoded = protect(&test, &config);
+            let info = inspect(&encoded).unwrap();
+            assert_eq!(info.codec.name(), name);
+            assert_eq!(info.header.length, 1000);
+            assert!(!info.damaged);
+            assert_eq!(recover(&encoded).unwrap().data, test);
+        }
+    }
+
+    #[test]
+    fn error_correction() {
+        let test = b"The quick brown fox jumps over the lazy dog".to_vec();
+        let mut encoded = protect(&test, &Config::default());
+        encoded[header::ENCODED_LEN + 10] ^= 0x01;
+        encoded[20] ^= 0x11;
+        assert!(inspect(&encoded).unwrap().damaged);
+        let output = recover(&encoded).unwrap();
+        assert_eq!(output.data, test);
+        // errors in the header are corrected separately and not reported
+        assert_eq!(output.report.corrected_bits, vec![(header::ENCODED_LEN + 10) * 8 + 7]);
+    }
+
+    #[test]
+    fn interleaving() {
+        let test: Vec<u8> = (0..10_000).map(|i| (i * 7 % 256) as u8).collect();
+        let config = Config::new("hamming", &[3]).unwrap().interleave(interleave::Kind::Block, 30).unwrap();
+        let mut encoded = protect(&test, &config);
+        let info = inspect(&encoded).unwrap();
+        assert_eq!(info.header.interleaver, interleave::Kind::Block.id());
+        assert_eq!(info.header.depth, 30);
+        for b in &mut encoded[header::ENCODED_LEN + 1000..header::ENCODED_LEN + 1003] {
+            *b = !*b;
+        }
+        assert_eq!(recover(&encoded).unwrap().data, test);
+
+        assert!(matches!(Config::default().interleave(interleave::Kind::Random, 0), Err(Error::Parameters(_))));
+
+        // a frame too large to shuffle is refused before its shuffle is made
+        let wide = || Config::new("hamming", &[16]).unwrap();
+        assert!(matches!(wide().interleave(interleave::Kind::Random, 4096), Err(Error::Parameters(_))));
+        let mut header = wide().interleave(interleave::Kind::Block, 4096).unwrap().header(0, 0, 0);
+        header.interleaver = interleave::Kind::Random.id();
+        assert!(matches!(Config::from_header(&header), Err(Error::Parameters(_))));
+        assert!(wide().interleave(interleave::Kind::Random, 64).is_ok());
+    }
+
+    #[test]
+    fn soft_decoding() {
+        let test = b"The quick brown fox jumps over the lazy dog".to_vec();
+        let encoded = protect(&test, &Config::new("multiple", &[2]).unwrap());
+        let mut soft = soft::from_hard(&encoded);
+        // every other copy of the data is flipped, but unsure
+        for bit in soft[header::ENCODED_LEN * 8..].iter_mut().step_by(2) {
+            *bit = -bit.signum() * 3;
+        }
+        assert!(recover(&soft::hard_decisions(&soft)).is_err());
+        let output = recover_soft(&soft).unwrap();
+        assert_eq!(output.data, test);
+        assert_eq!(output.report.corrected_bits.len(), test.len() * 8);
+        assert_eq!(output.report.corrected_bits[0], header::ENCODED_LEN * 8);
+    }
+
+    #[test]
+    fn erasures() {
+        let test: Vec<u8> = (0..3000).map(|i| (i * 7 % 256) as u8).collect();
+        let config = Config::new("reed-solomon", &[6]).unwrap();
+        let mut encoded = protect(&test, &config);
+        // six zeroed bytes in the first block, after the header
+        let start = header::ENCODED_LEN + 50;
+        for b in &mut encoded[start..start + 6] {
+            *b = 0;
+        }
+        assert!(recover(&encoded).is_err());
+        let output = recover_with_erasures(&encoded, &[0..10, start..start + 6]).unwrap();
+        assert_eq!(output.data, test);
+        assert!(output.report.corrected_bits.iter().all(|&bit| bit / 8 >= start && bit / 8 < start + 6));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(Config::new("nonsense", &[3]), Err(Error::UnknownCodec(_))));
+        assert!(matches!(Config::new("hamming", &[0]), Err(Error::Parameters(_))));
+        assert!(matches!(recover(b"not encoded"), Err(Error::Header(HeaderError::Truncated))));
+    }
+
+    #[test]
+    fn error_detection() {
+        let test = vec![0, 1, 2, 3];
+        let mut encoded = protect(&test, &Config::new("multiple", &[2]).unwrap());
+        // make the fourth bit of the data a tie
+        encoded[header::ENCODED_LEN] ^= 0x01;
+        match recover(&encoded) {
+            Err(Error::Decode(DecodeError::Uncorrectable { locations, output })) => {
+                assert_eq!(locations.len(), 1);
+                assert_eq!(locations[0].block, 3);
+                assert_eq!(locations[0].bit, header::ENCODED_LEN * 8 + 6);
+                assert_eq!(output.data.len(), 4);
+            }
+            _ => panic!("the error should have been detected"),
+        }
+    }
+
+    #[test]
+    fn miscorrection() {
+        let test = vec![0x5A; 100];
+        let mut encoded = protect(&test, &Config::new("hamming", &[3]).unwrap());
+        // two errors in a block of Hamming(7,4) are taken for a different single error
+        encoded[header::ENCODED_LEN + 10] ^= 0x03;
+        match recover(&encoded) {
+            Err(Error::Miscorrected(output)) => {
+                assert_eq!(output.data.len(), test.len());
+                assert_ne!(output.data, test);
+                assert_eq!(output.report.corrected_bits.len(), 1);
+            }
+            _ => panic!("the miscorrection should have been noticed"),
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+use crc::crc32;
+use rng::Rng;
+
+// LT (Luby Transform) codes are rateless: the data is split into source
+// symbols of a fixed size, and any number of encoded symbols can be made from
+// them, each the xor of a few source symbols chosen at random. A receiver that
+// collects somewhat more encoded symbols than there are source symbols (the
+// fewer source symbols, the larger the share), whichever they are, can recover
+// the data, so this suits links that lose whole packets rather than flipping
+// bits.
+//
+// Each encoded symbol travels in a packet with its id, and the id seeds the
+// choice of its source symbols, so the receiver can repeat it. The number of
+// source symbols, its degree, comes from the robust soliton distribution,
+// which makes sure there are enough symbols of degree 1 to start decoding and
+// enough of high degree to cover every source symbol.
+//
+// Decoding is by peeling: an encoded symbol with only one unknown source
+// symbol left is that symbol, which is then xored out of every other encoded
+// symbol that includes it, possibly leaving more with only one unknown.
+//
+// Large inputs are split into source blocks of up to `MAX_BLOCK_SYMBOLS`
+// symbols, each encoded separately, since peeling needs all of a block's
+// symbols in memory. The last symbol of the data is padded with zeros. Empty
+// data still has one source block, with no symbols, so that there is a packet
+// to say the data is empty.
+
+/// The largest number of source symbols in a source block.
+pub const MAX_BLOCK_SYMBOLS: usize = 4096;
+/// The symbol size used by default.
+pub const DEFAULT_SYMBOL_LEN: usize = 1024;
+
+pub const MAGIC: [u8; 4] = *b"ECCL";
+/// The bytes of a packet that aren't the symbol: the magic, the length of the
+/// data, the symbol size, the source block and the id before the symbol, and a
+/// checksum of the rest after it.
+pub const PACKET_OVERHEAD: usize = 4 + 8 + 2 + 4 + 4 + 4;
+
+// The parameters of the robust soliton distribution.
+const SPIKE_SCALE: f64 = 0.05;
+const FAILURE_PROBABILITY: f64 = 0.5;
+
+/// How some data is split into source blocks and symbols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub length: u64,
+    pub symbol_len: usize,
+}
+
+impl Layout {
+    /// # Panics
+    ///
+    /// Panics if `symbol_len` is 0 or doesn't fit in 16 bits.
+    pub fn new(length: u64, symbol_len: usize) -> Layout {
+        assert!(symbol_len > 0 && symbol_len <= u16::MAX as usize, "the symbol size must be between 1 and 65535");
+        Layout { length, symbol_len }
+    }
+
+    /// The number of source symbols in all.
+    pub fn symbols(&self) -> usize {
+        (self.length as usize).div_ceil(self.symbol_len)
+    }
+
+    pub fn blocks(&self) -> usize {
+        cmp::max(1, self.symbols().div_ceil(MAX_BLOCK_SYMBOLS))
+    }
+
+    /// The number of source symbols in a block.
+    pub fn block_symbols(&self, block: usize) -> usize {
+        cmp::min(MAX_BLOCK_SYMBOLS, self.symbols() - block * MAX_BLOCK_SYMBOLS)
+    }
+
+    // The range of the data a block covers.
+    fn block_bytes(&self, block: usize) -> (usize, usize) {
+        let start = block * MAX_BLOCK_SYMBOLS * self.symbol_len;
+        (start, cmp::min(self.length as usize, start + self.block_symbols(block) * self.symbol_len))
+    }
+}
+
+// The cumulative robust soliton distribution for `k` source symbols.
+fn distribution(k: usize) -> Vec<f64> {
+    let kf = k as f64;
+    let spike = SPIKE_SCALE * (kf / FAILURE_PROBABILITY).ln() * kf.sqrt();
+    let pivot = cmp::max(1, cmp::min(k, (kf / spike).floor() as usize));
+    let mut weights: Vec<f64> = (1..=k).map(|d| {
+        let ideal = if d == 1 { 1.0 / kf } else { 1.0 / (d * (d - 1)) as f64 };
+        let robust = match d.cmp(&pivot) {
+            cmp::Ordering::Less => spike / (d as f64 * kf),
+            cmp::Ordering::Equal => spike * (spike / FAILURE_PROBABILITY).ln().max(0.0) / kf,
+            cmp::Ordering::Greater => 0.0,
+        };
+        ideal + robust
+    }).collect();
+    let total: f64 = weights.iter().sum();
+    let mut sum = 0.0;
+    for weight in &mut weights {
+        sum += *weight / total;
+        *weight = sum;
+    }
+    weights
+}
+
+// Scrambles a seed with the finaliser of SplitMix64: consecutive ids given to
+// `Rng::new` as they are pick related symbols, which takes far more of them to
+// decode.
+fn mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// The source symbols the encoded symbol `id` of a block of `k` symbols is made of.
+fn neighbours(block: usize, id: u32, k: usize, distribution: &[f64]) -> Vec<usize> {
+    let mut rng = Rng::new(mix((block as u64) << 32 | u64::from(id)));
+    let x = rng.next_f64();
+    let degree = cmp::min(k, distribution.partition_point(|&p| p < x) + 1);
+    let mut picked: Vec<usize> = Vec::with_capacity(degree);
+    while picked.len() < degree {
+        let symbol = rng.below(k);
+        if !picked.contains(&symbol) {
+            picked.push(symbol);
+        }
+    }
+    picked
+}
+
+fn xor(into: &mut [u8], from: &[u8]) {
+    for (a, b) in into.iter_mut().zip(from) {
+        *a ^= b;
+    }
+}
+
+/// Makes encoded symbols for some data.
+pub struct Encoder<'a> {
+    data: &'a [u8],
+    layout: Layout,
+    distributions: Vec<Vec<f64>>,
+}
+
+impl<'a> Encoder<'a> {
+    /// # Panics
+    ///
+    /// Panics if `symbol_len` is 0 or doesn't fit in 16 bits.
+    pub fn new(data: &'a [u8], symbol_len: usize) -> Encoder<'a> {
+        let layout = Layout::new(data.len() as u64, symbol_len);
+        let distributions = (0..layout.blocks()).map(|block| distribution(layout.block_symbols(block))).collect();
+        Encoder { data, layout, distributions }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    // The source symbol `i` of a block, padded with zeros.
+    fn source(&self, block: usize, i: usize) -> Vec<u8> {
+        let (start, end) = self.layout.block_bytes(block);
+        let from = start + i * self.layout.symbol_len;
+        let mut symbol = self.data[from..cmp::min(end, from + self.layout.symbol_len)].to_vec();
+        symbol.resize(self.layout.symbol_len, 0);
+        symbol
+    }
+
+    /// The packet holding the encoded symbol `id` of a source block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such block.
+    pub fn packet(&self, block: usize, id: u32) -> Vec<u8> {
+        assert!(block < self.layout.blocks(), "there is no source block {}", block);
+        let k = self.layout.block_symbols(block);
+        let mut symbol = vec![0; self.layout.symbol_len];
+        for i in neighbours(block, id, k, &self.distributions[block]) {
+            xor(&mut symbol, &self.source(block, i));
+        }
+        Packet { layout: self.layout, block, id, symbol }.to_bytes()
+    }
+
+    /// Packets for every source block with `1 + overhead` times as many encoded
+    /// symbols as it has source symbols, and at least one, taking turns between
+    /// the blocks so that a run of lost packets is shared between them.
+    pub fn packets(&self, overhead: f64) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let counts: Vec<usize> = (0..self.layout.blocks())
+            .map(|block| (self.layout.block_symbols(block) as f64 * (1.0 + overhead)).ceil() as usize)
+            .map(|count| cmp::max(1, count))
+            .collect();
+        let most = counts.iter().cloned().max().unwrap_or(0);
+        (0..most).flat_map(move |id| {
+            counts.iter().enumerate()
+                .filter(|&(_, &count)| id < count)
+                .map(|(block, _)| self.packet(block, id as u32))
+                .collect::<Vec<_>>()
+        })
+    }
+}
+
+/// A packet that can't be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    /// It's shorter than its symbol size says, or too short to say.
+    Truncated,
+    BadMagic,
+    /// Its checksum doesn't match, so it was damaged on the way.
+    Corrupt,
+    /// It belongs to different data from the packets before it.
+    Mismatched,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PacketError::Truncated => write!(f, "the packet is cut short"),
+            PacketError::BadMagic => write!(f, "the input is not an LT packet"),
+            PacketError::Corrupt => write!(f, "the packet is damaged"),
+            PacketError::Mismatched => write!(f, "the packet belongs to different data"),
+        }
+    }
+}
+
+impl error::Error for PacketError {}
+
+/// An encoded symbol and what's needed to decode it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub layout: Layout,
+    pub block: usize,
+    pub id: u32,
+    pub symbol: Vec<u8>,
+}
+
+impl Packet {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_OVERHEAD + self.symbol.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.layout.length.to_be_bytes());
+        bytes.extend_from_slice(&(self.layout.symbol_len as u16).to_be_bytes());
+        bytes.extend_from_slice(&(self.block as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.id.to_be_bytes());
+        bytes.extend_from_slice(&self.symbol);
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        bytes
+    }
+
+    /// The length of the packet at the start of `bytes`, if it has a valid header.
+    pub fn len(bytes: &[u8]) -> Result<usize, PacketError> {
+        if bytes.len() < 14 {
+            return Err(PacketError::Truncated);
+        }
+        if bytes[..4] != MAGIC {
+            return Err(PacketError::BadMagic);
+        }
+        Ok(PACKET_OVERHEAD + u16::from_be_bytes([bytes[12], bytes[13]]) as usize)
+    }
+
+    /// Reads the packet at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Packet, PacketError> {
+        let len = Packet::len(bytes)?;
+        if bytes.len() < len || len == PACKET_OVERHEAD {
+            return Err(PacketError::Truncated);
+        }
+        let crc = u32::from_be_bytes([bytes[len - 4], bytes[len - 3], bytes[len - 2], bytes[len - 1]]);
+        if crc32(&bytes[..len - 4]) != crc {
+            return Err(PacketError::Corrupt);
+        }
+        let be_u32 = |i: usize| u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let mut length = [0; 8];
+        length.copy_from_slice(&bytes[4..12]);
+        let layout = Layout::new(u64::from_be_bytes(length), len - PACKET_OVERHEAD);
+        let block = be_u32(14) as usize;
+        if block >= layout.blocks() {
+            return Err(PacketError::Corrupt);
+        }
+        Ok(Packet { layout, block, id: be_u32(18), symbol: bytes[22..len - 4].to_vec() })
+    }
+}
+
+/// The packets found in some bytes.
+pub struct Scan {
+    pub packets: Vec<Packet>,
+    /// The number of bytes that weren't part of an intact packet.
+    pub skipped: usize,
+}
+
+/// Reads the packets written one after another into `bytes`, as a stream of
+/// them is saved. After a damaged packet, the next one is found by its magic.
+pub fn scan(bytes: &[u8]) -> Scan {
+    let mut packets = Vec::new();
+    let mut skipped = 0;
+    let mut position = 0;
+    while position < bytes.len() {
+        match Packet::parse(&bytes[position..]) {
+            Ok(packet) => {
+                position += PACKET_OVERHEAD + packet.symbol.len();
+                packets.push(packet);
+            }
+            Err(_) => {
+                let next = bytes[position + 1..].windows(MAGIC.len())
+                    .position(|window| window == MAGIC)
+                    .map_or(bytes.len(), |offset| position + 1 + offset);
+                skipped += next - position;
+                position = next;
+            }
+        }
+    }
+    Scan { packets, skipped }
+}
+
+// An encoded symbol some of whose source symbols are still unknown.
+struct Pending {
+    symbol: Vec<u8>,
+    unknown: Vec<usize>,
+}
+
+// Peels one source block.
+struct BlockDecoder {
+    distribution: Vec<f64>,
+    sources: Vec<Option<Vec<u8>>>,
+    known: usize,
+    pending: Vec<Pending>,
+    /// The pending symbols each source symbol is part of.
+    waiting: Vec<Vec<usize>>,
+}
+
+impl BlockDecoder {
+    fn new(k: usize) -> BlockDecoder {
+        BlockDecoder {
+            distribution: distribution(k),
+            sources: vec![None; k],
+            known: 0,
+            pending: Vec::new(),
+            waiting: vec![Vec::new(); k],
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.known == self.sources.len()
+    }
+
+    fn add(&mut self, block: usize, id: u32, mut symbol: Vec<u8>) {
+        if self.is_complete() {
+            return;
+        }
+        let mut unknown = Vec::new();
+        for i in neighbours(block, id, self.sources.len(), &self.distribution) {
+            match self.sources[i] {
+                Some(ref source) => xor(&mut symbol, source),
+                None => unknown.push(i),
+            }
+        }
+        match unknown.len() {
+            0 => {}
+            1 => self.resolve(unknown[0], symbol),
+            _ => {
+                let index = self.pending.len();
+                for &i in &unknown {
+                    self.waiting[i].push(index);
+                }
+                self.pending.push(Pending { symbol, unknown });
+            }
+        }
+    }
+
+    // Learns a source symbol and peels whatever that releases.
+    fn resolve(&mut self, i: usize, symbol: Vec<u8>) {
+        let mut ripple = vec![(i, symbol)];
+        while let Some((i, symbol)) = ripple.pop() {
+            if self.sources[i].is_some() {
+                continue;
+            }
+            for index in std::mem::take(&mut self.waiting[i]) {
+                let pending = &mut self.pending[index];
+                if let Some(position) = pending.unknown.iter().position(|&j| j == i) {
+                    pending.unknown.swap_remove(position);
+                    xor(&mut pending.symbol, &symbol);
+                    if pending.unknown.len() == 1 {
+                        let released = std::mem::take(&mut pending.symbol);
+                        ripple.push((pending.unknown.pop().expect("one is left"), released));
+                    }
+                }
+            }
+            self.sources[i] = Some(symbol);
+            self.known += 1;
+        }
+    }
+}
+
+/// Collects packets until the data can be recovered.
+#[derive(Default)]
+pub struct Decoder {
+    layout: Option<Layout>,
+    /// The source blocks that packets have been taken for, since the length
+    /// of the data in a packet can't be trusted until they all are.
+    blocks: BTreeMap<usize, BlockDecoder>,
+    /// The number of packets that were used.
+    received: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Takes a packet, ignoring it if its source block has already been decoded.
+    pub fn add(&mut self, packet: Packet) -> Result<(), PacketError> {
+        match self.layout {
+            Some(layout) if layout != packet.layout => return Err(PacketError::Mismatched),
+            Some(_) => {}
+            None => self.layout = Some(packet.layout),
+        }
+        self.received += 1;
+        let layout = packet.layout;
+        self.blocks.entry(packet.block)
+            .or_insert_with(|| BlockDecoder::new(layout.block_symbols(packet.block)))
+            .add(packet.block, packet.id, packet.symbol);
+        Ok(())
+    }
+
+    pub fn layout(&self) -> Option<Layout> {
+        self.layout
+    }
+
+    /// The number of packets taken.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// How many source symbols have been recovered, out of how many.
+    pub fn progress(&self) -> (usize, usize) {
+        let known = self.blocks.values().map(|block| block.known).sum();
+        (known, self.layout.map_or(0, |layout| layout.symbols()))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.layout.is_some_and(|layout| self.blocks.len() == layout.blocks())
+            && self.blocks.values().all(BlockDecoder::is_complete)
+    }
+
+    /// The data, once every source symbol has been recovered.
+    pub fn data(&self) -> Option<Vec<u8>> {
+        let layout = self.layout?;
+        if !self.is_complete() {
+            return None;
+        }
+        let mut data = Vec::with_capacity(layout.symbols() * layout.symbol_len);
+        for block in self.blocks.values() {
+            for source in &block.sources {
+                data.extend_from_slice(source.as_ref().expect("the block is complete"));
+            }
+        }
+        data.truncate(layout.length as usize);
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn degrees() {
+        let distribution = distribution(1000);
+        assert!((distribution[999] - 1.0).abs() < 1e-9);
+        // most encoded symbols have a low degree, but not all
+        assert!(distribution[0] > 0.01 && distribution[0] < 0.2);
+        assert!(distribution[9] > 0.7 && distribution[9] < 0.99);
+        let mean = (0..2000).map(|id| neighbours(0, id, 1000, &distribution).len()).sum::<usize>() as f64 / 2000.0;
+        assert!(mean > 4.0 && mean < 20.0, "mean degree {}", mean);
+    }
+
+    #[test]
+    fn packets() {
+        let test = sample(5000);
+        let encoder = Encoder::new(&test, 100);
+        let bytes = encoder.packet(0, 7);
+        assert_eq!(bytes.len(), PACKET_OVERHEAD + 100);
+        assert_eq!(Packet::len(&bytes), Ok(bytes.len()));
+        let packet = Packet::parse(&bytes).unwrap();
+        assert_eq!((packet.block, packet.id, packet.layout), (0, 7, encoder.layout()));
+
+        let mut damaged = bytes.clone();
+        damaged[30] ^= 1;
+        assert_eq!(Packet::parse(&damaged), Err(PacketError::Corrupt));
+        assert_eq!(Packet::parse(&bytes[..50]), Err(PacketError::Truncated));
+        assert_eq!(Packet::parse(b"not a packet at all"), Err(PacketError::BadMagic));
+
+        let mut stream: Vec<u8> = encoder.packets(0.0).flatten().collect();
+        let second = PACKET_OVERHEAD + 100;
+        stream[second + 1] ^= 1;
+        stream.truncate(stream.len() - 3);
+        let scan = scan(&stream);
+        assert_eq!(scan.packets.len(), 48);
+        assert_eq!(scan.skipped, second + PACKET_OVERHEAD + 97);
+        assert_eq!(scan.packets[1].id, 2);
+    }
+
+    #[test]
+    fn recovery_from_any_packets() {
+        let mut rng = Rng::new(23);
+        for &(len, symbol_len) in &[(1, 16), (1000, 16), (100_000, 64), (MAX_BLOCK_SYMBOLS * 10 + 5, 4)] {
+            let test: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            let encoder = Encoder::new(&test, symbol_len);
+            // lose a third of the packets
+            let mut decoder = Decoder::new();
+            for packet in encoder.packets(1.0) {
+                if rng.below(3) != 0 {
+                    decoder.add(Packet::parse(&packet).unwrap()).unwrap();
+                }
+            }
+            assert!(decoder.is_complete(), "{} bytes in symbols of {}: {:?}", len, symbol_len, decoder.progress());
+            assert_eq!(decoder.data().unwrap(), test);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let encoder = Encoder::new(&[], 16);
+        let packets: Vec<Vec<u8>> = encoder.packets(0.5).collect();
+        assert_eq!(packets.len(), 1);
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.data(), None);
+        decoder.add(Packet::parse(&packets[0]).unwrap()).unwrap();
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.data(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn untrusted_length() {
+        // a packet can claim far more data than could be held, which mustn't be
+        // allocated for before it turns up
+        let length = u64::MAX / 2;
+        let packet = Packet { layout: Layout::new(length, 1), block: 1 << 31, id: 0, symbol: vec![0] };
+        let mut decoder = Decoder::new();
+        decoder.add(Packet::parse(&packet.to_bytes()).unwrap()).unwrap();
+        assert_eq!(decoder.progress(), (0, length as usize));
+        assert!(!decoder.is_complete());
+        assert_eq!(decoder.data(), None);
+    }
+
+    #[test]
+    fn overhead() {
+        // how many packets it takes, in order, to decode 1000 source symbols
+        let test = sample(16_000);
+        let encoder = Encoder::new(&test, 16);
+        let mut decoder = Decoder::new();
+        let mut id = 0;
+        while !decoder.is_complete() {
+            decoder.add(Packet::parse(&encoder.packet(0, id)).unwrap()).unwrap();
+            id += 1;
+        }
+        assert!(id < 1300, "took {} packets", id);
+        assert_eq!(decoder.data().unwrap(), test);
+
+        let other = Encoder::new(&test[1..], 16);
+        assert_eq!(decoder.add(Packet::parse(&other.packet(0, 0)).unwrap()), Err(PacketError::Mismatched));
+    }
+}
@@ -1,57 +1,1563 @@
 extern crate argparse;
+extern crate eccfile;
 
-use std::io::{self, Read, Write};
-use argparse::{ArgumentParser, StoreFalse, Store, StoreTrue};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use argparse::{ArgumentParser, List, Store, StoreConst, StoreOption, StoreTrue};
 
-mod multiple;
-mod hamming;
-mod bitvec;
+use eccfile::{ber, channel, codec, ddrescue, interleave, lt, raptorq, shard, soft, stream, Codec, Config, DecodeError, DecodeReader, EncodeWriter, Error, HeaderError, Report};
+
+// Exit statuses, so that scripts can tell failures apart. Argparse already
+// exits with 2 when the arguments can't be parsed.
+const EXIT_UNRECOVERABLE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
 
 fn main() {
-    let mut multiply = false;
-    let mut hamm = false;
-    let mut encode = true;
+    let mut command = String::new();
+    let mut args: Vec<String> = Vec::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Expands files with an error correcting code, or recovers the originals from them. \
+            Exits with status 1 if the data could not be recovered, 2 if the arguments were invalid or an \
+            input isn't an encoded file, and 3 if there was an error reading or writing; with several files, \
+            the status is that of the first one to fail.");
+        ap.refer(&mut command).required()
+            .add_argument("command", Store,
+            "The command to run: 'encode', 'decode', 'verify' (check encoded files for damage), 'repair' \
+            (correct the errors in encoded files, keeping them encoded), 'info' (describe encoded files), \
+            'simulate' (damage a file as a noisy channel would, to try codes out), 'bench-ber' (measure how \
+            well codes correct errors, to choose between them), 'lt-encode' (encode a file into packets with a \
+            fountain code, for links that lose packets) or 'lt-decode' (recover a file from enough of them). \
+            Use 'COMMAND --help' for its options.");
+        ap.refer(&mut args)
+            .add_argument("arguments", List, "The arguments for the command.");
+        ap.stop_on_first_argument(true);
+        ap.parse_args_or_exit();
+    }
+
+    args.insert(0, format!("eccfile {}", command));
+    let status = match command.as_str() {
+        "encode" => encode_command(args),
+        "decode" => decode_command(args),
+        "verify" => verify_command(args),
+        "repair" => repair_command(args),
+        "info" => info_command(args),
+        "simulate" => simulate_command(args),
+        "bench-ber" => bench_ber_command(args),
+        "lt-encode" => lt_encode_command(args),
+        "lt-decode" => lt_decode_command(args),
+        _ => {
+            eprintln!("There is no command '{}'; use --help to list them", command);
+            EXIT_USAGE
+        }
+    };
+    process::exit(status);
+}
+
+/// Stands for stdin or stdout in place of a path.
+const STDIO: &str = "-";
+/// Added to the names of encoded files.
+const SUFFIX: &str = ".ecc";
+
+const FILES_HELP: &str = "The files to read, or '-' for stdin, which is also the default.";
+const VERBOSE_HELP: &str = "Use this flag to describe the error correction method that was used on stderr.";
+const REPORT_HELP: &str = "Use this option to describe the errors that were found on stderr, either as 'text' or \
+    as 'json'. The report counts the blocks that were corrected and estimates the raw bit error rate, \
+    which shows the storage going bad before errors stop being correctable. A JSON report is all that is \
+    written to stderr, and also says whether the encoded data had been damaged.";
+const FORCE_HELP: &str = "Use this flag to overwrite output files that already exist.";
+const THREADS_HELP: &str = "Use this option to process chunks of each file on this many threads, or 0 for one \
+    per core. The output is the same whatever the number of threads. The default is 1.";
+
+fn encode_command(args: Vec<String>) -> i32 {
+    let mut codec_name = String::new();
+    let mut shortcut = "hamming";
+    let mut verbose = false;
     let mut num = 3;
+    let mut errors: Option<usize> = None;
+    let mut generators = String::new();
+    let mut tail_biting = false;
+    let mut depth = 0;
+    let mut interleaver = "block".to_string();
+    let mut files: Vec<String> = Vec::new();
+    let mut output = String::new();
+    let mut force = false;
+    let mut threads = 1;
+    let mut shards = 0;
+    let mut required = 0;
+    let names: Vec<String> = codec::CODECS.iter()
+        .map(|entry| format!("'{}' ({})", entry.name, entry.description))
+        .collect();
+    let codec_help = format!("Use this option to choose the error correction method by name. The available methods are {}.",
+        names.join(", "));
     {
         let mut ap = ArgumentParser::new();
-        ap.set_description("");
-        ap.refer(&mut encode)
-            .add_option(&["-d", "--decode"], StoreFalse,
-            "Use this flag to decode a file as opposed to encoding it.");
-        ap.refer(&mut multiply)
-            .add_option(&["-m","--multiply"], StoreTrue,
+        ap.set_description("Expands files with an error correcting code. By default encoding 'name' writes 'name.ecc'.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue,
+            "Use this flag to describe the error correction method being used on stderr.");
+        ap.refer(&mut codec_name)
+            .add_option(&["-c","--codec"], Store, &codec_help);
+        ap.refer(&mut shortcut)
+            .add_option(&["-m","--multiply"], StoreConst("multiple"),
             "Use this option to indicate error correction method should be bit multiplication or duplication\
             where the numerical parameter used indicates the number of times each bit is duplicated. Odd numbers\
-            are more efficient than even numbers");
-        ap.refer(&mut hamm)
-            .add_option(&["-h","--hamming"], StoreTrue,
+            are more efficient than even numbers")
+            .add_option(&["-h","--hamming"], StoreConst("hamming"),
             "Use this option to indicate error correction method should be hamming codes. The numerical parameter\
-            indicates the number of parity bits; 3 hamming bits is equivalent to Hamming(7,4).");
+            indicates the number of parity bits; 3 hamming bits is equivalent to Hamming(7,4).")
+            .add_option(&["-e","--extended"], StoreConst("extended-hamming"),
+            "Use this option to indicate error correction method should be extended hamming codes, which have an \
+            extra parity bit in each block so that two errors in a block are detected rather than miscorrected. \
+            The numerical parameter is the same as for hamming codes; 3 is equivalent to Hamming(8,4).")
+            .add_option(&["-r","--reed-solomon"], StoreConst("reed-solomon"),
+            "Use this option to indicate error correction method should be Reed-Solomon codes over bytes, \
+            which survive bursts of errors. The numerical parameter indicates the number of parity bytes \
+            in each 255 byte block; up to half that many damaged bytes per block can be corrected.")
+            .add_option(&["-b","--bch"], StoreConst("bch"),
+            "Use this option to indicate error correction method should be binary BCH codes, which correct \
+            several bits anywhere in each block. The numerical parameter indicates the size of the field; blocks \
+            are 2^n - 1 bits long. Choose how many bits each block corrects with -t; -n 8 -t 4 is BCH(255,223).")
+            .add_option(&["--convolutional"], StoreConst("convolutional"),
+            "Use this option to indicate error correction method should be convolutional codes decoded with the \
+            Viterbi algorithm. The numerical parameter is the constraint length, from 3 to 9. Choose the \
+            generators with --generators; -n 7 uses the NASA standard rate 1/2 code by default.");
         ap.refer(&mut num)
             .add_option(&["-n","--numerical"], Store,
             "Use this option to indicate the amount of repetition in the error correction method. For details, see\
             the different methods.");
-        ap.parse_args_or_exit();
+        ap.refer(&mut errors)
+            .add_option(&["-t","--errors"], StoreOption,
+            "Use this option to choose how many bit errors in each block BCH codes correct.");
+        ap.refer(&mut generators)
+            .add_option(&["--generators"], Store,
+            "Use this option to choose the generator polynomials of convolutional codes, as two (for rate 1/2) \
+            or three (for rate 1/3) octal numbers like '171,133'.");
+        ap.refer(&mut tail_biting)
+            .add_option(&["--tail-biting"], StoreTrue,
+            "Use this flag to make convolutional codes tail-biting, which saves the bits that otherwise end \
+            each frame at the cost of slower decoding.");
+        ap.refer(&mut depth)
+            .add_option(&["-i","--interleave"], Store,
+            "Use this option to interleave this many codewords, so that a burst of up to that many \
+            damaged bits (or bytes, for Reed-Solomon) is spread across different codewords. The default of 0 \
+            doesn't interleave. Decoding reads the interleaving from the header.");
+        ap.refer(&mut interleaver)
+            .add_option(&["--interleaver"], Store,
+            "Use this option to choose how codewords are interleaved: 'block' (the default) writes them as rows \
+            and reads them out by columns, and 'random' shuffles them.");
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to choose where the output of a single file goes, or '-' for stdout.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue, FORCE_HELP);
+        ap.refer(&mut threads)
+            .add_option(&["--threads"], Store, THREADS_HELP);
+        ap.refer(&mut shards)
+            .add_option(&["--shards"], Store,
+            "Use this option to split each file into this many shards, up to 255, written as 'name.ecc.1', \
+            'name.ecc.2' and so on, so that they can be kept on separate devices. Each shard is encoded on its \
+            own, and any --required of them recover the file with 'decode --shards'. The file \
+            is read twice, so it can't be stdin.");
+        ap.refer(&mut required)
+            .add_option(&["--required"], Store,
+            "Use this option with --shards to choose how many of the shards are needed to recover the file; \
+            the rest are Reed-Solomon parity across the shards.");
+        ap.refer(&mut files)
+            .add_argument("files", List, FILES_HELP);
+        parse(ap, args);
+    }
+    check_output(&output, &files);
+    let threads = thread_count(threads);
+    if (shards > 0 || required > 0) && (required == 0 || required > shards || shards > shard::MAX_SHARDS) {
+        eprintln!("--shards and --required go together, and the number of shards required must be from 1 to the \
+            number of shards, which can be at most {}", shard::MAX_SHARDS);
+        return EXIT_USAGE;
+    }
+
+    if codec_name.is_empty() {
+        codec_name = shortcut.to_string();
+    }
+    let kind = match interleave::Kind::by_name(&interleaver) {
+        Some(kind) => kind,
+        None => {
+            eprintln!("The interleaver must be 'block' or 'random', not '{}'", interleaver);
+            return EXIT_USAGE;
+        }
+    };
+    // each file needs its own, but any problem should be reported before starting
+    let mut parameters: Vec<usize> = Some(num).into_iter().chain(errors).collect();
+    if tail_biting || !generators.is_empty() {
+        parameters.push(tail_biting as usize);
+    }
+    if !generators.is_empty() {
+        match generators.split(',').map(|g| usize::from_str_radix(g.trim(), 8)).collect::<Result<Vec<_>, _>>() {
+            Ok(generators) => parameters.extend(generators),
+            Err(_) => {
+                eprintln!("The generators must be octal numbers separated by commas, not '{}'", generators);
+                return EXIT_USAGE;
+            }
+        }
+    }
+    let config = || Config::new(&codec_name, &parameters)
+        .and_then(|config| if depth > 0 { config.interleave(kind, depth) } else { Ok(config) })
+        .map(|config| config.threads(threads));
+    match config() {
+        Ok(config) => if verbose {
+            describe(config.codec());
+        },
+        Err(err) => {
+            eprintln!("Could not use the {} codec: {}", codec_name, err);
+            return EXIT_USAGE;
+        }
+    }
+
+    let summaries = files.len() > 1;
+    each_file(files, summaries, |file| {
+        if shards > 0 {
+            return encode_shards(file, &output, shards, required, force,
+                &|| config().expect("the config was checked"));
+        }
+        Job::new(file, &output, Naming::AddSuffix, force)?.run(|input, output| {
+            encode_stream(input, output, config().expect("the config was checked"))
+        })
+    })
+}
+
+fn decode_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut report_format = String::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut output = String::new();
+    let mut force = false;
+    let mut threads = 1;
+    let mut soft_format = String::new();
+    let mut erasures = String::new();
+    let mut map = String::new();
+    let mut shards = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Recovers the originals of encoded files, correcting what errors it can. The method \
+            is read from the header of each file. By default decoding 'name.ecc' writes 'name'.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue, VERBOSE_HELP);
+        ap.refer(&mut report_format)
+            .add_option(&["--report"], Store, REPORT_HELP);
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to choose where the output of a single file goes, or '-' for stdout.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue, FORCE_HELP);
+        ap.refer(&mut threads)
+            .add_option(&["--threads"], Store, THREADS_HELP);
+        ap.refer(&mut soft_format)
+            .add_option(&["--soft"], Store,
+            "Use this option to read soft decisions from a demodulator rather than an encoded file: a byte for \
+            each bit of the encoded file, either 'llr' (signed, positive for a zero and negative for a one, larger \
+            the more certain) or 'confidence' (unsigned, from 0 for a certain zero to 255 for a certain one). \
+            Repetition and Hamming codes weigh each bit by how certain it is; other codes decide each bit first. \
+            The whole input is read before decoding.");
+        ap.refer(&mut erasures)
+            .add_option(&["--erasures"], Store,
+            "Use this option to give the byte ranges of the encoded file known to be unreadable, like \
+            '4096-8191,20000-20511' (both ends included), so that Reed-Solomon codes correct up to twice as many \
+            damaged bytes and repetition and Hamming codes ignore those bits rather than guessing from them. BCH \
+            and convolutional codes can't make use of them and take the unreadable bits as zeros. The whole input \
+            is read before decoding.");
+        ap.refer(&mut map)
+            .add_option(&["--ddrescue-map"], Store,
+            "Use this option to read the unreadable parts of an encoded file rescued with GNU ddrescue from its \
+            mapfile: every block that isn't finished is treated as erased, like with --erasures.");
+        ap.refer(&mut shards)
+            .add_option(&["--shards"], StoreTrue,
+            "Use this flag to recover a single file from the shards made by 'encode --shards', given as the \
+            files, in any order. Shards that can't be decoded or that belong to another file are skipped, and \
+            any --required of them, as given to 'encode', are enough. Damaged stripes of 64 KiB are left out of \
+            the shards they are in, so other shards can make up for them. By default recovering from \
+            'name.ecc.1' and the others writes 'name'.");
+        ap.refer(&mut files)
+            .add_argument("files", List, FILES_HELP);
+        parse(ap, args);
+    }
+    check_report_format(&report_format);
+    if shards {
+        if !soft_format.is_empty() || !erasures.is_empty() || !map.is_empty() {
+            eprintln!("Soft decisions and erasures can't be given with shards");
+            return EXIT_USAGE;
+        }
+        return match decode_shards(&files, &output, force, verbose) {
+            Ok(_) => 0,
+            Err(failure) => {
+                eprintln!("{}", failure.message);
+                failure.status
+            }
+        };
+    }
+    check_output(&output, &files);
+    let threads = thread_count(threads);
+    if !soft_format.is_empty() && soft_format != "llr" && soft_format != "confidence" {
+        eprintln!("The soft decisions must be 'llr' or 'confidence', not '{}'", soft_format);
+        return EXIT_USAGE;
+    }
+    let mut erasures = match parse_erasures(&erasures) {
+        Ok(erasures) => erasures,
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_USAGE;
+        }
+    };
+    if !map.is_empty() {
+        let text = match fs::read_to_string(&map) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", map, err);
+                return EXIT_IO;
+            }
+        };
+        match ddrescue::parse(&text) {
+            Ok(map) => erasures = codec::sort_erasures(erasures.into_iter().chain(map.erasures()).collect()),
+            Err(err) => {
+                eprintln!("Could not use {}: {}", map, err);
+                return EXIT_USAGE;
+            }
+        }
+    }
+    if !soft_format.is_empty() && !erasures.is_empty() {
+        eprintln!("Erasures can't be given with soft decisions; give the unreadable bits as 0 instead");
+        return EXIT_USAGE;
     }
-    
-    let mut input = Vec::new();
-    io::stdin().read_to_end(&mut input).expect("There was an error while reading from stdin.");
-    
-    let output: Vec<u8>;
-    
-    if multiply {
-        if encode {
-            output = multiple::encode(&input, num);
+
+    // a JSON report is all that is written to stderr, so that it can be parsed
+    let summaries = files.len() > 1 && report_format != "json";
+    each_file(files, summaries, |file| {
+        Job::new(file, &output, Naming::RemoveSuffix, force)?.run(|input, output| {
+            if !soft_format.is_empty() || !erasures.is_empty() {
+                return decode_in_memory(input, output, &soft_format, &erasures, verbose, &report_format);
+            }
+            let mut reader = open_reader(input, verbose, threads)?;
+            let length = copy(&mut reader, output)?;
+            Ok(finish_decode(&reader, length, &report_format))
+        })
+    })
+}
+
+fn verify_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut report_format = String::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut threads = 1;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Checks encoded files for damage without writing anything, and prints whether \
+            each is intact, corrected or unrecoverable. Exits with status 1 if any can't be recovered.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue, VERBOSE_HELP);
+        ap.refer(&mut report_format)
+            .add_option(&["--report"], Store, REPORT_HELP);
+        ap.refer(&mut threads)
+            .add_option(&["--threads"], Store, THREADS_HELP);
+        ap.refer(&mut files)
+            .add_argument("files", List, FILES_HELP);
+        parse(ap, args);
+    }
+    check_report_format(&report_format);
+    let threads = thread_count(threads);
+
+    each_file(files, false, |file| {
+        let mut input = Job::new(file, STDIO, Naming::None, false)?.open()?;
+        let mut reader = open_reader(&mut input, verbose, threads)?;
+        let length = copy(&mut reader, &mut io::sink())?;
+        let summary = finish_decode(&reader, length, &report_format);
+        let state = if summary.status != 0 {
+            "unrecoverable"
+        } else if reader.damaged() {
+            "corrected"
         } else {
-            output = multiple::decode(&input, num);
+            "intact"
+        };
+        println!("{}: {}, {}", display_name(file), state, summary.text);
+        Ok(summary)
+    })
+}
+
+fn repair_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut report_format = String::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut output = String::new();
+    let mut force = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Corrects the errors in encoded files in place, so that they don't pile up until \
+            there are more than the code can correct. Only the damaged parts of each file are rewritten, and \
+            parts with errors that can't be corrected are left as they are.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue, VERBOSE_HELP);
+        ap.refer(&mut report_format)
+            .add_option(&["--report"], Store, REPORT_HELP);
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to leave a single file alone and write a repaired copy of it somewhere else \
+            instead, or '-' for stdout. The copy isn't written if some errors can't be corrected.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue,
+            "Use this flag to overwrite an output given with -o if it already exists.");
+        ap.refer(&mut files)
+            .add_argument("files", List,
+            "The files to repair. With '-' for stdin, which is also the default, the repaired copy is written \
+            to stdout.");
+        parse(ap, args);
+    }
+    check_report_format(&report_format);
+    check_output(&output, &files);
+
+    let summaries = files.len() > 1 && report_format != "json";
+    each_file(files, summaries, |file| {
+        if output.is_empty() && file != STDIO {
+            return repair_in_place(file, verbose, &report_format);
         }
-    } else {
-        if encode {
-            output = hamming::encode(&input, num);
+        // the output is either given or stdout, so the naming doesn't matter
+        Job::new(file, &output, Naming::AddSuffix, force)?.run(|input, output| {
+            let mut reader = open_reader(input, verbose, 1)?;
+            let config = Config::from_header(reader.header())
+                .map_err(|err| Failure::new(EXIT_UNRECOVERABLE, format!("could not decode: {}", err)))?;
+            let mut writer = EncodeWriter::new(&mut *output, config).map_err(Failure::write)?;
+            let length = copy(&mut reader, &mut writer)?;
+            let summary = finish_decode(&reader, length, &report_format);
+            if summary.status != 0 {
+                // re-encoding the best guess would hide the damage
+                return Err(Failure::new(summary.status, format!("not repaired: {}", summary.text)));
+            }
+            writer.finish().map_err(Failure::write)?;
+            Ok(summary)
+        })
+    })
+}
+
+fn repair_in_place(file: &str, verbose: bool, report_format: &str) -> Result<Summary, Failure> {
+    let mut handle = OpenOptions::new().read(true).write(true).open(file)
+        .map_err(|err| Failure::new(EXIT_IO, format!("could not open: {}", err)))?;
+    let failure = |err| match err {
+        Error::Io(err) => Failure::new(EXIT_IO, format!("error while repairing: {}", err)),
+        err => Failure::new(decode_status(&err), format!("could not decode: {}", err)),
+    };
+    if verbose {
+        let header = stream::read_header(&mut handle).map_err(failure)?;
+        describe(Config::from_header(&header).map_err(failure)?.codec());
+    }
+    let scrub = stream::scrub(&mut handle).map_err(failure)?;
+    handle.sync_all().map_err(Failure::write)?;
+    let text = format!("-> {}: rewrote {} bytes", file, scrub.rewritten);
+    Ok(summarize(&scrub.report, scrub.damaged || scrub.rewritten > 0, scrub.miscorrected, text, report_format))
+}
+
+fn info_command(args: Vec<String>) -> i32 {
+    let mut files: Vec<String> = Vec::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Describes how encoded files were encoded, from their headers, without decoding them.");
+        ap.refer(&mut files).required()
+            .add_argument("files", List, "The files to describe.");
+        parse(ap, args);
+    }
+
+    each_file(files, false, |file| {
+        if file == STDIO {
+            return Err(Failure::new(EXIT_USAGE, "info needs a file rather than stdin".to_string()));
+        }
+        let mut input = File::open(file)
+            .map_err(|err| Failure::new(EXIT_IO, format!("could not open: {}", err)))?;
+        let header = stream::read_header(&mut input).map_err(|err| match err {
+            Error::Io(err) => Failure::read(err),
+            err => Failure::new(decode_status(&err), err.to_string()),
+        })?;
+        let size = input.metadata().map_err(Failure::read)?.len();
+        let config = Config::from_header(&header)
+            .map_err(|err| Failure::new(EXIT_UNRECOVERABLE, format!("could not read the header: {}", err)))?;
+        let codec = config.codec();
+        let parameters: Vec<String> = codec.parameters().iter().map(|p| p.to_string()).collect();
+        println!("{}:", file);
+        println!("  codec: {}({})", codec.name(), parameters.join(", "));
+        match config.interleaving() {
+            Some((kind, depth)) => println!("  interleaving: {}, {} codewords", kind.name(), depth),
+            None => println!("  interleaving: none"),
+        }
+        println!("  data: {} bytes", header.length);
+        println!("  encoded: {} bytes", size);
+        println!("  rate: {:.1}% of the output is data", codec.rate() * 100.0);
+        println!("  corrects: {}", codec.correction_capability());
+        Ok(Summary { status: 0, text: String::new() })
+    })
+}
+
+fn simulate_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut flip = -1.0;
+    let mut burst = String::new();
+    let mut erase = -1.0;
+    let mut truncate: Option<u64> = None;
+    let mut seed: Option<u64> = None;
+    let mut file = STDIO.to_string();
+    let mut output = String::new();
+    let mut force = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Damages a file as a noisy channel or failing storage would, and writes it to stdout, \
+            so that codes can be tried out. The damage is done in the order of the options below. With none of \
+            them, every bit is flipped with probability 0.001.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue,
+            "Use this flag to describe the damage that was done, and the seed, on stderr.");
+        ap.refer(&mut flip)
+            .add_option(&["--flip"], Store,
+            "Use this option to flip every bit with this probability, as a binary symmetric channel does.");
+        ap.refer(&mut burst)
+            .add_option(&["--burst"], Store,
+            "Use this option to flip bits in bursts with a Gilbert-Elliott channel, given as 'ENTER,LEAVE,BAD' \
+            or 'ENTER,LEAVE,BAD,GOOD': after each bit a burst starts with probability ENTER or ends with \
+            probability LEAVE, and bits are flipped with probability BAD in a burst and GOOD (0 by default) \
+            otherwise. For example '0.0001,0.05,0.5' makes bursts of about 20 bits.");
+        ap.refer(&mut erase)
+            .add_option(&["--erase"], Store,
+            "Use this option to erase every byte with this probability, as if it couldn't be read, \
+            replacing it with zero.");
+        ap.refer(&mut truncate)
+            .add_option(&["--truncate"], StoreOption,
+            "Use this option to cut the output off after this many bytes.");
+        ap.refer(&mut seed)
+            .add_option(&["-s", "--seed"], StoreOption,
+            "Use this option to seed the random damage, so that it can be repeated. By default the seed is \
+            different every time.");
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to write the damaged file somewhere other than stdout.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue, FORCE_HELP);
+        ap.refer(&mut file)
+            .add_argument("file", Store, "The file to damage, or '-' for stdin, which is also the default.");
+        parse(ap, args);
+    }
+
+    let mut channels = Vec::new();
+    if flip >= 0.0 {
+        channels.push(channel::Channel::BinarySymmetric { p: flip });
+    }
+    if !burst.is_empty() {
+        let parameters: Result<Vec<f64>, _> = burst.split(',').map(|p| p.trim().parse()).collect();
+        match parameters.as_ref().map(|p| p.as_slice()) {
+            Ok(&[enter, leave, bad]) => channels.push(channel::Channel::GilbertElliott { enter, leave, good: 0.0, bad }),
+            Ok(&[enter, leave, bad, good]) => channels.push(channel::Channel::GilbertElliott { enter, leave, good, bad }),
+            _ => {
+                eprintln!("The burst channel must be given as 'ENTER,LEAVE,BAD' or 'ENTER,LEAVE,BAD,GOOD', not '{}'", burst);
+                return EXIT_USAGE;
+            }
+        }
+    }
+    if erase >= 0.0 {
+        channels.push(channel::Channel::Erasure { p: erase });
+    }
+    if let Some(length) = truncate {
+        channels.push(channel::Channel::Truncate { length });
+    }
+    if channels.is_empty() {
+        channels.push(channel::Channel::BinarySymmetric { p: 0.001 });
+    }
+    let seed = seed.unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        now.as_secs() ^ u64::from(now.subsec_nanos()) << 32 ^ u64::from(process::id())
+    });
+    if let Err(err) = channel::Noise::new(&channels, seed) {
+        eprintln!("Could not simulate the channel: {}", err);
+        return EXIT_USAGE;
+    }
+
+    each_file(vec![file], false, |file| {
+        Job::new(file, &output, Naming::Stdout, force)?.run(|input, output| {
+            let noise = channel::Noise::new(&channels, seed).expect("the channels were checked");
+            let mut noisy = Noisy { inner: output, noise, buffer: Vec::new() };
+            copy(input, &mut noisy)?;
+            let damage = noisy.noise.damage_done();
+            if verbose {
+                eprintln!("Flipped {} bits, erased {} bytes and cut off {} bytes, with seed {}.",
+                    damage.flipped, damage.erased, damage.truncated, seed);
+            }
+            Ok(Summary { status: 0, text: String::new() })
+        })
+    })
+}
+
+// Damages everything written through it.
+struct Noisy<W> {
+    inner: W,
+    noise: channel::Noise,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for Noisy<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(buf);
+        let len = self.noise.damage(&mut self.buffer);
+        self.inner.write_all(&self.buffer[..len])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn bench_ber_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut codecs = "multiple:3,multiple:5,hamming:3,hamming:4,hamming:5,extended-hamming:4,reed-solomon:16,reed-solomon:32,convolutional:7"
+        .to_string();
+    let mut from: f64 = 0.0001;
+    let mut to: f64 = 0.1;
+    let mut steps = 7;
+    let mut frames = 200;
+    let mut frame_len = 1024;
+    let mut seed: u64 = 1;
+    let mut output = String::new();
+    let mut force = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Measures how well codes correct errors on a binary symmetric channel, which flips \
+            every bit with the same probability. For each codec and each probability, random frames are encoded, \
+            damaged and decoded, and a line of CSV gives the code rate (the fraction of the output that is data), \
+            the fraction of data bits left wrong (the residual bit error rate), the fraction of frames left wrong \
+            (the frame error rate) and the fraction of frames where errors were found that couldn't be corrected. \
+            The cheapest code that meets a target is the one with the highest rate. An error rate of 0 only \
+            means that no errors were seen, so use enough frames to see a few.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue,
+            "Use this flag to show progress on stderr.");
+        ap.refer(&mut codecs)
+            .add_option(&["--codecs"], Store,
+            "Use this option to choose the codecs to measure, as a list like 'hamming:3,multiple:5,bch:8:4' of \
+            names and numerical parameters.");
+        ap.refer(&mut from)
+            .add_option(&["--from"], Store, "Use this option to choose the lowest probability, 0.0001 by default.");
+        ap.refer(&mut to)
+            .add_option(&["--to"], Store, "Use this option to choose the highest probability, 0.1 by default.");
+        ap.refer(&mut steps)
+            .add_option(&["--steps"], Store,
+            "Use this option to choose how many probabilities to measure, spaced evenly on a logarithmic scale \
+            from the lowest to the highest. The default is 7.");
+        ap.refer(&mut frames)
+            .add_option(&["--frames"], Store,
+            "Use this option to choose how many frames to send for each measurement, at least 1 and 200 by \
+            default.");
+        ap.refer(&mut frame_len)
+            .add_option(&["--frame-length"], Store,
+            "Use this option to choose how many bytes of data are in each frame, 1024 by default.");
+        ap.refer(&mut seed)
+            .add_option(&["-s", "--seed"], Store,
+            "Use this option to change the seed for the random data and damage. The same seed gives the same results.");
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to write the CSV to a file rather than stdout.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue, FORCE_HELP);
+        parse(ap, args);
+    }
+
+    let mut configs = Vec::new();
+    for spec in codecs.split(',') {
+        let mut parts = spec.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        let parameters: Result<Vec<usize>, _> = parts.map(|n| n.parse::<usize>()).collect();
+        let config = match parameters {
+            Ok(ref parameters) if !parameters.is_empty() => Config::new(name, parameters),
+            _ => {
+                eprintln!("Codecs must be given as NAME:NUMBER or NAME:NUMBER:NUMBER, not '{}'", spec);
+                return EXIT_USAGE;
+            }
+        };
+        match config {
+            Ok(config) => configs.push(config),
+            Err(err) => {
+                eprintln!("Could not use the {} codec: {}", spec, err);
+                return EXIT_USAGE;
+            }
+        }
+    }
+    if !(0.0 < from && from <= to && to <= 1.0) || steps == 0 {
+        eprintln!("The probabilities must be between 0 and 1 with --from no more than --to, and --steps at least 1");
+        return EXIT_USAGE;
+    }
+    if frames == 0 || frame_len == 0 {
+        eprintln!("There must be at least one frame of at least one byte to measure");
+        return EXIT_USAGE;
+    }
+    let probabilities: Vec<f64> = (0..steps)
+        .map(|i| if steps == 1 { from } else { from * (to / from).powf(i as f64 / (steps - 1) as f64) })
+        .collect();
+
+    // the CSV is written to a temporary file that replaces the output only once it is complete
+    let result = Job::new(STDIO, &output, Naming::Stdout, force).and_then(|job| job.run(|_, writer| {
+        let mut write = || -> io::Result<()> {
+            writeln!(writer, "codec,parameter,rate,p,frames,bit_error_rate,frame_error_rate,detected_frame_rate")?;
+            for config in &configs {
+                let codec = config.codec();
+                let parameters: Vec<String> = codec.parameters().iter().map(|p| p.to_string()).collect();
+                for (i, &p) in probabilities.iter().enumerate() {
+                    if verbose {
+                        eprintln!("Measuring {}({}) at {}", codec.name(), parameters.join(", "), p);
+                    }
+                    // every codec sees the same data and damage at each probability
+                    let channels = [channel::Channel::BinarySymmetric { p }];
+                    let measurement = ber::measure(codec, &channels, frame_len, frames, seed.wrapping_add(i as u64))
+                        .expect("the probabilities were checked");
+                    writeln!(writer, "{},{},{},{:.4e},{},{},{},{}", codec.name(), parameters.join(":"), codec.rate(),
+                        p, measurement.frames, measurement.bit_error_rate(), measurement.frame_error_rate(),
+                        measurement.detected_frame_rate())?;
+                }
+            }
+            writer.flush()
+        };
+        write().map_err(Failure::write)?;
+        Ok(Summary { status: 0, text: String::new() })
+    }));
+    match result {
+        Ok(summary) => summary.status,
+        Err(failure) => {
+            eprintln!("{}", failure.message);
+            failure.status
+        }
+    }
+}
+
+fn lt_encode_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut symbol_len = lt::DEFAULT_SYMBOL_LEN;
+    let mut overhead: f64 = 0.5;
+    let mut raptorq = false;
+    let mut file = STDIO.to_string();
+    let mut output = String::new();
+    let mut force = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Encodes a file with an LT fountain code into packets, written one after another to \
+            stdout. Each packet can be sent on its own, and the file can be recovered by 'lt-decode' from \
+            somewhat more packets than the file has symbols, whichever packets they are, so this suits links \
+            that lose packets rather than damaging them.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue,
+            "Use this flag to describe the packets on stderr.");
+        ap.refer(&mut symbol_len)
+            .add_option(&["--symbol-size"], Store,
+            "Use this option to choose how many bytes of data each packet carries, 1024 by default. Each packet \
+            adds 26 bytes to this, or 4 with --raptorq.");
+        ap.refer(&mut overhead)
+            .add_option(&["--overhead"], Store,
+            "Use this option to choose how many more packets to make than the file has symbols, as a fraction: \
+            0.5, the default, makes half as many again. Decoding takes more packets than symbols, and more so \
+            for small files: typically around 40% more for 100 symbols, 15% for 1000 and 7% for 4000 or more. \
+            The rest make up for the packets that are lost.");
+        ap.refer(&mut raptorq)
+            .add_option(&["--raptorq"], StoreTrue,
+            "Use this flag to encode with RaptorQ (RFC 6330) instead, which needs almost no more packets than \
+            symbols to decode, and which other RaptorQ implementations can decode. The output starts with the \
+            12 bytes of object transmission information, followed by packets of the source block number and \
+            encoding symbol id in 4 bytes and the symbol. The first packets are the file itself, and --overhead \
+            gives the number of repair packets that follow. RaptorQ has no way to describe an empty file, so \
+            one can't be encoded with this flag.");
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to write the packets to a file rather than stdout.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue, FORCE_HELP);
+        ap.refer(&mut file)
+            .add_argument("file", Store, "The file to encode, or '-' for stdin, which is also the default.");
+        parse(ap, args);
+    }
+    if symbol_len == 0 || symbol_len > u16::MAX as usize {
+        eprintln!("The symbol size must be between 1 and {}", u16::MAX);
+        return EXIT_USAGE;
+    }
+    if overhead.is_nan() || overhead < 0.0 {
+        eprintln!("The overhead can't be negative");
+        return EXIT_USAGE;
+    }
+
+    each_file(vec![file], false, |file| {
+        Job::new(file, &output, Naming::Stdout, force)?.run(|input, output| {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data).map_err(Failure::read)?;
+            if raptorq {
+                return raptorq_encode(&data, symbol_len as u16, overhead, output, verbose);
+            }
+            let encoder = lt::Encoder::new(&data, symbol_len);
+            let mut count = 0;
+            for packet in encoder.packets(overhead) {
+                output.write_all(&packet).map_err(Failure::write)?;
+                count += 1;
+            }
+            output.flush().map_err(Failure::write)?;
+            if verbose {
+                let layout = encoder.layout();
+                eprintln!("Encoded {} bytes as {} symbols of {} bytes in {} source blocks, into {} packets of {} bytes.",
+                    layout.length, layout.symbols(), layout.symbol_len, layout.blocks(), count,
+                    lt::PACKET_OVERHEAD + layout.symbol_len);
+            }
+            Ok(Summary { status: 0, text: String::new() })
+        })
+    })
+}
+
+fn raptorq_encode<W: Write>(data: &[u8], symbol_size: u16, overhead: f64, output: &mut W, verbose: bool)
+    -> Result<Summary, Failure>
+{
+    let oti = raptorq::Oti::for_length(data.len() as u64, symbol_size)
+        .map_err(|err| Failure::new(EXIT_USAGE, format!("could not encode with RaptorQ: {}", err)))?;
+    let encoder = raptorq::Encoder::new(data, oti);
+    output.write_all(&oti.to_bytes()).map_err(Failure::write)?;
+    let mut count = 0;
+    for packet in encoder.packets(overhead) {
+        output.write_all(&packet.to_bytes()).map_err(Failure::write)?;
+        count += 1;
+    }
+    output.flush().map_err(Failure::write)?;
+    if verbose {
+        eprintln!("Encoded {} bytes as {} symbols of {} bytes in {} source blocks, into {} packets of {} bytes.",
+            oti.transfer_length, oti.symbols(), oti.symbol_size, oti.source_blocks, count,
+            raptorq::PAYLOAD_ID_LEN + usize::from(oti.symbol_size));
+    }
+    Ok(Summary { status: 0, text: String::new() })
+}
+
+fn lt_decode_command(args: Vec<String>) -> i32 {
+    let mut verbose = false;
+    let mut raptorq = false;
+    let mut file = STDIO.to_string();
+    let mut output = String::new();
+    let mut force = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Recovers a file from packets made by 'lt-encode', in any order and with any of them \
+            missing, as long as there are enough. Damaged packets are found by their checksums and skipped. \
+            The file is written to stdout.");
+        ap.refer(&mut verbose)
+            .add_option(&["-v", "--verbose"], StoreTrue,
+            "Use this flag to count the packets that were used and skipped on stderr.");
+        ap.refer(&mut raptorq)
+            .add_option(&["--raptorq"], StoreTrue,
+            "Use this flag to decode packets made with 'lt-encode --raptorq', or by other RaptorQ encoders \
+            when they are saved the same way. These have no checksums, so damaged packets can't be skipped.");
+        ap.refer(&mut output)
+            .add_option(&["-o","--output"], Store,
+            "Use this option to write the file somewhere other than stdout.");
+        ap.refer(&mut force)
+            .add_option(&["-f","--force"], StoreTrue, FORCE_HELP);
+        ap.refer(&mut file)
+            .add_argument("file", Store, "The packets, or '-' for stdin, which is also the default.");
+        parse(ap, args);
+    }
+
+    each_file(vec![file], false, |file| {
+        let mut bytes = Vec::new();
+        Job::new(file, "", Naming::None, false)?.open()?.read_to_end(&mut bytes).map_err(Failure::read)?;
+        let data = if raptorq { raptorq_decode(&bytes, verbose)? } else { lt_decode(&bytes, verbose)? };
+        Job::new(STDIO, &output, Naming::Stdout, force)?.run(|_, output| {
+            output.write_all(&data).and_then(|_| output.flush()).map_err(Failure::write)?;
+            Ok(Summary { status: 0, text: String::new() })
+        })
+    })
+}
+
+fn lt_decode(bytes: &[u8], verbose: bool) -> Result<Vec<u8>, Failure> {
+    let scan = lt::scan(bytes);
+    let mut decoder = lt::Decoder::new();
+    let mut mismatched = 0;
+    for packet in scan.packets {
+        if decoder.add(packet).is_err() {
+            mismatched += 1;
+        }
+    }
+    if verbose {
+        eprintln!("Read {} packets, skipping {} damaged bytes and {} packets of other files.",
+            decoder.received(), scan.skipped, mismatched);
+    }
+    if decoder.received() == 0 && mismatched == 0 && !bytes.is_empty() {
+        let text = if raptorq::Oti::from_bytes(bytes).is_ok() {
+            "could not decode: there are no LT packets, and these look like RaptorQ ones, so try --raptorq"
         } else {
-            output = hamming::decode(&input, num);
+            "could not decode: there are no LT packets"
+        };
+        return Err(Failure::new(EXIT_USAGE, text.to_string()));
+    }
+    decoder.data().ok_or_else(|| {
+        let (known, symbols) = decoder.progress();
+        Failure::new(EXIT_UNRECOVERABLE, format!(
+            "could not decode: only {} of {} symbols could be recovered, so more packets are needed",
+            known, symbols))
+    })
+}
+
+fn raptorq_decode(bytes: &[u8], verbose: bool) -> Result<Vec<u8>, Failure> {
+    if bytes.starts_with(&lt::MAGIC) {
+        return Err(Failure::new(EXIT_USAGE,
+            "could not decode: these are LT packets rather than RaptorQ ones, so leave out --raptorq".to_string()));
+    }
+    let oti = raptorq::Oti::from_bytes(bytes)
+        .map_err(|err| Failure::new(EXIT_UNRECOVERABLE, format!("could not decode: {}", err)))?;
+    let packet_len = raptorq::PAYLOAD_ID_LEN + usize::from(oti.symbol_size);
+    let packets = bytes[raptorq::OTI_LEN..].chunks(packet_len);
+    let mut decoder = raptorq::Decoder::new(oti);
+    let mut skipped = 0;
+    for packet in packets {
+        let used = raptorq::Packet::parse(packet, oti.symbol_size).and_then(|packet| decoder.add(packet));
+        if used.is_err() {
+            skipped += 1;
+        }
+    }
+    if verbose {
+        eprintln!("Read {} packets, skipping {} that were cut short or of no source block.", decoder.received(), skipped);
+    }
+    decoder.decode().ok_or_else(|| {
+        let (decoded, blocks) = decoder.progress();
+        Failure::new(EXIT_UNRECOVERABLE, format!(
+            "could not decode: only {} of {} source blocks could be recovered, so more packets are needed",
+            decoded, blocks))
+    })
+}
+
+fn parse(ap: ArgumentParser, args: Vec<String>) {
+    if let Err(status) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+        process::exit(status);
+    }
+}
+
+fn check_report_format(format: &str) {
+    if !format.is_empty() && format != "text" && format != "json" {
+        eprintln!("The report format must be 'text' or 'json', not '{}'", format);
+        process::exit(EXIT_USAGE);
+    }
+}
+
+fn check_output(output: &str, files: &[String]) {
+    if !output.is_empty() && files.len() > 1 {
+        eprintln!("An output can only be given for a single file");
+        process::exit(EXIT_USAGE);
+    }
+}
+
+// The number of threads to use given `--threads`, where 0 means one per core.
+fn thread_count(threads: usize) -> usize {
+    if threads > 0 {
+        return threads;
+    }
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+fn display_name(file: &str) -> &str {
+    if file == STDIO { "stdin" } else { file }
+}
+
+// Runs `process` on each file, or on stdin if there are none, and returns the
+// status of the first one to fail. Failures are described on stderr, and so
+// are the summaries if `summaries` is set.
+fn each_file<F>(mut files: Vec<String>, summaries: bool, mut process: F) -> i32
+    where F: FnMut(&str) -> Result<Summary, Failure>
+{
+    if files.is_empty() {
+        files.push(STDIO.to_string());
+    }
+    let mut status = 0;
+    for file in &files {
+        let file_status = match process(file) {
+            Ok(summary) => {
+                if summaries {
+                    eprintln!("{} {}", file, summary.text);
+                }
+                summary.status
+            }
+            Err(failure) => {
+                eprintln!("{}: {}", display_name(file), failure.message);
+                failure.status
+            }
+        };
+        if status == 0 {
+            status = file_status;
+        }
+    }
+    status
+}
+
+struct Failure {
+    status: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: i32, message: String) -> Failure {
+        Failure { status, message }
+    }
+
+    fn read(err: io::Error) -> Failure {
+        match err.kind() {
+            // the trailer of a streamed file couldn't be read
+            io::ErrorKind::InvalidData => Failure::new(EXIT_UNRECOVERABLE, format!("could not decode: {}", err)),
+            _ => Failure::new(EXIT_IO, format!("error while reading: {}", err)),
+        }
+    }
+
+    fn write(err: io::Error) -> Failure {
+        Failure::new(EXIT_IO, format!("error while writing: {}", err))
+    }
+}
+
+/// What happened to one input.
+struct Summary {
+    status: i32,
+    text: String,
+}
+
+// Like `io::copy`, but tells reading and writing errors apart.
+fn copy<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> Result<u64, Failure> {
+    let mut buffer = vec![0; 1 << 16];
+    let mut total = 0;
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => return writer.flush().map(|_| total).map_err(Failure::write),
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Failure::read(err)),
+        };
+        writer.write_all(&buffer[..len]).map_err(Failure::write)?;
+        total += len as u64;
+    }
+}
+
+// Counts the bytes written through it.
+struct Counter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where the output goes when `-o` isn't given.
+enum Naming {
+    AddSuffix,
+    RemoveSuffix,
+    /// The output goes to stdout.
+    Stdout,
+    /// There is no output.
+    None,
+}
+
+// The file the output of `input` goes to, or `None` for stdout or no output.
+// `output` is the `-o` option, if it was given.
+fn output_path(input: &str, output: &str, naming: Naming) -> Result<Option<PathBuf>, Failure> {
+    Ok(match (input, output, naming) {
+        (_, _, Naming::None) | (_, STDIO, _) | (STDIO, "", _) | (_, "", Naming::Stdout) => None,
+        (_, "", Naming::AddSuffix) => Some(PathBuf::from(format!("{}{}", input, SUFFIX))),
+        (_, "", Naming::RemoveSuffix) => match input.strip_suffix(SUFFIX) {
+            Some(stem) if !stem.is_empty() => Some(PathBuf::from(stem)),
+            _ => return Err(Failure::new(EXIT_USAGE,
+                format!("the name doesn't end in {}, so give an output with -o", SUFFIX))),
+        },
+        (_, output, _) => Some(PathBuf::from(output)),
+    })
+}
+
+/// One input and where its output goes.
+struct Job {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    /// Whether the output was created empty to claim it, and should be removed
+    /// again if the job doesn't replace it.
+    reserved: bool,
+    /// The file being written in place of the output, removed unless it replaces it.
+    temporary: Option<PathBuf>,
+}
+
+impl Job {
+    // Without `force`, the output is created straight away, so that another
+    // file appearing there before it is written can't be overwritten.
+    fn new(input: &str, output: &str, naming: Naming, force: bool) -> Result<Job, Failure> {
+        let output = output_path(input, output, naming)?;
+        let mut reserved = false;
+        if let Some(ref path) = output {
+            if !force {
+                OpenOptions::new().write(true).create_new(true).open(path).map_err(|err| match err.kind() {
+                    io::ErrorKind::AlreadyExists => Failure::new(EXIT_USAGE,
+                        format!("{} already exists; use --force to overwrite it", path.display())),
+                    _ => Failure::new(EXIT_IO, format!("could not create {}: {}", path.display(), err)),
+                })?;
+                reserved = true;
+            }
+        }
+        let input = if input == STDIO { None } else { Some(PathBuf::from(input)) };
+        Ok(Job { input, output, reserved, temporary: None })
+    }
+
+    fn open(&self) -> Result<Box<dyn Read>, Failure> {
+        match self.input {
+            Some(ref path) => match File::open(path) {
+                Ok(file) => Ok(Box::new(BufReader::new(file))),
+                Err(err) => Err(Failure::new(EXIT_IO, format!("could not open: {}", err))),
+            },
+            None => Ok(Box::new(io::stdin())),
+        }
+    }
+
+    // Runs `process` from the input to the output. Files are written to a
+    // temporary file that replaces the output only once it is complete.
+    fn run<F>(mut self, process: F) -> Result<Summary, Failure>
+        where F: FnOnce(&mut dyn Read, &mut Counter<&mut dyn Write>) -> Result<Summary, Failure>
+    {
+        let mut input = self.open()?;
+        let path = match self.output {
+            Some(ref path) => path,
+            None => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                let mut counter = Counter { inner: &mut stdout as &mut dyn Write, count: 0 };
+                let mut summary = process(&mut input, &mut counter)?;
+                summary.text = format!("-> stdout: {}", summary.text);
+                return Ok(summary);
+            }
+        };
+        let text = format!("-> {}: ", path.display());
+        let mut writer = BufWriter::new(self.create()?);
+        let mut summary = {
+            let mut counter = Counter { inner: &mut writer as &mut dyn Write, count: 0 };
+            process(&mut input, &mut counter)?
+        };
+        summary.text = text + &summary.text;
+        let file = writer.into_inner().map_err(|err| Failure::write(err.into_error()))?;
+        self.commit(file)?;
+        Ok(summary)
+    }
+
+    // Creates the file written in place of the output, which `commit` moves
+    // there once it is complete.
+    fn create(&mut self) -> Result<File, Failure> {
+        let temporary = temporary_path(self.output.as_ref().expect("only files are created"));
+        let file = OpenOptions::new().write(true).create_new(true).open(&temporary)
+            .map_err(|err| Failure::new(EXIT_IO, format!("could not create {}: {}", temporary.display(), err)))?;
+        self.temporary = Some(temporary);
+        Ok(file)
+    }
+
+    fn commit(mut self, file: File) -> Result<(), Failure> {
+        file.sync_all().map_err(Failure::write)?;
+        let path = self.output.clone().expect("only files are created");
+        let temporary = self.temporary.clone().expect("the output was created");
+        fs::rename(&temporary, &path)
+            .map_err(|err| Failure::new(EXIT_IO, format!("could not replace {}: {}", path.display(), err)))?;
+        self.temporary = None;
+        self.reserved = false;
+        Ok(())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        if let Some(ref temporary) = self.temporary {
+            let _ = fs::remove_file(temporary);
+        }
+        match self.output {
+            Some(ref path) if self.reserved => { let _ = fs::remove_file(path); }
+            _ => {}
+        }
+    }
+}
+
+// A file next to `path`, so that renaming it to `path` is atomic.
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, process::id()))
+}
+
+fn encode_stream<W: Write>(input: &mut dyn Read, output: &mut Counter<W>, config: Config) -> Result<Summary, Failure> {
+    let mut writer = EncodeWriter::new(&mut *output, config).map_err(Failure::write)?;
+    let length = copy(input, &mut writer)?;
+    writer.finish().map_err(Failure::write)?;
+    Ok(Summary { status: 0, text: format!("encoded {} bytes into {}", length, output.count) })
+}
+
+// Splits `file` into shards, each encoded on its own and written next to the
+// output as 'name.ecc.1', 'name.ecc.2' and so on. Every shard starts with the
+// length and checksum of the file, so the file is read twice, first for those
+// and then to split it a row of stripes at a time, and it can't be stdin.
+fn encode_shards(file: &str, output: &str, shards: usize, required: usize, force: bool,
+    config: &dyn Fn() -> Config) -> Result<Summary, Failure>
+{
+    let base = match (file, output) {
+        (_, STDIO) => return Err(Failure::new(EXIT_USAGE, "shards can't be written to stdout".to_string())),
+        (STDIO, _) => return Err(Failure::new(EXIT_USAGE,
+            "shards are made from a file, which is read twice, so not from stdin".to_string())),
+        (file, "") => format!("{}{}", file, SUFFIX),
+        (_, output) => output.to_string(),
+    };
+    // every output is checked before any is written
+    let mut jobs = (1..=shards)
+        .map(|index| Job::new(STDIO, &format!("{}.{}", base, index), Naming::Stdout, force))
+        .collect::<Result<Vec<Job>, Failure>>()?;
+    let input = Job::new(file, "", Naming::None, false)?;
+    let (length, checksum) = shard::measure(&mut input.open()?).map_err(Failure::read)?;
+    let mut splitter = shard::Splitter::new(shards, required, length, checksum)
+        .map_err(|err| Failure::new(EXIT_USAGE, err))?;
+    let mut writers = Vec::with_capacity(shards);
+    for (job, header) in jobs.iter_mut().zip(splitter.headers()) {
+        let output = Counter { inner: BufWriter::new(job.create()?), count: 0 };
+        let mut writer = EncodeWriter::new(output, config()).map_err(Failure::write)?;
+        writer.write_all(&header.to_bytes()).map_err(Failure::write)?;
+        writers.push(writer);
+    }
+    let mut reader = input.open()?;
+    let mut row = vec![0; required * shard::STRIPE_LEN];
+    loop {
+        let len = fill(&mut reader, &mut row)?;
+        if len > 0 {
+            for (writer, stripe) in writers.iter_mut().zip(splitter.split_row(&row[..len])) {
+                writer.write_all(&stripe).map_err(Failure::write)?;
+            }
+        }
+        if len < row.len() {
+            break;
         }
     }
-    
-    io::stdout().write_all(&output).expect("There was an error while writing to stdout");
+    if !splitter.is_complete() {
+        return Err(Failure::new(EXIT_IO, "the file changed while it was being split".to_string()));
+    }
+    let mut total = 0;
+    for (job, writer) in jobs.into_iter().zip(writers) {
+        let output = writer.finish().map_err(Failure::write)?;
+        total += output.count;
+        job.commit(output.inner.into_inner().map_err(|err| Failure::write(err.into_error()))?)?;
+    }
+    Ok(Summary { status: 0, text: format!("-> {}.1 to {}.{}: encoded {} bytes into {}, any {} shards of which recover it",
+        base, base, shards, length, total, required) })
+}
+
+// Reads until `buffer` is full or the input ends, returning how much was read.
+fn fill<R: Read + ?Sized>(input: &mut R, buffer: &mut [u8]) -> Result<usize, Failure> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match input.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(Failure::read(err)),
+        }
+    }
+    Ok(len)
+}
+
+// Opens an encoded shard and reads its header.
+fn open_shard<R: Read>(input: R) -> Result<(DecodeReader<R>, shard::Header), String> {
+    let mut reader = DecodeReader::new(input).map_err(|err| err.to_string())?;
+    let mut bytes = Vec::with_capacity(shard::HEADER_LEN);
+    reader.by_ref().take(shard::HEADER_LEN as u64).read_to_end(&mut bytes).map_err(|err| err.to_string())?;
+    let header = shard::Header::parse(&bytes).map_err(|err| err.to_string())?;
+    Ok((reader, header))
+}
+
+// Recovers a file from its shards, skipping any that can't be read or that
+// belong to another file. The shards are read side by side a row of stripes
+// at a time, and a damaged stripe is left out of its row. By default the
+// output is named after the first shard, without '.N' and '.ecc'.
+fn decode_shards(files: &[String], output: &str, force: bool, verbose: bool) -> Result<Summary, Failure> {
+    let first = match files.first() {
+        Some(first) => first,
+        None => return Err(Failure::new(EXIT_USAGE, "give the files of the shards".to_string())),
+    };
+    let output = match output {
+        "" => match first.rsplit_once('.') {
+            Some((stem, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) =>
+                match stem.strip_suffix(SUFFIX) {
+                    Some(name) if !name.is_empty() => name.to_string(),
+                    _ => return Err(Failure::new(EXIT_USAGE,
+                        format!("the name doesn't end in {}.N, so give an output with -o", SUFFIX))),
+                },
+            _ => return Err(Failure::new(EXIT_USAGE,
+                format!("the name doesn't end in {}.N, so give an output with -o", SUFFIX))),
+        },
+        output => output.to_string(),
+    };
+    let job = Job::new(STDIO, &output, Naming::Stdout, force)?;
+
+    let mut opened = Vec::new();
+    for file in files {
+        let reader = Job::new(file, "", Naming::None, false).and_then(|job| job.open())
+            .map_err(|failure| failure.message);
+        match reader.and_then(open_shard) {
+            Ok((reader, header)) => opened.push((file, header, Some(reader))),
+            Err(message) => eprintln!("{}: skipped: {}", display_name(file), message),
+        }
+    }
+    let headers: Vec<shard::Header> = opened.iter().map(|&(_, header, _)| header).collect();
+    let choice = shard::choose(&headers)
+        .map_err(|err| Failure::new(EXIT_UNRECOVERABLE, format!("could not recover the file: {}", err)))?;
+    for &position in &choice.skipped {
+        eprintln!("{}: skipped: the shard belongs to a different file", display_name(opened[position].0));
+    }
+    let header = headers[choice.chosen[0]];
+    let mut chosen: Vec<_> = choice.chosen.iter().map(|&position| {
+        let (file, header, ref mut reader) = opened[position];
+        (file, header, reader.take())
+    }).collect();
+    let mut joiner = shard::Joiner::new(&header);
+
+    let result = job.run(|_, output| {
+        let mut stripes = vec![Vec::new(); chosen.len()];
+        for stripe_len in header.rows() {
+            for (&mut (file, _, ref mut reader), stripe) in chosen.iter_mut().zip(&mut stripes) {
+                stripe.resize(stripe_len + shard::STRIPE_CHECKSUM_LEN, 0);
+                let read = match *reader {
+                    Some(ref mut reader) => reader.read_exact(stripe),
+                    None => continue,
+                };
+                if let Err(err) = read {
+                    eprintln!("{}: skipped the rest: {}", display_name(file), err);
+                    *reader = None;
+                }
+            }
+            let mut row = vec![None; header.shards];
+            for (&(_, shard, ref reader), stripe) in chosen.iter().zip(&stripes) {
+                if reader.is_some() {
+                    row[shard.index] = Some(&stripe[..]);
+                }
+            }
+            let data = joiner.join_row(&row)
+                .map_err(|err| Failure::new(EXIT_UNRECOVERABLE, format!("could not recover the file: {}", err)))?;
+            output.write_all(&data).map_err(Failure::write)?;
+        }
+        output.flush().map_err(Failure::write)?;
+        if !joiner.is_complete() {
+            return Err(Failure::new(EXIT_UNRECOVERABLE,
+                "could not recover the file: it doesn't match its checksum".to_string()));
+        }
+        Ok(Summary { status: 0, text: format!("decoded {} bytes", header.length) })
+    });
+    for &(file, shard, _) in &chosen {
+        match joiner.damaged()[shard.index] {
+            0 => {}
+            damaged => eprintln!("{}: left out {} damaged stripes", display_name(file), damaged),
+        }
+    }
+    if verbose && result.is_ok() {
+        eprintln!("Recovered the file from {} of {} shard files.", chosen.len(), files.len());
+    }
+    result
+}
+
+// The exit status for an error reading encoded data. An input that isn't an
+// encoded file at all was given by mistake rather than damaged beyond repair.
+fn decode_status(err: &Error) -> i32 {
+    match *err {
+        Error::Header(HeaderError::BadMagic) => EXIT_USAGE,
+        Error::Io(_) => EXIT_IO,
+        _ => EXIT_UNRECOVERABLE,
+    }
+}
+
+fn open_reader<R: Read>(input: R, verbose: bool, threads: usize) -> Result<DecodeReader<R>, Failure> {
+    let reader = match DecodeReader::new(input) {
+        Ok(reader) => reader.threads(threads),
+        Err(Error::Io(err)) => return Err(Failure::read(err)),
+        Err(err) => return Err(Failure::new(decode_status(&err), format!("could not decode: {}", err))),
+    };
+    if verbose {
+        describe(reader.codec());
+    }
+    Ok(reader)
+}
+
+// Parses byte ranges like '4096-8191,20000-20511', where both ends are included.
+fn parse_erasures(ranges: &str) -> Result<Vec<Range<usize>>, String> {
+    let mut erasures = Vec::new();
+    for range in ranges.split(',').map(str::trim).filter(|range| !range.is_empty()) {
+        let bounds: Option<(usize, usize)> = range.split_once('-')
+            .and_then(|(start, end)| Some((start.trim().parse().ok()?, end.trim().parse().ok()?)));
+        match bounds {
+            Some((start, end)) if start <= end => erasures.push(start..end + 1),
+            _ => return Err(format!("Erasures must be given as START-END byte offsets, not '{}'", range)),
+        }
+    }
+    Ok(codec::sort_erasures(erasures))
+}
+
+// Decodes the whole input at once, either as soft decisions in `soft_format`,
+// a byte for each bit of an encoded file, or as an encoded file with `erasures`.
+fn decode_in_memory<W: Write>(input: &mut dyn Read, output: &mut W, soft_format: &str, erasures: &[Range<usize>],
+    verbose: bool, report_format: &str) -> Result<Summary, Failure>
+{
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes).map_err(Failure::read)?;
+    let failure = |err: Error| Failure::new(decode_status(&err), format!("could not decode: {}", err));
+    let (info, result) = match soft_format {
+        "" => (eccfile::inspect(&bytes), eccfile::recover_with_erasures(&bytes, erasures)),
+        format => {
+            let soft = if format == "llr" { soft::from_llrs(&bytes) } else { soft::from_confidences(&bytes) };
+            (eccfile::inspect(&soft::hard_decisions(&soft)), eccfile::recover_soft(&soft))
+        }
+    };
+    let info = info.map_err(failure)?;
+    if verbose {
+        describe(info.codec.as_ref());
+    }
+    // if some errors couldn't be corrected, the best guess is still written
+    let (decoded, miscorrected) = match result {
+        Ok(decoded) | Err(Error::Decode(DecodeError::Uncorrectable { output: decoded, .. })) => (decoded, false),
+        Err(Error::Miscorrected(decoded)) => (decoded, true),
+        Err(err) => return Err(failure(err)),
+    };
+    output.write_all(&decoded.data).and_then(|_| output.flush()).map_err(Failure::write)?;
+    let text = format!("decoded {} bytes", decoded.data.len());
+    Ok(summarize(&decoded.report, info.damaged, miscorrected, text, report_format))
+}
+
+// Reports on the `length` bytes that were read from `reader`. If some of them
+// couldn't be corrected, the best guess at them has still been read.
+fn finish_decode<R: Read>(reader: &DecodeReader<R>, length: u64, report_format: &str) -> Summary {
+    summarize(reader.report(), reader.damaged(), reader.miscorrected(), format!("decoded {} bytes", length),
+        report_format)
+}
+
+// Describes the errors in `report` on stderr, and adds what was corrected to `text`.
+// Data that doesn't match its checksum is as unrecoverable as uncorrectable blocks.
+// A JSON report is written on its own, with everything else in it.
+fn summarize(report: &Report, damaged: bool, miscorrected: bool, mut text: String, report_format: &str) -> Summary {
+    let prose = report_format != "json";
+    if damaged && prose {
+        eprintln!("The encoded data had been damaged.");
+    }
+    print_report(report, damaged, miscorrected, report_format);
+    text += &format!(", correcting {} bits", report.corrected_bits.len());
+    if miscorrected {
+        if prose {
+            eprintln!("The decoded data doesn't match its checksum, so some errors were corrected wrongly.");
+        }
+        text += ", but the decoded data doesn't match its checksum";
+        return Summary { status: EXIT_UNRECOVERABLE, text };
+    }
+    if report.uncorrectable.is_empty() {
+        return Summary { status: 0, text };
+    }
+    if prose {
+        eprintln!("{} blocks had errors that could not be corrected:", report.uncorrectable.len());
+        for location in &report.uncorrectable {
+            eprintln!("  block {} at bit {} (byte {})", location.block, location.bit, location.bit / 8);
+        }
+    }
+    text += &format!(", but {} blocks could not be corrected", report.uncorrectable.len());
+    Summary { status: EXIT_UNRECOVERABLE, text }
+}
+
+fn describe(codec: &dyn Codec) {
+    let parameters: Vec<String> = codec.parameters().iter().map(|p| p.to_string()).collect();
+    eprintln!("Using {}({}): {:.1}% of the output is data, and it corrects {}.",
+        codec.name(), parameters.join(", "), codec.rate() * 100.0, codec.correction_capability());
+}
+
+fn print_report(report: &Report, damaged: bool, miscorrected: bool, format: &str) {
+    match format {
+        "text" => {
+            eprintln!("Decoded {} blocks: {} corrected, {} uncorrectable.",
+                report.blocks, report.corrected_blocks, report.uncorrectable.len());
+            eprintln!("Corrected {} bits; estimated raw bit error rate {:e}.",
+                report.corrected_bits.len(), report.bit_error_rate());
+            if !report.corrected_bits.is_empty() {
+                let bits: Vec<String> = report.corrected_bits.iter().map(|b| b.to_string()).collect();
+                eprintln!("Corrected bits: {}", bits.join(", "));
+            }
+        }
+        "json" => {
+            let bits: Vec<String> = report.corrected_bits.iter().map(|b| b.to_string()).collect();
+            let blocks: Vec<String> = report.uncorrectable.iter()
+                .map(|l| format!("{{\"block\":{},\"bit\":{}}}", l.block, l.bit))
+                .collect();
+            eprintln!("{{\"blocks\":{},\"corrected_blocks\":{},\"corrected_bits\":[{}],\
+                \"uncorrectable_blocks\":[{}],\"code_bits\":{},\"bit_error_rate\":{},\"damaged\":{},\
+                \"miscorrected\":{}}}",
+                report.blocks, report.corrected_blocks, bits.join(","),
+                blocks.join(","), report.code_bits, report.bit_error_rate(), damaged, miscorrected);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn named(input: &str, output: &str, naming: Naming) -> Option<String> {
+        output_path(input, output, naming).ok().expect("the output can be named")
+            .map(|path| path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn naming() {
+        assert_eq!(named("photo.jpg", "", Naming::AddSuffix), Some("photo.jpg.ecc".to_string()));
+        assert_eq!(named("photo.jpg.ecc", "", Naming::RemoveSuffix), Some("photo.jpg".to_string()));
+        assert_eq!(named("photo.jpg", "", Naming::Stdout), None);
+        assert_eq!(named("photo.jpg", "", Naming::None), None);
+        // an output given with -o is used whatever the naming, and '-' is stdout
+        for naming in [Naming::AddSuffix, Naming::RemoveSuffix, Naming::Stdout] {
+            assert_eq!(named("photo.jpg", "copy", naming), Some("copy".to_string()));
+        }
+        assert_eq!(named("photo.jpg.ecc", STDIO, Naming::RemoveSuffix), None);
+        assert_eq!(named("photo.jpg", "copy", Naming::None), None);
+        // stdin has no name to go by, so its output goes to stdout
+        assert_eq!(named(STDIO, "", Naming::AddSuffix), None);
+        assert_eq!(named(STDIO, "", Naming::RemoveSuffix), None);
+
+        for input in &["photo.jpg", "photo.jpg.ecc.1", ".ecc"] {
+            match output_path(input, "", Naming::RemoveSuffix) {
+                Err(failure) => assert_eq!(failure.status, EXIT_USAGE, "{}", input),
+                Ok(path) => panic!("{} was named {:?}", input, path),
+            }
+        }
+    }
+
+    #[test]
+    fn outputs_are_claimed() {
+        let path = env::temp_dir().join(format!("eccfile-job-{}", process::id()));
+        let name = path.to_str().unwrap();
+        let _ = fs::remove_file(&path);
+
+        let job = Job::new(STDIO, name, Naming::Stdout, false).ok().unwrap();
+        assert!(path.exists());
+        // a second job can't have the same output, unless it's forced
+        assert_eq!(Job::new(STDIO, name, Naming::Stdout, false).err().map(|failure| failure.status), Some(EXIT_USAGE));
+        assert!(Job::new(STDIO, name, Naming::Stdout, true).is_ok());
+        // an output that was never written is given up again
+        drop(job);
+        assert!(!path.exists());
+
+        let job = Job::new(STDIO, name, Naming::Stdout, false).ok().unwrap();
+        let failed = job.run(|_, _| Err(Failure::new(EXIT_IO, "broken".to_string())));
+        assert_eq!(failed.err().map(|failure| failure.status), Some(EXIT_IO));
+        assert!(!path.exists());
+
+        let job = Job::new(STDIO, name, Naming::Stdout, false).ok().unwrap();
+        job.run(|_, output| {
+            output.write_all(b"written").map_err(Failure::write)?;
+            Ok(Summary { status: 0, text: String::new() })
+        }).ok().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"written");
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,7 +1,10 @@
+use std::cmp;
+
 use bitvec::BitVec;
+use codec::{self, Capability, Codec, DecodeError, DecodeOutput, ErrorLocation, Report, Unit};
 
-pub fn encode(v: &Vec<u8>, n: usize) -> Vec<u8> {
-    let plain = BitVec::from_bytes(&v);
+pub fn encode(v: &[u8], n: usize) -> Vec<u8> {
+    let plain = BitVec::from_bytes(v);
     let mut code = BitVec::with_capacity(plain.len() * n);
     
     for bit in plain.iter() {
@@ -13,10 +16,13 @@ pub fn encode(v: &Vec<u8>, n: usize) -> Vec<u8> {
     code.to_bytes()
 }
 
-pub fn decode(v: &Vec<u8>, n: usize) -> Vec<u8> {
-    let code = BitVec::from_bytes(&v);
+/// Decodes by majority vote. With an even `n` a group can be evenly split, and
+/// then the error is reported rather than guessed at.
+pub fn decode(v: &[u8], n: usize) -> Result<DecodeOutput, DecodeError> {
+    let code = BitVec::from_bytes(v);
     let mut plain = BitVec::with_capacity(code.len() / n);
-    let mut buffer = vec![0 as u8; n];
+    let mut report = Report::new(code.len());
+    let mut buffer = vec![0u8; n];
     let mut index = 0;
     
     for bit in code.iter() {
@@ -25,24 +31,98 @@ pub fn decode(v: &Vec<u8>, n: usize) -> Vec<u8> {
         
         if index == n {
             index = 0;
+            let block = plain.len();
             let sum: u8 = buffer.iter().sum();
-            let bit = if n % 2 == 0 {
-                if sum == (n as u8)/2 { panic!("An error was detected that cannot be corrected") }
-                else if sum > (n as u8)/2 { 1 } else { 0 }
-            } else {
-                if sum > (n as u8)/2 { 1 } else { 0 }
-            };
-            plain.push(bit == 1);
+            if n.is_multiple_of(2) && sum == (n as u8)/2 {
+                report.uncorrectable_block(ErrorLocation { block, bit: block * n });
+                plain.push(false);
+                continue;
+            }
+            let bit = sum > (n as u8)/2;
+            // the copies that lost the vote were errors
+            let start = block * n;
+            report.block(buffer.iter().enumerate()
+                .filter(|&(_, &b)| (b == 1) != bit)
+                .map(|(i, _)| start + i));
+            plain.push(bit);
         }
     }
     
-    plain.to_bytes()
+    codec::decode_result(plain.to_bytes(), report)
+}
+
+/// Decodes soft bits by adding up the copies of each bit, so that the copies
+/// the demodulator was sure of outweigh the ones it wasn't. A sum of 0 is
+/// reported like a tied vote.
+pub fn decode_soft(soft: &[i8], n: usize) -> Result<DecodeOutput, DecodeError> {
+    let mut plain = BitVec::with_capacity(soft.len() / n);
+    let mut report = Report::new(soft.len());
+
+    for (block, copies) in soft.chunks_exact(n).enumerate() {
+        let sum: i32 = copies.iter().map(|&llr| i32::from(llr)).sum();
+        if sum == 0 {
+            report.uncorrectable_block(ErrorLocation { block, bit: block * n });
+            plain.push(false);
+            continue;
+        }
+        let bit = sum < 0;
+        let start = block * n;
+        report.block(copies.iter().enumerate()
+            .filter(|&(_, &llr)| (llr < 0) != bit)
+            .map(|(i, _)| start + i));
+        plain.push(bit);
+    }
+
+    codec::decode_result(plain.to_bytes(), report)
+}
+
+/// Repetition code: every bit is written `n` times and decoded by majority vote.
+pub struct Multiple {
+    n: usize,
 }
 
+impl Multiple {
+    /// # Panics
+    ///
+    /// Panics if `n` is not between 1 and 255.
+    pub fn new(n: usize) -> Multiple {
+        assert!((1..=255).contains(&n), "the number of repetitions must be between 1 and 255");
+        Multiple { n }
+    }
+
+    pub fn build(parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+        let n = codec::single_parameter(parameters, 1, 255)?;
+        Ok(Box::new(Multiple::new(n)))
+    }
+}
+
+impl Codec for Multiple {
+    fn name(&self) -> &'static str { "multiple" }
+
+    fn parameters(&self) -> Vec<usize> { vec![self.n] }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { encode(data, self.n) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> { decode(code, self.n) }
+
+    fn decode_soft(&self, soft: &[i8]) -> Result<DecodeOutput, DecodeError> { decode_soft(soft, self.n) }
+
+    fn rate(&self) -> f64 { 1.0 / self.n as f64 }
+
+    fn chunk_len(&self) -> usize { cmp::max(1, codec::CHUNK_BYTES / self.n) }
+
+    fn encoded_chunk_len(&self) -> usize { self.chunk_len() * self.n }
+
+    fn correction_capability(&self) -> Capability {
+        Capability { errors: (self.n - 1) / 2, block: self.n, unit: Unit::Bit }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use soft;
+    use std::ops::Range;
     
     #[test]
     fn inverse() {
@@ -52,11 +132,11 @@ mod tests {
         let test4: Vec<u8> = vec![0,0,0];
         let test5: Vec<u8> = vec![128,32,2,4];
         
-        assert_eq!(decode(&encode(&test1, 3), 3), test1);
-        assert_eq!(decode(&encode(&test2, 17), 17), test2);
-        assert_eq!(decode(&encode(&test3, 9), 9), test3);
-        assert_eq!(decode(&encode(&test4, 3), 3), test4);
-        assert_eq!(decode(&encode(&test5, 5), 5), test5);
+        assert_eq!(decode(&encode(&test1, 3), 3).unwrap().data, test1);
+        assert_eq!(decode(&encode(&test2, 17), 17).unwrap().data, test2);
+        assert_eq!(decode(&encode(&test3, 9), 9).unwrap().data, test3);
+        assert_eq!(decode(&encode(&test4, 3), 3).unwrap().data, test4);
+        assert_eq!(decode(&encode(&test5, 5), 5).unwrap().data, test5);
     }
 
     #[test]
@@ -76,16 +156,55 @@ mod tests {
         let test2: Vec<u8> = vec![4,8,2,129,127,254,253,255];
         let test3: Vec<u8> = vec![127,1,2,4,8,16,32,64];
         
-        assert_eq!(decode(&test1, 4), vec![15]);
-        assert_eq!(decode(&test2, 8), vec![15]);
-        assert_eq!(decode(&test3, 8), vec![128]);
+        assert_eq!(decode(&test1, 4).unwrap().data, vec![15]);
+        assert_eq!(decode(&test2, 8).unwrap().data, vec![15]);
+        assert_eq!(decode(&test3, 8).unwrap().data, vec![128]);
     }
     
     #[test]
-    #[should_panic]
     fn error_detection() {
         let test: Vec<u8> = vec![2,254];
-        decode(&test, 2);
+        match decode(&test, 2) {
+            Err(DecodeError::Uncorrectable { locations, output }) => {
+                assert_eq!(locations, vec![ErrorLocation { block: 3, bit: 6 }, ErrorLocation { block: 7, bit: 14 }]);
+                assert_eq!(output.data, vec![14]);
+            }
+            other => panic!("expected an uncorrectable error, not {:?}", other),
+        }
+    }
+
+    #[test]
+    fn soft_decoding() {
+        let test: Vec<u8> = vec![17, 8, 254, 255, 2, 129];
+        let mut code = encode(&test, 3);
+        code[1] ^= 0x24;
+        code[4] ^= 0x81;
+        assert_eq!(decode_soft(&soft::from_hard(&code), 3), decode(&code, 3));
+
+        // one sure copy outweighs two unsure ones
+        let output = decode_soft(&[-100, 20, 30, 5, 5, -90], 3).unwrap();
+        assert_eq!(output.data, vec![0b1100_0000]);
+        assert_eq!(output.report.corrected_bits, vec![1, 2, 3, 4]);
+        // and breaks what would be a tie
+        assert_eq!(decode_soft(&[10, -3], 2).unwrap().data, vec![0]);
+        assert!(decode_soft(&[10, -10], 2).is_err());
+    }
+
+    #[test]
+    fn erasures() {
+        let test: Vec<u8> = vec![0x0F, 0xA5];
+        let codec = Multiple::new(16);
+        let mut code = codec.encode(&test);
+        // the first half of the copies of every bit
+        let erasures: Vec<Range<usize>> = (0..code.len()).step_by(2).map(|i| i..i + 1).collect();
+        for range in &erasures {
+            code[range.start] = 0;
+        }
+        assert!(codec.decode(&code).is_err());
+        assert_eq!(codec.decode_with_erasures(&code, &erasures).unwrap().data, test);
+        // with every copy gone there is nothing to go on
+        let first = 0..2;
+        assert!(codec.decode_with_erasures(&code, &[first]).is_err());
     }
 
 }
@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use codec::Codec;
+
+// Codewords are independent, and so are the chunks codecs are streamed in, so
+// chunks can be encoded and decoded on separate threads. Each batch of chunks
+// is shared between a pool of threads that take the next chunk until there are
+// none left, and the results are put back in order, so the output is the same
+// whatever the number of threads.
+
+// The number of chunks each thread is given at a time when streaming, so that
+// starting threads takes a negligible share of the time.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// The number of chunks to stream at a time with `threads` threads.
+pub fn batch(threads: usize) -> usize {
+    if threads > 1 { threads * CHUNKS_PER_THREAD } else { 1 }
+}
+
+/// Calls `f` on every piece, using up to `threads` threads, and returns the results in order.
+pub fn map<'a, T, F>(pieces: &[&'a [u8]], threads: usize, f: F) -> Vec<T>
+    where T: Send, F: Fn(&'a [u8]) -> T + Sync
+{
+    if threads <= 1 || pieces.len() <= 1 {
+        return pieces.iter().map(|&piece| f(piece)).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(pieces.len()));
+    thread::scope(|scope| {
+        for _ in 0..threads.min(pieces.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                match pieces.get(i) {
+                    Some(&piece) => {
+                        let result = f(piece);
+                        results.lock().expect("no thread panics holding the lock").push((i, result));
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().expect("no thread panics holding the lock");
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Encodes a whole number of chunks with `codec`, using up to `threads` threads.
+/// The output is the same as that of `codec.encode_chunk(data)`.
+pub fn encode_chunks(codec: &dyn Codec, data: &[u8], threads: usize) -> Vec<u8> {
+    let chunk = codec.chunk_len();
+    let chunks = data.len() / chunk;
+    if threads <= 1 || chunks <= 1 {
+        return codec.encode_chunk(data);
+    }
+    let per_piece = chunks.div_ceil(threads) * chunk;
+    let pieces: Vec<&[u8]> = data.chunks(per_piece).collect();
+    map(&pieces, threads, |piece| codec.encode_chunk(piece)).concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec;
+
+    #[test]
+    fn in_order() {
+        let data: Vec<u8> = (0..100).collect();
+        let pieces: Vec<&[u8]> = data.chunks(3).collect();
+        let sums = |threads| map(&pieces, threads, |piece| piece.iter().map(|&b| u32::from(b)).sum::<u32>());
+        assert_eq!(sums(1), sums(8));
+        assert_eq!(sums(8).len(), 34);
+        assert_eq!(map(&[], 4, |piece| piece.len()), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn same_as_one_thread() {
+        for &(name, n) in &[("multiple", 3), ("hamming", 4), ("reed-solomon", 16), ("extended-hamming", 3)] {
+            let codec = codec::build(name, &[n]).unwrap();
+            let data: Vec<u8> = (0..codec.chunk_len() * 7).map(|i| (i * 157 % 251) as u8).collect();
+            let expected = codec.encode_chunk(&data);
+            for threads in 1..=9 {
+                assert_eq!(encode_chunks(codec.as_ref(), &data, threads), expected, "{} with {} threads", name, threads);
+            }
+        }
+    }
+}
@@ -0,0 +1,852 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use gf256;
+use lt::PacketError;
+use rfc6330::{self, MAX_EXTENDED_SYMBOLS, SYSTEMATIC};
+
+// RaptorQ (RFC 6330) is a fountain code like LT, but with a precode: the
+// source symbols are first expanded into intermediate symbols with a few LDPC
+// and HDPC check symbols, and the encoded symbols are made from those. The
+// checks fill in the few source symbols that an LT code alone would leave
+// undecoded, so almost any K encoded symbols of a block of K source symbols
+// recover it, rather than the 7 to 40% more that LT needs for 4000 to 100.
+//
+// The code is systematic: the first K encoded symbols are the source symbols
+// themselves, and repair symbols follow. To get there, the intermediate
+// symbols are solved for from the source symbols, as if decoding them.
+//
+// This follows the RFC closely enough to interoperate with other
+// implementations: the object transmission information (OTI) says how the
+// object was split into source blocks and sub-blocks, each packet starts with
+// the source block number and encoding symbol id of its symbol, and the
+// encoded symbols are those the RFC defines. Repair symbols are numbered from
+// K, as in the RFC, which some implementations don't do for blocks whose K
+// isn't one of the sizes in its table. Packets carry no checksum, as the RFC
+// leaves catching damage to the transport.
+//
+// Decoding is by inactivation: the sparse binary equations are solved one
+// unknown at a time like LT peeling, and when that gets stuck an unknown is
+// set aside, or inactivated, to be solved with the dense HDPC equations by
+// Gaussian elimination at the end. There are few of those, so this is far
+// faster than eliminating the whole system.
+
+/// The length of the source block number and encoding symbol id at the start
+/// of each packet.
+pub const PAYLOAD_ID_LEN: usize = 4;
+/// The length of the serialized OTI.
+pub const OTI_LEN: usize = 12;
+/// The most source symbols `Oti::for_length` puts in a block, which keeps
+/// decoding quick. Other encoders can use blocks of up to 56403 symbols.
+pub const DEFAULT_BLOCK_SYMBOLS: usize = 8192;
+/// The largest object the OTI allows, in section 4.4.1.2.
+pub const MAX_TRANSFER_LENGTH: u64 = 946_270_874_880;
+/// Encoding symbol ids are 24 bits.
+pub const MAX_SYMBOL_ID: u32 = (1 << 24) - 1;
+
+/// The object transmission information of section 3.3: what the receiver
+/// needs to know about an object to decode it, sent once rather than in every
+/// packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Oti {
+    /// The length of the object in bytes, F.
+    pub transfer_length: u64,
+    /// The size of an encoding symbol in bytes, T.
+    pub symbol_size: u16,
+    /// Z.
+    pub source_blocks: u8,
+    /// N, the number of parts each symbol is split into.
+    pub sub_blocks: u16,
+    /// Al; symbols and sub-symbols are multiples of this many bytes.
+    pub alignment: u8,
+}
+
+// Partition[I, J] of section 4.4.1.2: splits I into J parts as evenly as
+// possible, JL of IL and JS of IS.
+fn partition(i: usize, j: usize) -> (usize, usize, usize, usize) {
+    let (large, small) = (i.div_ceil(j), i / j);
+    let large_count = i - small * j;
+    (large, small, large_count, j - large_count)
+}
+
+impl Oti {
+    pub fn new(transfer_length: u64, symbol_size: u16, source_blocks: u8, sub_blocks: u16, alignment: u8)
+        -> Result<Oti, String>
+    {
+        let oti = Oti { transfer_length, symbol_size, source_blocks, sub_blocks, alignment };
+        if transfer_length == 0 {
+            return Err("there is no data".to_string());
+        }
+        if transfer_length > MAX_TRANSFER_LENGTH {
+            return Err(format!("the data is longer than the {} bytes RaptorQ allows", MAX_TRANSFER_LENGTH));
+        }
+        if alignment == 0 || symbol_size == 0 || !symbol_size.is_multiple_of(u16::from(alignment)) {
+            return Err("the symbol size must be a multiple of the alignment".to_string());
+        }
+        if sub_blocks == 0 || sub_blocks > symbol_size / u16::from(alignment) {
+            return Err("there must be at least one sub-block, and no more than aligned parts of a symbol".to_string());
+        }
+        if source_blocks == 0 || oti.symbols() < usize::from(source_blocks) {
+            return Err("there must be at least one source block, and no more than symbols".to_string());
+        }
+        if oti.symbols().div_ceil(usize::from(source_blocks)) > MAX_EXTENDED_SYMBOLS {
+            return Err(format!("there must be no more than {} symbols in a source block", MAX_EXTENDED_SYMBOLS));
+        }
+        Ok(oti)
+    }
+
+    /// The OTI for `transfer_length` bytes in symbols of `symbol_size`, with
+    /// source blocks of up to `DEFAULT_BLOCK_SYMBOLS` symbols, or more if
+    /// that would take over 255 blocks, and no sub-blocks.
+    pub fn for_length(transfer_length: u64, symbol_size: u16) -> Result<Oti, String> {
+        let symbols = (transfer_length as usize).div_ceil(cmp::max(1, usize::from(symbol_size)));
+        let blocks = symbols.div_ceil(DEFAULT_BLOCK_SYMBOLS).clamp(1, 255);
+        Oti::new(transfer_length, symbol_size, blocks as u8, 1, 1)
+    }
+
+    pub fn to_bytes(&self) -> [u8; OTI_LEN] {
+        let length = self.transfer_length.to_be_bytes();
+        let symbol_size = self.symbol_size.to_be_bytes();
+        let sub_blocks = self.sub_blocks.to_be_bytes();
+        // the common part is the length in 40 bits, a reserved byte and the
+        // symbol size; the scheme-specific part is the rest
+        [length[3], length[4], length[5], length[6], length[7], 0, symbol_size[0], symbol_size[1],
+            self.source_blocks, sub_blocks[0], sub_blocks[1], self.alignment]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Oti, String> {
+        if bytes.len() < OTI_LEN {
+            return Err("the object transmission information is cut short".to_string());
+        }
+        let length = bytes[..5].iter().fold(0, |length, &b| length << 8 | u64::from(b));
+        Oti::new(length, u16::from_be_bytes([bytes[6], bytes[7]]), bytes[8],
+            u16::from_be_bytes([bytes[9], bytes[10]]), bytes[11])
+    }
+
+    /// The number of source symbols in all, Kt.
+    pub fn symbols(&self) -> usize {
+        (self.transfer_length as usize).div_ceil(usize::from(self.symbol_size))
+    }
+
+    /// The number of source symbols in a source block.
+    pub fn block_symbols(&self, block: usize) -> usize {
+        let (large, small, large_count, _) = partition(self.symbols(), usize::from(self.source_blocks));
+        if block < large_count { large } else { small }
+    }
+
+    // The bytes of the object a source block covers.
+    fn block_bytes(&self, block: usize) -> Range<usize> {
+        let start = (0..block).map(|b| self.block_symbols(b)).sum::<usize>() * usize::from(self.symbol_size);
+        let end = start + self.block_symbols(block) * usize::from(self.symbol_size);
+        start..cmp::min(end, self.transfer_length as usize)
+    }
+
+    // The sizes of the parts each symbol is split into.
+    fn sub_symbol_sizes(&self) -> Vec<usize> {
+        let alignment = usize::from(self.alignment);
+        let (large, small, large_count, small_count) =
+            partition(usize::from(self.symbol_size) / alignment, usize::from(self.sub_blocks));
+        let mut sizes = vec![large * alignment; large_count];
+        sizes.extend(vec![small * alignment; small_count]);
+        sizes
+    }
+
+    // Splits a source block, padded with zeros, into its symbols. With
+    // sub-blocks, each sub-block is a run of the block, and each symbol is
+    // made of one part from each.
+    fn split(&self, block: &[u8], k: usize) -> Vec<Vec<u8>> {
+        let mut padded = block.to_vec();
+        padded.resize(k * usize::from(self.symbol_size), 0);
+        let mut symbols = vec![Vec::with_capacity(usize::from(self.symbol_size)); k];
+        let mut offset = 0;
+        for size in self.sub_symbol_sizes() {
+            for symbol in &mut symbols {
+                symbol.extend_from_slice(&padded[offset..offset + size]);
+                offset += size;
+            }
+        }
+        symbols
+    }
+
+    // The inverse of `split`.
+    fn join(&self, symbols: &[Vec<u8>]) -> Vec<u8> {
+        let mut block = Vec::with_capacity(symbols.len() * usize::from(self.symbol_size));
+        let mut start = 0;
+        for size in self.sub_symbol_sizes() {
+            for symbol in symbols {
+                block.extend_from_slice(&symbol[start..start + size]);
+            }
+            start += size;
+        }
+        block
+    }
+}
+
+/// An encoding symbol and its FEC payload id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    /// The source block number, SBN.
+    pub source_block: u8,
+    /// The encoding symbol id, ESI: below K for a source symbol.
+    pub symbol_id: u32,
+    pub symbol: Vec<u8>,
+}
+
+impl Packet {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PAYLOAD_ID_LEN + self.symbol.len());
+        bytes.push(self.source_block);
+        bytes.extend_from_slice(&self.symbol_id.to_be_bytes()[1..]);
+        bytes.extend_from_slice(&self.symbol);
+        bytes
+    }
+
+    /// Reads a packet holding a symbol of `symbol_size` bytes.
+    pub fn parse(bytes: &[u8], symbol_size: u16) -> Result<Packet, PacketError> {
+        if bytes.len() != PAYLOAD_ID_LEN + usize::from(symbol_size) {
+            return Err(PacketError::Truncated);
+        }
+        Ok(Packet {
+            source_block: bytes[0],
+            symbol_id: u32::from_be_bytes([0, bytes[1], bytes[2], bytes[3]]),
+            symbol: bytes[PAYLOAD_ID_LEN..].to_vec(),
+        })
+    }
+}
+
+// Rand[y, i, m] of section 5.3.5.1.
+fn rand(y: u32, i: u32, m: u32) -> u32 {
+    let byte = |shift: u32| ((y >> shift).wrapping_add(i) & 0xFF) as usize;
+    (rfc6330::V0[byte(0)] ^ rfc6330::V1[byte(8)] ^ rfc6330::V2[byte(16)] ^ rfc6330::V3[byte(24)]) % m
+}
+
+// Deg[v] of section 5.3.5.2.
+fn degree(v: u32, w: u32) -> u32 {
+    const F: [u32; 31] = [0, 5243, 529531, 704294, 791675, 844104, 879057, 904023, 922747, 937311, 948962, 958494,
+        966438, 973160, 978921, 983914, 988283, 992138, 995565, 998631, 1001391, 1003887, 1006157, 1008229,
+        1010129, 1011876, 1013490, 1014983, 1016370, 1017662, 1048576];
+    let d = F.partition_point(|&f| f <= v) as u32;
+    cmp::min(d, w - 2)
+}
+
+fn is_prime(n: usize) -> bool {
+    n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d))
+}
+
+// The parameters of a source block of K symbols, from section 5.3.3.3.
+#[derive(Clone, Copy, Debug)]
+struct Params {
+    k: usize,
+    /// K', the size the block is padded to.
+    extended: usize,
+    /// J(K').
+    systematic_index: u32,
+    s: usize,
+    h: usize,
+    w: usize,
+    /// L, the number of intermediate symbols.
+    l: usize,
+    /// P, the number of permanently inactivated symbols.
+    p: usize,
+    p1: usize,
+}
+
+impl Params {
+    fn new(k: usize) -> Params {
+        let row = SYSTEMATIC[SYSTEMATIC.partition_point(|row| (row.0 as usize) < k)];
+        let (extended, s, h, w) = (row.0 as usize, row.2 as usize, row.3 as usize, row.4 as usize);
+        let l = extended + s + h;
+        let p = l - w;
+        let p1 = (p..).find(|&n| is_prime(n)).expect("there are always more primes");
+        Params { k, extended, systematic_index: u32::from(row.1), s, h, w, l, p, p1 }
+    }
+
+    // The intermediate symbols the symbol with internal symbol id `x` is the
+    // sum of, from Tuple[K', X] of section 5.3.5.4 and Enc of 5.3.5.3.
+    fn indices(&self, x: u32) -> Vec<usize> {
+        let (w, p1) = (self.w as u32, self.p1 as u32);
+        let j = self.systematic_index;
+        let a = (53591 + j * 997) | 1;
+        let b = 10267 * (j + 1);
+        let y = b.wrapping_add(x.wrapping_mul(a));
+        let d = degree(rand(y, 0, 1 << 20), w);
+        let a = 1 + rand(y, 1, w - 1);
+        let mut b = rand(y, 2, w);
+        let d1 = if d < 4 { 2 + rand(x, 3, 2) } else { 2 };
+        let a1 = 1 + rand(x, 4, p1 - 1);
+        let mut b1 = rand(x, 5, p1);
+
+        let mut indices = Vec::with_capacity((d + d1) as usize);
+        indices.push(b as usize);
+        for _ in 1..d {
+            b = (b + a) % w;
+            indices.push(b as usize);
+        }
+        while b1 as usize >= self.p {
+            b1 = (b1 + a1) % p1;
+        }
+        indices.push(self.w + b1 as usize);
+        for _ in 1..d1 {
+            b1 = (b1 + a1) % p1;
+            while b1 as usize >= self.p {
+                b1 = (b1 + a1) % p1;
+            }
+            indices.push(self.w + b1 as usize);
+        }
+        indices
+    }
+
+    // The internal symbol id of an encoding symbol id: repair symbols skip the
+    // padding symbols.
+    fn internal_id(&self, symbol_id: u32) -> u32 {
+        if (symbol_id as usize) < self.k { symbol_id } else { symbol_id + (self.extended - self.k) as u32 }
+    }
+
+    // The LDPC equations of section 5.3.3.3, which sum to zero.
+    fn ldpc_rows(&self) -> Vec<Vec<usize>> {
+        let mut rows = vec![Vec::new(); self.s];
+        for i in 0..self.w - self.s {
+            let a = 1 + i / self.s;
+            let mut b = i % self.s;
+            for _ in 0..3 {
+                rows[b].push(i);
+                b = (b + a) % self.s;
+            }
+        }
+        for (i, row) in rows.iter_mut().enumerate() {
+            row.push(self.w - self.s + i);
+            row.push(self.w + i % self.p);
+            row.push(self.w + (i + 1) % self.p);
+        }
+        rows
+    }
+
+    // The HDPC equations of section 5.3.3.3, with coefficients in GF(256).
+    fn hdpc_rows(&self) -> Vec<Vec<u8>> {
+        let width = self.extended + self.s;
+        let mut rows = vec![vec![0; self.l]; self.h];
+        // MT times GAMMA, built from the last column back
+        for (i, row) in rows.iter_mut().enumerate() {
+            row[width - 1] = gf256::exp(i);
+        }
+        for j in (0..width - 1).rev() {
+            for row in &mut rows {
+                row[j] = gf256::mul(row[j + 1], 2);
+            }
+            let i1 = rand(j as u32 + 1, 6, self.h as u32) as usize;
+            let i2 = (i1 + rand(j as u32 + 1, 7, self.h as u32 - 1) as usize + 1) % self.h;
+            rows[i1][j] ^= 1;
+            rows[i2][j] ^= 1;
+        }
+        for (i, row) in rows.iter_mut().enumerate() {
+            row[width + i] = 1;
+        }
+        rows
+    }
+
+    // Solves for the intermediate symbols from the symbols with the given
+    // internal ids, or gives up if they don't determine them.
+    fn solve(&self, symbols: Vec<(u32, Vec<u8>)>, symbol_size: usize) -> Option<Vec<Vec<u8>>> {
+        let mut rows: Vec<Vec<usize>> = self.ldpc_rows();
+        let mut values = vec![vec![0; symbol_size]; self.s];
+        // the padding symbols are known to be zero
+        for x in self.k..self.extended {
+            rows.push(self.indices(x as u32));
+            values.push(vec![0; symbol_size]);
+        }
+        for (x, symbol) in symbols {
+            rows.push(self.indices(x));
+            values.push(symbol);
+        }
+        let hdpc = self.hdpc_rows();
+        solve(self.l, self.w, rows, values, hdpc)
+    }
+
+    // The symbol with internal id `x`, from the intermediate symbols.
+    fn symbol(&self, intermediate: &[Vec<u8>], x: u32) -> Vec<u8> {
+        let mut symbol = vec![0; intermediate[0].len()];
+        for i in self.indices(x) {
+            xor(&mut symbol, &intermediate[i]);
+        }
+        symbol
+    }
+}
+
+fn xor(into: &mut [u8], from: &[u8]) {
+    for (a, b) in into.iter_mut().zip(from) {
+        *a ^= b;
+    }
+}
+
+// Adds `c` times `from` to `into`, in GF(256).
+fn mul_add(into: &mut [u8], from: &[u8], c: u8) {
+    match c {
+        0 => {}
+        1 => xor(into, from),
+        _ => {
+            let mut table = [0; 256];
+            for (x, product) in table.iter_mut().enumerate() {
+                *product = gf256::mul(x as u8, c);
+            }
+            for (a, &b) in into.iter_mut().zip(from) {
+                *a ^= table[b as usize];
+            }
+        }
+    }
+}
+
+fn scale(symbol: &mut [u8], c: u8) {
+    if c != 1 {
+        for byte in symbol {
+            *byte = gf256::mul(*byte, c);
+        }
+    }
+}
+
+// The state of solving binary equations one unknown at a time.
+struct Peeling {
+    /// The equations each unknown is in.
+    column_rows: Vec<Vec<usize>>,
+    /// How many active unknowns each equation has left.
+    weights: Vec<usize>,
+    /// Each equation's sum over the inactivated unknowns, as bits in the order
+    /// they were inactivated in.
+    bits: Vec<Vec<u64>>,
+    inactivated: Vec<usize>,
+    is_active: Vec<bool>,
+    /// The equations that have solved an unknown.
+    used: Vec<bool>,
+    /// Equations by weight, found lazily: an equation can be in several
+    /// buckets, but only the one for its current weight counts.
+    buckets: Vec<Vec<usize>>,
+}
+
+impl Peeling {
+    fn new(l: usize, rows: &[Vec<usize>]) -> Peeling {
+        let mut column_rows: Vec<Vec<usize>> = vec![Vec::new(); l];
+        for (r, row) in rows.iter().enumerate() {
+            for &c in row {
+                column_rows[c].push(r);
+            }
+        }
+        let weights: Vec<usize> = rows.iter().map(Vec::len).collect();
+        let mut buckets = vec![Vec::new(); weights.iter().cloned().max().unwrap_or(0) + 1];
+        for (r, &weight) in weights.iter().enumerate() {
+            buckets[weight].push(r);
+        }
+        Peeling {
+            column_rows,
+            weights,
+            bits: vec![Vec::new(); rows.len()],
+            inactivated: Vec::new(),
+            is_active: vec![true; l],
+            used: vec![false; rows.len()],
+            buckets,
+        }
+    }
+
+    fn inactivate(&mut self, c: usize) {
+        let bit = self.inactivated.len();
+        self.inactivated.push(c);
+        self.is_active[c] = false;
+        for &r in &self.column_rows[c] {
+            if !self.used[r] {
+                if self.bits[r].len() <= bit / 64 {
+                    self.bits[r].resize(bit / 64 + 1, 0);
+                }
+                self.bits[r][bit / 64] ^= 1 << (bit % 64);
+                self.weights[r] -= 1;
+                self.buckets[self.weights[r]].push(r);
+            }
+        }
+    }
+
+    // An unused equation with the fewest active unknowns, but at least one.
+    fn lightest(&mut self) -> Option<usize> {
+        for weight in 1..self.buckets.len() {
+            while let Some(r) = self.buckets[weight].pop() {
+                if !self.used[r] && self.weights[r] == weight {
+                    return Some(r);
+                }
+            }
+        }
+        None
+    }
+
+    fn has_bit(&self, r: usize, bit: usize) -> bool {
+        self.bits[r].get(bit / 64).is_some_and(|word| word >> (bit % 64) & 1 == 1)
+    }
+}
+
+// Solves for `l` unknowns given binary equations, each the unknowns it sums
+// and the value of the sum, and dense equations over GF(256). The unknowns
+// from `inactive` on start out inactivated.
+fn solve(l: usize, inactive: usize, rows: Vec<Vec<usize>>, mut values: Vec<Vec<u8>>, dense: Vec<Vec<u8>>)
+    -> Option<Vec<Vec<u8>>>
+{
+    let mut peeling = Peeling::new(l, &rows);
+    for c in inactive..l {
+        peeling.inactivate(c);
+    }
+    let mut pivots: Vec<(usize, usize)> = Vec::new();
+    while let Some(r) = peeling.lightest() {
+        // solve the equation for one of its unknowns, and set the rest aside
+        let mut active = rows[r].iter().cloned().filter(|&c| peeling.is_active[c]);
+        let pivot = active.next().expect("the equation has an active unknown");
+        let others: Vec<usize> = active.collect();
+        for c in others {
+            peeling.inactivate(c);
+        }
+        peeling.used[r] = true;
+        peeling.is_active[pivot] = false;
+        let (row_bits, value) = (peeling.bits[r].clone(), values[r].clone());
+        for i in 0..peeling.column_rows[pivot].len() {
+            let q = peeling.column_rows[pivot][i];
+            if !peeling.used[q] {
+                let bits = &mut peeling.bits[q];
+                if bits.len() < row_bits.len() {
+                    bits.resize(row_bits.len(), 0);
+                }
+                for (a, b) in bits.iter_mut().zip(&row_bits) {
+                    *a ^= b;
+                }
+                xor(&mut values[q], &value);
+                peeling.weights[q] -= 1;
+                peeling.buckets[peeling.weights[q]].push(q);
+            }
+        }
+        pivots.push((r, pivot));
+    }
+    // whatever no row could solve is left to the dense equations
+    for c in 0..l {
+        if peeling.is_active[c] {
+            peeling.inactivate(c);
+        }
+    }
+
+    // the equations left over, over the inactivated unknowns alone
+    let inactivated = peeling.inactivated.clone();
+    let u = inactivated.len();
+    let mut matrix: Vec<Vec<u8>> = Vec::new();
+    let mut rhs: Vec<Vec<u8>> = Vec::new();
+    for r in (0..rows.len()).filter(|&r| !peeling.used[r]) {
+        matrix.push((0..u).map(|bit| peeling.has_bit(r, bit) as u8).collect());
+        rhs.push(values[r].clone());
+    }
+    let symbol_size = values.first().map_or(0, Vec::len);
+    for coefficients in dense {
+        let mut row: Vec<u8> = inactivated.iter().map(|&c| coefficients[c]).collect();
+        let mut value = vec![0; symbol_size];
+        for &(r, pivot) in &pivots {
+            let c = coefficients[pivot];
+            if c != 0 {
+                mul_add(&mut value, &values[r], c);
+                for (bit, coefficient) in row.iter_mut().enumerate() {
+                    if peeling.has_bit(r, bit) {
+                        *coefficient ^= c;
+                    }
+                }
+            }
+        }
+        matrix.push(row);
+        rhs.push(value);
+    }
+
+    // Gauss-Jordan elimination over GF(256)
+    for col in 0..u {
+        let pivot = (col..matrix.len()).find(|&r| matrix[r][col] != 0)?;
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let inverse = gf256::inverse(matrix[col][col]);
+        scale(&mut matrix[col], inverse);
+        scale(&mut rhs[col], inverse);
+        let (row, value) = (matrix[col].clone(), rhs[col].clone());
+        for r in (0..matrix.len()).filter(|&r| r != col) {
+            let c = matrix[r][col];
+            if c != 0 {
+                mul_add(&mut matrix[r], &row, c);
+                mul_add(&mut rhs[r], &value, c);
+            }
+        }
+    }
+
+    let mut solution = vec![Vec::new(); l];
+    for (bit, &c) in inactivated.iter().enumerate() {
+        solution[c] = rhs[bit].clone();
+    }
+    for (r, pivot) in pivots {
+        let mut value = std::mem::take(&mut values[r]);
+        for (bit, &c) in inactivated.iter().enumerate() {
+            if peeling.has_bit(r, bit) {
+                xor(&mut value, &solution[c]);
+            }
+        }
+        solution[pivot] = value;
+    }
+    Some(solution)
+}
+
+struct SourceBlock {
+    params: Params,
+    intermediate: Vec<Vec<u8>>,
+    source: Vec<Vec<u8>>,
+}
+
+/// Makes the encoding symbols of an object.
+pub struct Encoder {
+    oti: Oti,
+    blocks: Vec<SourceBlock>,
+}
+
+impl Encoder {
+    /// # Panics
+    ///
+    /// Panics if the OTI isn't for data of this length.
+    pub fn new(data: &[u8], oti: Oti) -> Encoder {
+        assert_eq!(data.len() as u64, oti.transfer_length, "the OTI is for data of another length");
+        let symbol_size = usize::from(oti.symbol_size);
+        let blocks = (0..usize::from(oti.source_blocks)).map(|block| {
+            let params = Params::new(oti.block_symbols(block));
+            let source = oti.split(&data[oti.block_bytes(block)], params.k);
+            let symbols = source.iter().cloned().enumerate().map(|(x, symbol)| (x as u32, symbol)).collect();
+            let intermediate = params.solve(symbols, symbol_size).expect("the source symbols always determine the rest");
+            SourceBlock { params, intermediate, source }
+        }).collect();
+        Encoder { oti, blocks }
+    }
+
+    pub fn oti(&self) -> Oti {
+        self.oti
+    }
+
+    /// The packet holding an encoding symbol of a source block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such block or the id doesn't fit in 24 bits.
+    pub fn packet(&self, block: usize, symbol_id: u32) -> Packet {
+        assert!(symbol_id <= MAX_SYMBOL_ID, "encoding symbol ids are 24 bits");
+        let source_block = &self.blocks[block];
+        let symbol = match source_block.source.get(symbol_id as usize) {
+            Some(symbol) => symbol.clone(),
+            None => source_block.params.symbol(&source_block.intermediate, source_block.params.internal_id(symbol_id)),
+        };
+        Packet { source_block: block as u8, symbol_id, symbol }
+    }
+
+    /// The source symbols of every block, followed by `overhead` times as many
+    /// repair symbols, taking turns between the blocks.
+    pub fn packets(&self, overhead: f64) -> impl Iterator<Item = Packet> + '_ {
+        let counts: Vec<usize> = self.blocks.iter()
+            .map(|block| cmp::min(MAX_SYMBOL_ID as usize + 1, block.params.k + (block.params.k as f64 * overhead).ceil() as usize))
+            .collect();
+        let most = counts.iter().cloned().max().unwrap_or(0);
+        (0..most).flat_map(move |id| {
+            counts.iter().enumerate()
+                .filter(|&(_, &count)| id < count)
+                .map(|(block, _)| self.packet(block, id as u32))
+                .collect::<Vec<_>>()
+        })
+    }
+}
+
+struct BlockDecoder {
+    params: Params,
+    received: BTreeMap<u32, Vec<u8>>,
+    source: Option<Vec<Vec<u8>>>,
+}
+
+impl BlockDecoder {
+    fn decode(&mut self, symbol_size: usize) {
+        if self.source.is_some() || self.received.len() < self.params.k {
+            return;
+        }
+        let params = self.params;
+        if (0..params.k as u32).all(|x| self.received.contains_key(&x)) {
+            self.source = Some(self.received.range(..params.k as u32).map(|(_, symbol)| symbol.clone()).collect());
+            return;
+        }
+        let symbols = self.received.iter().map(|(&id, symbol)| (params.internal_id(id), symbol.clone())).collect();
+        if let Some(intermediate) = params.solve(symbols, symbol_size) {
+            self.source = Some((0..params.k as u32).map(|x| match self.received.get(&x) {
+                Some(symbol) => symbol.clone(),
+                None => params.symbol(&intermediate, x),
+            }).collect());
+        }
+    }
+}
+
+/// Collects packets until the object can be recovered.
+pub struct Decoder {
+    oti: Oti,
+    blocks: Vec<BlockDecoder>,
+    received: usize,
+}
+
+impl Decoder {
+    pub fn new(oti: Oti) -> Decoder {
+        let blocks = (0..usize::from(oti.source_blocks))
+            .map(|block| BlockDecoder { params: Params::new(oti.block_symbols(block)), received: BTreeMap::new(), source: None })
+            .collect();
+        Decoder { oti, blocks, received: 0 }
+    }
+
+    /// Takes a packet. Repeated packets are ignored.
+    pub fn add(&mut self, packet: Packet) -> Result<(), PacketError> {
+        if packet.symbol.len() != usize::from(self.oti.symbol_size) {
+            return Err(PacketError::Truncated);
+        }
+        let block = self.blocks.get_mut(usize::from(packet.source_block)).ok_or(PacketError::Mismatched)?;
+        if packet.symbol_id > MAX_SYMBOL_ID {
+            return Err(PacketError::Mismatched);
+        }
+        self.received += 1;
+        block.received.insert(packet.symbol_id, packet.symbol);
+        Ok(())
+    }
+
+    /// The number of packets taken.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// How many source blocks have been recovered, out of how many.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.blocks.iter().filter(|block| block.source.is_some()).count(), self.blocks.len())
+    }
+
+    /// Recovers the object, if there are enough packets. Each source block
+    /// needs at least as many as it has source symbols, and almost always no
+    /// more than a couple beyond that.
+    pub fn decode(&mut self) -> Option<Vec<u8>> {
+        let symbol_size = usize::from(self.oti.symbol_size);
+        for block in &mut self.blocks {
+            block.decode(symbol_size);
+        }
+        let mut data = Vec::with_capacity(self.oti.transfer_length as usize);
+        for block in &self.blocks {
+            data.extend(self.oti.join(block.source.as_ref()?));
+        }
+        data.truncate(self.oti.transfer_length as usize);
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rng::Rng;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 37 + 5) % 256) as u8).collect()
+    }
+
+    #[test]
+    fn oti() {
+        let oti = Oti::new(1_000_003, 1024, 3, 4, 8).unwrap();
+        assert_eq!(oti.to_bytes(), [0, 0, 0x0F, 0x42, 0x43, 0, 4, 0, 3, 0, 4, 8]);
+        assert_eq!(Oti::from_bytes(&oti.to_bytes()), Ok(oti));
+        assert_eq!((oti.symbols(), oti.block_symbols(0), oti.block_symbols(2)), (977, 326, 325));
+        assert_eq!(oti.sub_symbol_sizes(), vec![256; 4]);
+        assert_eq!(Oti::new(1000, 7, 1, 1, 1).unwrap().sub_symbol_sizes(), vec![7]);
+
+        assert!(Oti::new(0, 1024, 1, 1, 1).is_err());
+        assert!(Oti::new(1000, 1020, 1, 1, 8).is_err());
+        assert!(Oti::new(1000, 1024, 2, 1, 1).is_err());
+        assert!(Oti::new(100_000, 1, 1, 1, 1).is_err());
+        assert_eq!(Oti::for_length(100_000_000, 1024).unwrap().source_blocks, 12);
+        assert_eq!(Oti::for_length(100, 1024).unwrap().source_blocks, 1);
+    }
+
+    #[test]
+    fn parameters() {
+        let params = Params::new(11);
+        assert_eq!((params.extended, params.s, params.h, params.w, params.l, params.p, params.p1),
+            (12, 7, 10, 19, 29, 10, 11));
+        assert_eq!(degree(0, 100), 1);
+        assert_eq!(degree(5243, 100), 2);
+        assert_eq!(degree(1048575, 100), 30);
+        assert_eq!(degree(1048575, 20), 18);
+    }
+
+    // Encoding symbols made with the `raptorq` crate, version 1.7.0, for the
+    // data `sample(40)` (and `sample(44)`) in symbols of 4 bytes, from the
+    // repair packets of its `SourceBlockEncoder` for each OTI. That crate numbers
+    // repair symbols from K' rather than from K as the RFC does, so the
+    // ids here are its ids less K' - K: the same for 40 bytes, where K = K' =
+    // 10, and one less for 44, where K = 11 and K' = 12.
+    const VECTORS: [([u8; OTI_LEN], u32, [u8; 4]); 6] = [
+        ([0, 0, 0, 0, 40, 0, 0, 4, 1, 0, 1, 1], 10, [110, 52, 75, 222]),
+        ([0, 0, 0, 0, 40, 0, 0, 4, 1, 0, 1, 1], 13, [221, 21, 63, 51]),
+        ([0, 0, 0, 0, 40, 0, 0, 4, 1, 0, 2, 2], 10, [211, 71, 192, 203]),
+        ([0, 0, 0, 0, 40, 0, 0, 4, 1, 0, 2, 2], 12, [91, 178, 184, 30]),
+        ([0, 0, 0, 0, 44, 0, 0, 4, 1, 0, 1, 1], 11, [92, 169, 187, 89]),
+        ([0, 0, 0, 0, 44, 0, 0, 4, 1, 0, 1, 1], 14, [170, 141, 36, 15]),
+    ];
+
+    #[test]
+    fn interoperability() {
+        for &(oti, symbol_id, symbol) in &VECTORS {
+            let oti = Oti::from_bytes(&oti).unwrap();
+            let test = sample(oti.transfer_length as usize);
+            let encoder = Encoder::new(&test, oti);
+            let packet = encoder.packet(0, symbol_id);
+            assert_eq!(packet.symbol, symbol, "symbol {} of {:?}", symbol_id, oti);
+            assert_eq!(Packet::parse(&packet.to_bytes(), 4), Ok(packet));
+        }
+        // the source symbols are the data
+        let oti = Oti::from_bytes(&VECTORS[2].0).unwrap();
+        let encoder = Encoder::new(&sample(40), oti);
+        assert_eq!(encoder.packet(0, 1).symbol, [sample(40)[2], sample(40)[3], sample(40)[22], sample(40)[23]]);
+    }
+
+    #[test]
+    fn recovery_from_any_packets() {
+        let mut rng = Rng::new(24);
+        for &(len, symbol_size, blocks, sub_blocks) in &[(1, 4, 1, 1), (1000, 16, 1, 1), (40_000, 64, 3, 4),
+            (100_000, 10, 1, 1)]
+        {
+            let test: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            let alignment = if symbol_size % 2 == 0 { 2 } else { 1 };
+            let oti = Oti::new(len as u64, symbol_size, blocks, sub_blocks, alignment).unwrap();
+            let encoder = Encoder::new(&test, oti);
+            // keep just two more packets of each block than it has source symbols
+            let mut packets: Vec<Packet> = encoder.packets(1.0).collect();
+            for i in (1..packets.len()).rev() {
+                packets.swap(i, rng.below(i + 1));
+            }
+            let mut kept = vec![0; usize::from(blocks)];
+            let mut decoder = Decoder::new(oti);
+            for packet in packets {
+                let block = usize::from(packet.source_block);
+                if kept[block] < oti.block_symbols(block) + 2 {
+                    kept[block] += 1;
+                    decoder.add(packet).unwrap();
+                }
+            }
+            assert_eq!(decoder.decode(), Some(test), "{} bytes in symbols of {}", len, symbol_size);
+        }
+    }
+
+    #[test]
+    fn too_few_packets() {
+        let test = sample(4000);
+        let oti = Oti::for_length(4000, 40).unwrap();
+        let encoder = Encoder::new(&test, oti);
+        let mut decoder = Decoder::new(oti);
+        for id in 50..149 {
+            decoder.add(encoder.packet(0, id)).unwrap();
+        }
+        assert_eq!(decoder.decode(), None);
+        assert_eq!(decoder.progress(), (0, 1));
+        decoder.add(encoder.packet(0, 149)).unwrap();
+        decoder.add(encoder.packet(0, 150)).unwrap();
+        assert_eq!(decoder.decode(), Some(test));
+
+        assert_eq!(decoder.add(Packet { source_block: 1, symbol_id: 0, symbol: vec![0; 40] }),
+            Err(PacketError::Mismatched));
+        assert_eq!(decoder.add(Packet { source_block: 0, symbol_id: 0, symbol: vec![0; 4] }),
+            Err(PacketError::Truncated));
+    }
+}
@@ -0,0 +1,378 @@
+use std::ops::Range;
+
+use codec::{self, Capability, Codec, DecodeError, DecodeOutput, ErrorLocation, Report, Unit};
+use gf256;
+
+// Reed-Solomon codes over bytes. The data is split into blocks of at most
+// `BLOCK_LEN - nsym` bytes and `nsym` parity bytes are appended to each; the
+// last block is shortened rather than padded, so the code is exactly as long
+// as it needs to be. A block with up to `nsym / 2` damaged bytes anywhere in
+// it can be corrected, or up to `nsym` bytes whose positions are known
+// (erasures), or any mix where `2 * errors + erasures <= nsym`.
+
+pub const BLOCK_LEN: usize = 255;
+
+fn generator(nsym: usize) -> Vec<u8> {
+    let mut g = vec![1];
+    for i in 0..nsym {
+        g = gf256::poly_mul(&g, &[1, gf256::exp(i)]);
+    }
+    g
+}
+
+fn encode_block(msg: &[u8], generator: &[u8]) -> Vec<u8> {
+    let nsym = generator.len() - 1;
+    let mut padded = msg.to_vec();
+    padded.resize(msg.len() + nsym, 0);
+    let (_, remainder) = gf256::poly_div(&padded, generator);
+    let mut block = msg.to_vec();
+    block.extend_from_slice(&remainder);
+    block
+}
+
+// The syndromes have a leading zero so that the indices line up with the
+// powers of the generator in the locator polynomial computations.
+fn syndromes(block: &[u8], nsym: usize) -> Vec<u8> {
+    let mut synd = vec![0; nsym + 1];
+    for i in 0..nsym {
+        synd[i + 1] = gf256::poly_eval(block, gf256::exp(i));
+    }
+    synd
+}
+
+// Removes the known erasures from the syndromes, so that Berlekamp-Massey
+// only has to find the unknown errors.
+fn forney_syndromes(synd: &[u8], erasures: &[usize], len: usize) -> Vec<u8> {
+    let mut fsynd = synd[1..].to_vec();
+    for &pos in erasures {
+        let x = gf256::exp(len - 1 - pos);
+        for j in 0..fsynd.len() - 1 {
+            fsynd[j] = gf256::mul(fsynd[j], x) ^ fsynd[j + 1];
+        }
+    }
+    fsynd
+}
+
+// Berlekamp-Massey
+fn error_locator(synd: &[u8], nsym: usize, erase_count: usize) -> Option<Vec<u8>> {
+    let mut err_loc = vec![1];
+    let mut old_loc = vec![1];
+
+    for i in 0..(nsym - erase_count) {
+        let mut delta = synd[i];
+        for j in 1..err_loc.len() {
+            delta ^= gf256::mul(err_loc[err_loc.len() - 1 - j], synd[i - j]);
+        }
+        old_loc.push(0);
+        if delta != 0 {
+            if old_loc.len() > err_loc.len() {
+                let new_loc = gf256::poly_scale(&old_loc, delta);
+                old_loc = gf256::poly_scale(&err_loc, gf256::inverse(delta));
+                err_loc = new_loc;
+            }
+            err_loc = gf256::poly_add(&err_loc, &gf256::poly_scale(&old_loc, delta));
+        }
+    }
+
+    let leading = err_loc.iter().take_while(|&&c| c == 0).count();
+    err_loc.drain(..leading);
+    let errs = err_loc.len() - 1;
+    if errs * 2 + erase_count > nsym {
+        return None;
+    }
+    Some(err_loc)
+}
+
+// Chien search: the roots of the locator give the error positions.
+fn find_errors(err_loc: &[u8], len: usize) -> Option<Vec<usize>> {
+    let mut reversed = err_loc.to_vec();
+    reversed.reverse();
+    let errs = err_loc.len() - 1;
+    let mut positions = Vec::with_capacity(errs);
+    for i in 0..len {
+        if gf256::poly_eval(&reversed, gf256::exp(i)) == 0 {
+            positions.push(len - 1 - i);
+        }
+    }
+    if positions.len() != errs {
+        return None;
+    }
+    Some(positions)
+}
+
+// Forney's algorithm: computes the magnitude of each error and removes it.
+fn correct_errata(block: &mut [u8], synd: &[u8], positions: &[usize]) -> Option<()> {
+    let coef_pos: Vec<usize> = positions.iter().map(|&p| block.len() - 1 - p).collect();
+
+    let mut locator = vec![1];
+    for &i in &coef_pos {
+        locator = gf256::poly_mul(&locator, &[gf256::exp(i), 1]);
+    }
+
+    let mut reversed_synd = synd.to_vec();
+    reversed_synd.reverse();
+    let mut divisor = vec![0; locator.len() + 1];
+    divisor[0] = 1;
+    let (_, evaluator) = gf256::poly_div(&gf256::poly_mul(&reversed_synd, &locator), &divisor);
+
+    let x: Vec<u8> = coef_pos.iter().map(|&p| gf256::exp(p)).collect();
+
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = gf256::inverse(xi);
+        let mut loc_prime = 1;
+        for (j, &xj) in x.iter().enumerate() {
+            if j != i {
+                loc_prime = gf256::mul(loc_prime, 1 ^ gf256::mul(xi_inv, xj));
+            }
+        }
+        if loc_prime == 0 {
+            return None;
+        }
+        let y = gf256::mul(xi, gf256::poly_eval(&evaluator, xi_inv));
+        block[positions[i]] ^= gf256::div(y, loc_prime);
+    }
+    Some(())
+}
+
+/// Corrects a single block in place. `erasures` are the positions within the
+/// block of bytes known to be bad. Returns `None` if the block has more
+/// errors than the code can correct, in which case it is left as it was.
+pub fn correct(block: &mut [u8], nsym: usize, erasures: &[usize]) -> Option<()> {
+    if erasures.len() > nsym || block.len() <= nsym {
+        return None;
+    }
+    let mut fixed = block.to_vec();
+    for &pos in erasures {
+        fixed[pos] = 0;
+    }
+
+    let synd = syndromes(&fixed, nsym);
+    if synd.iter().all(|&s| s == 0) {
+        block.copy_from_slice(&fixed);
+        return Some(());
+    }
+
+    let fsynd = forney_syndromes(&synd, erasures, fixed.len());
+    let err_loc = error_locator(&fsynd, nsym, erasures.len())?;
+    let mut positions = erasures.to_vec();
+    positions.extend(find_errors(&err_loc, fixed.len())?);
+    correct_errata(&mut fixed, &synd, &positions)?;
+
+    if syndromes(&fixed, nsym).iter().any(|&s| s != 0) {
+        return None;
+    }
+    block.copy_from_slice(&fixed);
+    Some(())
+}
+
+pub fn encode(v: &[u8], nsym: usize) -> Vec<u8> {
+    assert!(nsym > 0 && nsym < BLOCK_LEN, "the number of parity bytes must be between 1 and 254");
+    let generator = generator(nsym);
+    let mut code = Vec::with_capacity(v.len() + (v.len() / (BLOCK_LEN - nsym) + 1) * nsym);
+    for msg in v.chunks(BLOCK_LEN - nsym) {
+        code.extend_from_slice(&encode_block(msg, &generator));
+    }
+    code
+}
+
+pub fn decode(v: &[u8], nsym: usize) -> Result<DecodeOutput, DecodeError> {
+    decode_with_erasures(v, nsym, &[])
+}
+
+/// Decodes like `decode`, treating the bytes at the sorted offsets in `erasures` as unreadable.
+pub fn decode_with_erasures(v: &[u8], nsym: usize, erasures: &[usize]) -> Result<DecodeOutput, DecodeError> {
+    decode_blocks(v, nsym, |block| {
+        let first = erasures.partition_point(|&e| e < block.start);
+        erasures[first..].iter().take_while(|&&e| e < block.end).map(|&e| e - block.start).collect()
+    })
+}
+
+// Decodes each block, given the offsets of the erasures in the bytes of the code it covers.
+fn decode_blocks<F>(v: &[u8], nsym: usize, erasures: F) -> Result<DecodeOutput, DecodeError>
+    where F: Fn(Range<usize>) -> Vec<usize>
+{
+    let mut plain = Vec::with_capacity(v.len());
+    let mut report = Report::new(v.len() * 8);
+    for (n, chunk) in v.chunks(BLOCK_LEN).enumerate() {
+        // a block that is only parity can't have come from `encode`
+        if chunk.len() <= nsym {
+            break;
+        }
+        let start = n * BLOCK_LEN;
+        let local = erasures(start..start + chunk.len());
+        let mut block = chunk.to_vec();
+        // if the block can't be corrected, the data is passed through as it is
+        if correct(&mut block, nsym, &local).is_none() {
+            report.uncorrectable_block(ErrorLocation { block: n, bit: start * 8 });
+        } else {
+            report.block(chunk.iter().zip(&block).enumerate()
+                .flat_map(|(i, (&before, &after))| {
+                    let diff = before ^ after;
+                    (0..8).filter(move |b| diff & (0x80 >> b) != 0).map(move |b| (start + i) * 8 + b)
+                }));
+        }
+        plain.extend_from_slice(&block[..block.len() - nsym]);
+    }
+    codec::decode_result(plain, report)
+}
+
+/// Reed-Solomon code with `nsym` parity bytes in each block of 255 bytes.
+pub struct ReedSolomon {
+    nsym: usize,
+}
+
+impl ReedSolomon {
+    /// # Panics
+    ///
+    /// Panics if `nsym` is not between 1 and 254.
+    pub fn new(nsym: usize) -> ReedSolomon {
+        assert!(nsym > 0 && nsym < BLOCK_LEN, "the number of parity bytes must be between 1 and 254");
+        ReedSolomon { nsym }
+    }
+
+    pub fn build(parameters: &[usize]) -> Result<Box<dyn Codec>, String> {
+        let nsym = codec::single_parameter(parameters, 1, BLOCK_LEN - 1)?;
+        Ok(Box::new(ReedSolomon::new(nsym)))
+    }
+}
+
+impl Codec for ReedSolomon {
+    fn name(&self) -> &'static str { "reed-solomon" }
+
+    fn parameters(&self) -> Vec<usize> { vec![self.nsym] }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> { encode(data, self.nsym) }
+
+    fn decode(&self, code: &[u8]) -> Result<DecodeOutput, DecodeError> { decode(code, self.nsym) }
+
+    fn decode_with_erasures(&self, code: &[u8], erasures: &[Range<usize>]) -> Result<DecodeOutput, DecodeError> {
+        decode_blocks(code, self.nsym, |block| codec::erased_offsets(erasures, block))
+    }
+
+    fn rate(&self) -> f64 {
+        (BLOCK_LEN - self.nsym) as f64 / BLOCK_LEN as f64
+    }
+
+    fn chunk_len(&self) -> usize { (BLOCK_LEN - self.nsym) * (codec::CHUNK_BYTES / BLOCK_LEN) }
+
+    fn encoded_chunk_len(&self) -> usize { BLOCK_LEN * (codec::CHUNK_BYTES / BLOCK_LEN) }
+
+    fn correction_capability(&self) -> Capability {
+        Capability { errors: self.nsym / 2, block: BLOCK_LEN, unit: Unit::Byte }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn generator_roots() {
+        let g = generator(10);
+        assert_eq!(g.len(), 11);
+        for i in 0..10 {
+            assert_eq!(gf256::poly_eval(&g, gf256::exp(i)), 0);
+        }
+    }
+
+    #[test]
+    fn systematic() {
+        let test = sample(20);
+        let code = encode(&test, 4);
+        assert_eq!(code.len(), 24);
+        assert_eq!(&code[..20], &test[..]);
+    }
+
+    #[test]
+    fn inverse() {
+        for &nsym in &[1, 2, 10, 32, 254] {
+            for &len in &[0, 1, 2, 100, 221, 255, 1000] {
+                let test = sample(len);
+                assert_eq!(decode(&encode(&test, nsym), nsym).unwrap().data, test, "nsym = {}, length = {}", nsym, len);
+            }
+        }
+    }
+
+    #[test]
+    fn error_correction() {
+        let test = sample(1000);
+        let mut code = encode(&test, 10);
+        // five errors in every block, the most that can be corrected
+        for block in 0..code.len() / BLOCK_LEN {
+            for i in 0..5 {
+                code[block * BLOCK_LEN + i * 37] ^= 0x5A;
+            }
+        }
+        // and a burst of five in the shortened last block
+        let len = code.len();
+        for b in &mut code[len - 8..len - 3] {
+            *b = !*b;
+        }
+        assert_eq!(decode(&code, 10).unwrap().data, test);
+    }
+
+    #[test]
+    fn erasure_correction() {
+        let test = sample(500);
+        let mut code = encode(&test, 8);
+        // eight erasures in the first block, and six more next to two errors in the second
+        let mut erasures: Vec<usize> = (10..18).collect();
+        erasures.extend(300..306);
+        for &e in &erasures {
+            code[e] = 0;
+        }
+        code[400] ^= 1;
+        assert_eq!(decode_with_erasures(&code, 8, &erasures).unwrap().data, test);
+    }
+
+    #[test]
+    fn erased_ranges() {
+        let codec = ReedSolomon::new(8);
+        let test = sample(1000);
+        let mut code = codec.encode(&test);
+        // eight bytes of every block are unreadable, twice as many as could be corrected otherwise
+        let erasures: Vec<Range<usize>> = (0..code.len() / BLOCK_LEN).map(|block| {
+            let start = block * BLOCK_LEN + 100;
+            start..start + 8
+        }).collect();
+        for range in &erasures {
+            for b in &mut code[range.clone()] {
+                *b = 0;
+            }
+        }
+        assert!(codec.decode(&code).is_err());
+        let output = codec.decode_with_erasures(&code, &erasures).unwrap();
+        assert_eq!(output.data, test);
+        assert_eq!(output.report.corrected_blocks, erasures.len());
+    }
+
+    #[test]
+    fn error_detection() {
+        let test = sample(100);
+        let mut block = encode(&test, 4);
+        let original = block.clone();
+        for i in 0..3 {
+            block[i * 20] ^= 0xFF;
+        }
+        let damaged = block.clone();
+        assert_eq!(correct(&mut block, 4, &[]), None);
+        assert_eq!(block, damaged);
+        assert_ne!(block, original);
+
+        let mut code = encode(&sample(600), 4);
+        code[300] ^= 1;
+        code[310] ^= 1;
+        code[320] ^= 1;
+        match decode(&code, 4) {
+            Err(DecodeError::Uncorrectable { locations, output }) => {
+                assert_eq!(locations, vec![ErrorLocation { block: 1, bit: 255 * 8 }]);
+                assert_eq!(output.data.len(), 600);
+            }
+            other => panic!("expected an uncorrectable error, not {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// A small seeded pseudo-random number generator (xorshift64*). Its output is
+// part of the file format wherever it decides where data goes, so it must never
+// change.
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be zero, and similar seeds should diverge quickly
+        let mut rng = Rng { state: seed ^ 0x9E37_79B9_7F4A_7C15 };
+        if rng.state == 0 {
+            rng.state = 1;
+        }
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number in `0..n`. The bias from the modulus is negligible for the sizes used here.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let first: Vec<u64> = { let mut rng = Rng::new(7); (0..5).map(|_| rng.next_u64()).collect() };
+        let second: Vec<u64> = { let mut rng = Rng::new(7); (0..5).map(|_| rng.next_u64()).collect() };
+        assert_eq!(first, second);
+        assert_ne!(first[0], Rng::new(8).next_u64());
+        let mut rng = Rng::new(0);
+        assert!((0..1000).all(|_| rng.below(10) < 10));
+        assert!((0..1000).all(|_| { let x = rng.next_f64(); (0.0..1.0).contains(&x) }));
+    }
+}
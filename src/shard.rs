@@ -0,0 +1,641 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io::{self, Read};
+
+use crc::{self, crc32};
+use gf256;
+use reed_solomon;
+
+// Splits data into shards to be kept on separate devices, so that the data
+// survives losing whole devices: the data is cut into rows of `required`
+// stripes of `STRIPE_LEN` bytes (the last row into shorter stripes, padded with
+// zeros), and `shards - required` parity stripes are added to each row, each
+// byte of them the Reed-Solomon parity of the bytes at the same offset in the
+// data stripes. Each shard holds one stripe of every row, so any `required` of
+// the shards recover the data, whichever they are, and it can be split and
+// joined a row at a time.
+//
+// The code is systematic and linear, so each parity stripe is a fixed
+// combination of the data stripes, taken from the parity that
+// `reed_solomon::encode` gives for each unit vector. Missing data stripes are
+// recovered by inverting the rows of that matrix for the shards that are left,
+// which works for any of them since Reed-Solomon codes are MDS.
+//
+// Each shard starts with a header, so that shards can be told apart before
+// they are read any further, and each stripe is followed by its checksum, so
+// that a damaged stripe can be left out of its row and the rest of the shard
+// still used.
+
+/// The most shards the data can be split into, the length of a Reed-Solomon block.
+pub const MAX_SHARDS: usize = reed_solomon::BLOCK_LEN;
+/// The length of the stripes of every row but the last.
+pub const STRIPE_LEN: usize = 1 << 16;
+
+pub const MAGIC: [u8; 4] = *b"ECCS";
+pub const VERSION: u8 = 1;
+/// The magic, the version, the index, the number of shards and the number
+/// required, the length and checksum of the data, and a checksum of the rest.
+pub const HEADER_LEN: usize = 4 + 1 + 1 + 1 + 1 + 8 + 4 + 4;
+/// The checksum after each stripe.
+pub const STRIPE_CHECKSUM_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShardError {
+    /// It's shorter than its header says, or too short to say.
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// Its checksum doesn't match, or its header makes no sense.
+    Corrupt,
+    /// There aren't enough different shards, or intact stripes of them, to
+    /// recover the data.
+    TooFew { found: usize, required: usize },
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShardError::Truncated => write!(f, "the shard is cut short"),
+            ShardError::BadMagic => write!(f, "the input is not a shard"),
+            ShardError::UnsupportedVersion(version) => write!(f, "shard version {} is not supported", version),
+            ShardError::Corrupt => write!(f, "the shard is damaged"),
+            ShardError::TooFew { found, required } =>
+                write!(f, "only {} of the {} shards needed could be read", found, required),
+        }
+    }
+}
+
+impl error::Error for ShardError {}
+
+/// What a shard says about itself at its start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// From 0; the first `required` shards are the data itself.
+    pub index: usize,
+    pub shards: usize,
+    pub required: usize,
+    /// The length of the data.
+    pub length: u64,
+    /// The CRC-32 of the data.
+    pub checksum: u32,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&[VERSION, self.index as u8, self.shards as u8, self.required as u8]);
+        bytes[8..16].copy_from_slice(&self.length.to_be_bytes());
+        bytes[16..20].copy_from_slice(&self.checksum.to_be_bytes());
+        let crc = crc32(&bytes[..20]);
+        bytes[20..].copy_from_slice(&crc.to_be_bytes());
+        bytes
+    }
+
+    /// Reads the header at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Header, ShardError> {
+        if bytes.len() < 5 {
+            return Err(ShardError::Truncated);
+        }
+        if bytes[..4] != MAGIC {
+            return Err(ShardError::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(ShardError::UnsupportedVersion(bytes[4]));
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(ShardError::Truncated);
+        }
+        if crc32(&bytes[..20]) != u32::from_be_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]) {
+            return Err(ShardError::Corrupt);
+        }
+        let mut length = [0; 8];
+        length.copy_from_slice(&bytes[8..16]);
+        let header = Header {
+            index: bytes[5] as usize,
+            shards: bytes[6] as usize,
+            required: bytes[7] as usize,
+            length: u64::from_be_bytes(length),
+            checksum: u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]),
+        };
+        if header.required == 0 || header.required > header.shards || header.index >= header.shards {
+            return Err(ShardError::Corrupt);
+        }
+        Ok(header)
+    }
+
+    /// The length of the stripes of each row.
+    pub fn rows(&self) -> impl Iterator<Item = usize> {
+        let row = (self.required * STRIPE_LEN) as u64;
+        let last = (self.length % row).div_ceil(self.required as u64) as usize;
+        (0..self.length / row).map(|_| STRIPE_LEN).chain(Some(last).filter(|&len| len > 0))
+    }
+
+    // The length of the shard after the header, or `None` if it couldn't be held.
+    fn body_len(&self) -> Option<u64> {
+        let row = (self.required * STRIPE_LEN) as u64;
+        let last = (self.length % row).div_ceil(self.required as u64);
+        let last = if last > 0 { last + STRIPE_CHECKSUM_LEN as u64 } else { 0 };
+        (self.length / row).checked_mul((STRIPE_LEN + STRIPE_CHECKSUM_LEN) as u64)?.checked_add(last)
+    }
+
+    // What shards of the same data have in common.
+    fn data_key(&self) -> (usize, usize, u64, u32) {
+        (self.shards, self.required, self.length, self.checksum)
+    }
+}
+
+/// One of the pieces that data is split into, whole.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub header: Header,
+    /// The stripe of each row, each followed by its checksum.
+    pub body: Vec<u8>,
+}
+
+impl Shard {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.body.len());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Reads a whole shard. The checksums of the stripes are only checked when
+    /// the shard is joined, so that the intact stripes can still be used.
+    pub fn parse(bytes: &[u8]) -> Result<Shard, ShardError> {
+        let header = Header::parse(bytes)?;
+        match header.body_len() {
+            Some(len) if len == (bytes.len() - HEADER_LEN) as u64 => {}
+            Some(len) if len > (bytes.len() - HEADER_LEN) as u64 => return Err(ShardError::Truncated),
+            _ => return Err(ShardError::Corrupt),
+        }
+        Ok(Shard { header, body: bytes[HEADER_LEN..].to_vec() })
+    }
+}
+
+// The coefficients of each parity stripe, by data stripe.
+fn parity_matrix(shards: usize, required: usize) -> Vec<Vec<u8>> {
+    let nsym = shards - required;
+    let mut matrix = vec![vec![0; required]; nsym];
+    if nsym == 0 {
+        return matrix;
+    }
+    for column in 0..required {
+        let mut unit = vec![0; required];
+        unit[column] = 1;
+        let code = reed_solomon::encode(&unit, nsym);
+        for (row, &parity) in matrix.iter_mut().zip(&code[required..]) {
+            row[column] = parity;
+        }
+    }
+    matrix
+}
+
+// Adds `coefficient` times `source` to `target`.
+fn add_scaled(target: &mut [u8], source: &[u8], coefficient: u8) {
+    match coefficient {
+        0 => {}
+        1 => for (t, &s) in target.iter_mut().zip(source) {
+            *t ^= s;
+        },
+        _ => {
+            let products: Vec<u8> = (0..=255).map(|x| gf256::mul(coefficient, x)).collect();
+            for (t, &s) in target.iter_mut().zip(source) {
+                *t ^= products[s as usize];
+            }
+        }
+    }
+}
+
+// Inverts a square matrix by Gauss-Jordan elimination, or returns `None` if it's singular.
+fn invert(mut matrix: Vec<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+    let n = matrix.len();
+    let mut inverse: Vec<Vec<u8>> = (0..n).map(|i| (0..n).map(|j| (i == j) as u8).collect()).collect();
+    for column in 0..n {
+        let pivot = (column..n).find(|&row| matrix[row][column] != 0)?;
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = gf256::inverse(matrix[column][column]);
+        for j in 0..n {
+            matrix[column][j] = gf256::mul(matrix[column][j], scale);
+            inverse[column][j] = gf256::mul(inverse[column][j], scale);
+        }
+        for row in 0..n {
+            let factor = matrix[row][column];
+            if row == column || factor == 0 {
+                continue;
+            }
+            for j in 0..n {
+                matrix[row][j] ^= gf256::mul(factor, matrix[column][j]);
+                inverse[row][j] ^= gf256::mul(factor, inverse[column][j]);
+            }
+        }
+    }
+    Some(inverse)
+}
+
+// Whether a stripe matches the checksum that follows it.
+fn intact(stripe: &[u8]) -> bool {
+    let (stripe, crc) = stripe.split_at(stripe.len() - STRIPE_CHECKSUM_LEN);
+    crc32(stripe) == u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]])
+}
+
+/// The length and CRC-32 of everything `input` holds, which the headers of
+/// the shards need before any of the data is split.
+pub fn measure<R: Read + ?Sized>(input: &mut R) -> io::Result<(u64, u32)> {
+    let mut buffer = vec![0; STRIPE_LEN];
+    let (mut length, mut checksum) = (0, 0);
+    loop {
+        match input.read(&mut buffer) {
+            Ok(0) => return Ok((length, checksum)),
+            Ok(len) => {
+                length += len as u64;
+                checksum = crc::update(checksum, &buffer[..len]);
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Makes the stripes of every shard a row of data at a time.
+pub struct Splitter {
+    shards: usize,
+    required: usize,
+    parity: Vec<Vec<u8>>,
+    length: u64,
+    checksum: u32,
+    /// The length and checksum of the rows split so far.
+    split: (u64, u32),
+}
+
+impl Splitter {
+    /// Splits `length` bytes of data whose CRC-32 is `checksum`.
+    pub fn new(shards: usize, required: usize, length: u64, checksum: u32) -> Result<Splitter, String> {
+        if required == 0 || required > shards || shards > MAX_SHARDS {
+            return Err(format!("the number of shards required must be from 1 to the number of shards, \
+                which can be at most {}", MAX_SHARDS));
+        }
+        Ok(Splitter { shards, required, parity: parity_matrix(shards, required), length, checksum, split: (0, 0) })
+    }
+
+    /// The header of each shard, to be written before its stripes.
+    pub fn headers(&self) -> Vec<Header> {
+        (0..self.shards).map(|index| Header {
+            index,
+            shards: self.shards,
+            required: self.required,
+            length: self.length,
+            checksum: self.checksum,
+        }).collect()
+    }
+
+    /// Whether the rows split so far are the data the headers describe.
+    pub fn is_complete(&self) -> bool {
+        self.split == (self.length, self.checksum)
+    }
+
+    /// The stripe of each shard for a row of data, each followed by its
+    /// checksum. Every row but the last must be `required * STRIPE_LEN` bytes.
+    pub fn split_row(&mut self, row: &[u8]) -> Vec<Vec<u8>> {
+        self.split = (self.split.0 + row.len() as u64, crc::update(self.split.1, row));
+        let stripe_len = row.len().div_ceil(self.required);
+        let mut stripes: Vec<Vec<u8>> = (0..self.required).map(|i| {
+            let start = (i * stripe_len).min(row.len());
+            let mut stripe = row[start..(start + stripe_len).min(row.len())].to_vec();
+            stripe.resize(stripe_len, 0);
+            stripe
+        }).collect();
+        for coefficients in &self.parity {
+            let mut parity = vec![0; stripe_len];
+            for (stripe, &coefficient) in stripes.iter().zip(coefficients) {
+                add_scaled(&mut parity, stripe, coefficient);
+            }
+            stripes.push(parity);
+        }
+        for stripe in &mut stripes {
+            let crc = crc32(stripe);
+            stripe.extend_from_slice(&crc.to_be_bytes());
+        }
+        stripes
+    }
+}
+
+/// Recovers data a row at a time from the stripes of any `required` shards.
+pub struct Joiner {
+    shards: usize,
+    required: usize,
+    parity: Vec<Vec<u8>>,
+    length: u64,
+    checksum: u32,
+    /// The length and checksum of the data recovered so far.
+    joined: (u64, u32),
+    /// The shards the last row was recovered from, and the inverse of their rows.
+    inverse: Option<(Vec<usize>, Vec<Vec<u8>>)>,
+    damaged: Vec<usize>,
+}
+
+impl Joiner {
+    /// Recovers the data that shards with `header` (any of them) belong to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the numbers of shards are impossible, which `Header::parse` rules out.
+    pub fn new(header: &Header) -> Joiner {
+        let (shards, required) = (header.shards, header.required);
+        assert!(required > 0 && required <= shards && shards <= MAX_SHARDS, "there can't be {} of {} shards",
+            required, shards);
+        Joiner {
+            shards,
+            required,
+            parity: parity_matrix(shards, required),
+            length: header.length,
+            checksum: header.checksum,
+            joined: (0, 0),
+            inverse: None,
+            damaged: vec![0; shards],
+        }
+    }
+
+    /// Recovers a row of data from the stripe of the row with its checksum
+    /// for each shard by index, or `None` for the shards that are missing.
+    /// Stripes that don't match their checksums are left out. The padding
+    /// of the last row is left out too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there isn't a stripe or `None` for each shard, or the stripes
+    /// aren't all the same length.
+    pub fn join_row(&mut self, stripes: &[Option<&[u8]>]) -> Result<Vec<u8>, ShardError> {
+        assert_eq!(stripes.len(), self.shards, "there must be a stripe or None for each shard");
+        let mut chosen = Vec::with_capacity(self.required);
+        for (index, stripe) in stripes.iter().enumerate() {
+            if let Some(stripe) = *stripe {
+                if !intact(stripe) {
+                    self.damaged[index] += 1;
+                } else if chosen.len() < self.required {
+                    chosen.push(index);
+                }
+            }
+        }
+        if chosen.len() < self.required {
+            return Err(ShardError::TooFew { found: chosen.len(), required: self.required });
+        }
+        let stripe = |index: usize| {
+            let stripe = stripes[index].expect("only given stripes are chosen");
+            &stripe[..stripe.len() - STRIPE_CHECKSUM_LEN]
+        };
+        let stripe_len = stripe(chosen[0]).len();
+        let mut data = Vec::with_capacity(stripe_len * self.required);
+        // the data shards need no decoding, and are chosen first
+        if chosen.iter().enumerate().all(|(i, &index)| index == i) {
+            for &index in &chosen {
+                data.extend_from_slice(stripe(index));
+            }
+            return Ok(self.account(data));
+        }
+        if self.inverse.as_ref().is_none_or(|(shards, _)| *shards != chosen) {
+            let rows: Vec<Vec<u8>> = chosen.iter().map(|&index| match index.checked_sub(self.required) {
+                Some(row) => self.parity[row].clone(),
+                None => (0..self.required).map(|i| (i == index) as u8).collect(),
+            }).collect();
+            let inverse = invert(rows).expect("any rows of the generator matrix of an MDS code are independent");
+            self.inverse = Some((chosen.clone(), inverse));
+        }
+        let inverse = &self.inverse.as_ref().expect("the inverse was just made").1;
+        for coefficients in inverse {
+            let mut recovered = vec![0; stripe_len];
+            for (&index, &coefficient) in chosen.iter().zip(coefficients) {
+                add_scaled(&mut recovered, stripe(index), coefficient);
+            }
+            data.extend_from_slice(&recovered);
+        }
+        Ok(self.account(data))
+    }
+
+    // Drops the padding from a row and adds it to the data recovered.
+    fn account(&mut self, mut data: Vec<u8>) -> Vec<u8> {
+        data.truncate(cmp::min(data.len() as u64, self.length.saturating_sub(self.joined.0)) as usize);
+        self.joined = (self.joined.0 + data.len() as u64, crc::update(self.joined.1, &data));
+        data
+    }
+
+    /// Whether the rows joined so far are the data the headers describe.
+    pub fn is_complete(&self) -> bool {
+        self.joined == (self.length, self.checksum)
+    }
+
+    /// The number of stripes of each shard, by index, that were left out for
+    /// not matching their checksums.
+    pub fn damaged(&self) -> &[usize] {
+        &self.damaged
+    }
+}
+
+/// Which of some shards to join.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Choice {
+    /// The positions of the shards of the data being recovered, one for each
+    /// index found.
+    pub chosen: Vec<usize>,
+    /// The positions of the shards that belong to different data.
+    pub skipped: Vec<usize>,
+}
+
+/// Chooses shards to recover data from, by their headers. Shards of different
+/// data can be mixed in: the shards are grouped by the data they say they
+/// belong to, and the first group with enough different shards is chosen.
+/// Shards given more than once are only counted once.
+pub fn choose(headers: &[Header]) -> Result<Choice, ShardError> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (position, header) in headers.iter().enumerate() {
+        match groups.iter_mut().find(|group| headers[group[0]].data_key() == header.data_key()) {
+            Some(group) => group.push(position),
+            None => groups.push(vec![position]),
+        }
+    }
+    let distinct: Vec<Vec<usize>> = groups.iter().map(|group| {
+        let mut distinct: Vec<usize> = Vec::with_capacity(group.len());
+        for &position in group {
+            if !distinct.iter().any(|&other| headers[other].index == headers[position].index) {
+                distinct.push(position);
+            }
+        }
+        distinct
+    }).collect();
+    let required = |group: &Vec<usize>| headers[group[0]].required;
+    match distinct.iter().position(|group| group.len() >= required(group)) {
+        Some(group) => Ok(Choice {
+            chosen: distinct[group].clone(),
+            skipped: (0..headers.len()).filter(|position| !groups[group].contains(position)).collect(),
+        }),
+        None => Err(distinct.iter().max_by_key(|group| group.len())
+            .map_or(ShardError::TooFew { found: 0, required: 1 },
+                |group| ShardError::TooFew { found: group.len(), required: required(group) })),
+    }
+}
+
+/// Splits `data` into `shards` shards, any `required` of which recover it.
+pub fn split(data: &[u8], shards: usize, required: usize) -> Result<Vec<Shard>, String> {
+    let mut splitter = Splitter::new(shards, required, data.len() as u64, crc32(data))?;
+    let mut pieces: Vec<Shard> = splitter.headers().into_iter()
+        .map(|header| Shard { header, body: Vec::new() })
+        .collect();
+    for row in data.chunks(required * STRIPE_LEN) {
+        for (piece, stripe) in pieces.iter_mut().zip(splitter.split_row(row)) {
+            piece.body.extend_from_slice(&stripe);
+        }
+    }
+    debug_assert!(splitter.is_complete());
+    Ok(pieces)
+}
+
+/// Data recovered from its shards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Joined {
+    pub data: Vec<u8>,
+    /// The positions of the shards given that belong to different data.
+    pub skipped: Vec<usize>,
+}
+
+/// Recovers the data from enough of its shards, as `split` or `Shard::parse`
+/// made them, in any order (see `choose`). Damaged stripes are left out, so a
+/// row only needs `required` intact stripes among all the shards.
+pub fn join(shards: &[Shard]) -> Result<Joined, ShardError> {
+    let headers: Vec<Header> = shards.iter().map(|shard| shard.header).collect();
+    let Choice { chosen, skipped } = choose(&headers)?;
+    let header = headers[chosen[0]];
+    let mut joiner = Joiner::new(&header);
+    let mut data = Vec::with_capacity(header.length as usize);
+    let mut offset = 0;
+    for stripe_len in header.rows() {
+        let end = offset + stripe_len + STRIPE_CHECKSUM_LEN;
+        let mut stripes = vec![None; header.shards];
+        for &position in &chosen {
+            stripes[headers[position].index] = Some(&shards[position].body[offset..end]);
+        }
+        data.extend_from_slice(&joiner.join_row(&stripes)?);
+        offset = end;
+    }
+    if !joiner.is_complete() {
+        return Err(ShardError::Corrupt);
+    }
+    Ok(Joined { data, skipped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rng::Rng;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn bytes() {
+        let shards = split(&sample(1000), 10, 6).unwrap();
+        assert_eq!(shards.len(), 10);
+        let bytes = shards[7].to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 167 + STRIPE_CHECKSUM_LEN);
+        assert_eq!(Shard::parse(&bytes), Ok(shards[7].clone()));
+
+        let mut damaged = bytes.clone();
+        damaged[10] ^= 1;
+        assert_eq!(Shard::parse(&damaged), Err(ShardError::Corrupt));
+        assert_eq!(Shard::parse(&bytes[..10]), Err(ShardError::Truncated));
+        assert_eq!(Shard::parse(&bytes[..bytes.len() - 1]), Err(ShardError::Truncated));
+        assert_eq!(Shard::parse(b"not a shard at all"), Err(ShardError::BadMagic));
+        let mut later = bytes.clone();
+        later[4] = 2;
+        assert_eq!(Shard::parse(&later), Err(ShardError::UnsupportedVersion(2)));
+
+        let rows: Vec<usize> = split(&sample(STRIPE_LEN * 6 + 1000), 5, 3).unwrap()[0].header.rows().collect();
+        assert_eq!(rows, [STRIPE_LEN, STRIPE_LEN, 334]);
+    }
+
+    #[test]
+    fn recovery_from_any_shards() {
+        let mut rng = Rng::new(5);
+        for &(len, shards, required) in &[(1000, 10, 6), (0, 3, 2), (1, 4, 1), (777, 5, 5), (3000, 255, 200),
+            (STRIPE_LEN * 6 + 1000, 5, 3)]
+        {
+            let test = sample(len);
+            let all = split(&test, shards, required).unwrap();
+            for _ in 0..10 {
+                let mut kept = all.clone();
+                while kept.len() > required {
+                    kept.remove(rng.below(kept.len()));
+                }
+                assert_eq!(join(&kept).map(|joined| joined.data), Ok(test.clone()), "{:?}", (len, shards, required));
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_stripes() {
+        // a different shard damaged in each row is fine with one to spare
+        let test = sample(STRIPE_LEN * 6 + 1000);
+        let mut shards = split(&test, 5, 3).unwrap();
+        shards.truncate(4);
+        shards[0].body[5] ^= 1;
+        shards[3].body[STRIPE_LEN + 10] ^= 1;
+        shards[1].body[2 * (STRIPE_LEN + STRIPE_CHECKSUM_LEN) + 5] ^= 1;
+        assert_eq!(join(&shards).map(|joined| joined.data), Ok(test.clone()));
+        // but not two in the same row
+        shards[2].body[5] ^= 1;
+        assert_eq!(join(&shards), Err(ShardError::TooFew { found: 2, required: 3 }));
+
+        let mut joiner = Joiner::new(&shards[0].header);
+        let stripes: Vec<Option<&[u8]>> = shards.iter().map(|shard| Some(&shard.body[..STRIPE_LEN + 4]))
+            .chain(Some(None)).collect();
+        assert!(joiner.join_row(&stripes).is_err());
+        assert_eq!(joiner.damaged(), [1, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(split(b"data", 3, 4).is_err());
+        assert!(split(b"data", 256, 4).is_err());
+        assert!(split(b"data", 3, 0).is_err());
+
+        let test = sample(1000);
+        let shards = split(&test, 10, 6).unwrap();
+        assert_eq!(join(&[]), Err(ShardError::TooFew { found: 0, required: 1 }));
+        let repeated = vec![shards[8].clone(); 6];
+        assert_eq!(join(&repeated), Err(ShardError::TooFew { found: 1, required: 6 }));
+        let mut others = split(&sample(999), 10, 6).unwrap();
+        others.truncate(3);
+        others.extend_from_slice(&shards[3..6]);
+        assert_eq!(join(&others), Err(ShardError::TooFew { found: 3, required: 6 }));
+
+        // damage the checksum of each stripe can't catch is caught by that of the data
+        let mut damaged = shards[4..10].to_vec();
+        let body = &mut damaged[0].body;
+        body[0] ^= 1;
+        let end = body.len() - STRIPE_CHECKSUM_LEN;
+        let crc = crc32(&body[..end]);
+        body[end..].copy_from_slice(&crc.to_be_bytes());
+        assert_eq!(join(&damaged), Err(ShardError::Corrupt));
+    }
+
+    #[test]
+    fn other_data_mixed_in() {
+        let test = sample(1000);
+        let shards = split(&test, 10, 6).unwrap();
+        let other = split(&sample(1001), 10, 6).unwrap();
+        // a shard of another file first, and one in the middle
+        let mut mixed = vec![other[2].clone()];
+        mixed.extend_from_slice(&shards[..3]);
+        mixed.push(other[7].clone());
+        mixed.extend_from_slice(&shards[6..9]);
+        assert_eq!(join(&mixed), Ok(Joined { data: test.clone(), skipped: vec![0, 4] }));
+
+        // shards of the other file are used if it's the one there are enough of
+        let mut mixed = other[..6].to_vec();
+        mixed.insert(1, shards[0].clone());
+        assert_eq!(join(&mixed), Ok(Joined { data: sample(1001), skipped: vec![1] }));
+    }
+}
@@ -0,0 +1,83 @@
+// Soft decisions: rather than a bit, each code bit is given as how likely it
+// is to be a zero or a one, as a demodulator measures it. Codecs that can use
+// this weigh the bits they are sure of above the ones they aren't, which
+// corrects more than deciding each bit on its own first. Codecs that can't
+// decode the hard decisions instead.
+//
+// A soft bit is a log-likelihood ratio scaled to fit an `i8`: positive for a
+// zero, negative for a one, and larger the more certain it is, with 0 saying
+// nothing at all. Demodulators that put out unsigned confidences instead, from
+// 0 for a certain zero to 255 for a certain one, are converted by
+// `from_confidences`.
+//
+// Bits known to be unreadable, like those of bad sectors, are soft bits of 0.
+
+use std::cmp;
+use std::ops::Range;
+
+/// The most certain soft bit.
+pub const CERTAIN: i8 = 127;
+
+/// Packs the hard decisions for some soft bits into bytes, most significant
+/// bit first. A soft bit of 0 is taken as a zero.
+pub fn hard_decisions(soft: &[i8]) -> Vec<u8> {
+    soft.chunks(8).map(|bits| {
+        bits.iter().enumerate().fold(0, |byte, (i, &llr)| byte | ((llr < 0) as u8) << (7 - i))
+    }).collect()
+}
+
+/// The bits of some bytes as certain soft bits.
+pub fn from_hard(code: &[u8]) -> Vec<i8> {
+    code.iter().flat_map(|&byte| (0..8).rev().map(move |i| if byte >> i & 1 == 1 { -CERTAIN } else { CERTAIN }))
+        .collect()
+}
+
+/// The bits of some bytes as certain soft bits, except for the bits of the
+/// erased bytes, which say nothing.
+pub fn with_erasures(code: &[u8], erasures: &[Range<usize>]) -> Vec<i8> {
+    let mut soft = from_hard(code);
+    for range in erasures {
+        let end = cmp::min(range.end, code.len());
+        if range.start < end {
+            for bit in &mut soft[range.start * 8..end * 8] {
+                *bit = 0;
+            }
+        }
+    }
+    soft
+}
+
+/// Reads soft bits given as signed bytes.
+pub fn from_llrs(bytes: &[u8]) -> Vec<i8> {
+    // -128 would make a one slightly more certain than any zero can be
+    bytes.iter().map(|&b| (b as i8).max(-CERTAIN)).collect()
+}
+
+/// Reads soft bits given as unsigned confidences that each is a one, where 127
+/// says nothing either way.
+pub fn from_confidences(bytes: &[u8]) -> Vec<i8> {
+    bytes.iter().map(|&b| (127 - i16::from(b)).max(-i16::from(CERTAIN)) as i8).collect()
+}
+
+/// How certain a soft bit is.
+pub fn reliability(llr: i8) -> u32 {
+    (llr as i32).unsigned_abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let code = [0b1010_0110, 0xFF, 0];
+        assert_eq!(hard_decisions(&from_hard(&code)), code);
+        assert_eq!(from_llrs(&[0, 5, 0xFB, 0x80, 0x7F]), vec![0, 5, -5, -127, 127]);
+        assert_eq!(from_confidences(&[0, 127, 128, 255]), vec![127, 0, -1, -127]);
+        assert_eq!(hard_decisions(&[1, -1, 0, -100]), vec![0b0101_0000]);
+        let unreadable = 1..5;
+        let erased = with_erasures(&code, &[unreadable]);
+        assert_eq!(erased[..8], from_hard(&code)[..8]);
+        assert!(erased[8..].iter().all(|&bit| bit == 0));
+    }
+}
@@ -0,0 +1,762 @@
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use codec::{Codec, DecodeError, DecodeOutput, Report, Unit};
+use crc;
+use header::{self, Header, HeaderError, Trailer};
+use parallel;
+use {Config, Error};
+
+// Streams are encoded a chunk at a time (see `Codec::chunk_len`), so only one
+// chunk is held in memory whatever the size of the data. The length of the data
+// isn't known until the end, so the header is marked as streamed and the length
+// and checksums follow the payload in a trailer. With more than one thread,
+// several chunks are held at a time and shared between the threads.
+
+/// Encodes everything written to it into `inner`. Call `finish` once all of
+/// the data has been written, to encode the last partial chunk and the trailer.
+pub struct EncodeWriter<W: Write> {
+    inner: W,
+    config: Config,
+    /// Data that hasn't been encoded yet, always less than a batch of chunks.
+    buffer: Vec<u8>,
+    length: u64,
+    checksum: u32,
+    data_checksum: u32,
+}
+
+impl<W: Write> EncodeWriter<W> {
+    /// Writes the header to `inner` straight away.
+    pub fn new(mut inner: W, config: Config) -> io::Result<EncodeWriter<W>> {
+        let header = config.header(header::STREAMED, 0, 0);
+        inner.write_all(&header.to_bytes())?;
+        let buffer = Vec::with_capacity(config.codec.chunk_len() * parallel::batch(config.threads));
+        Ok(EncodeWriter { inner, config, buffer, length: 0, checksum: 0, data_checksum: 0 })
+    }
+
+    // The number of bytes of data encoded at a time.
+    fn batch_len(&self) -> usize {
+        self.config.codec.chunk_len() * parallel::batch(self.config.threads)
+    }
+
+    fn encode_chunks(&self, data: &[u8]) -> Vec<u8> {
+        parallel::encode_chunks(self.config.codec(), data, self.config.threads)
+    }
+
+    fn emit(&mut self, code: &[u8]) -> io::Result<()> {
+        self.checksum = crc::update(self.checksum, code);
+        self.inner.write_all(code)
+    }
+
+    /// Encodes whatever is left and writes the trailer, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let code = self.config.codec.encode(&self.buffer);
+        self.emit(&code)?;
+        let trailer = Trailer { length: self.length, checksum: self.checksum, data_checksum: Some(self.data_checksum) };
+        self.inner.write_all(&trailer.to_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncodeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let batch = self.batch_len();
+        let mut data = buf;
+        if !self.buffer.is_empty() {
+            let wanted = cmp::min(batch - self.buffer.len(), data.len());
+            self.buffer.extend_from_slice(&data[..wanted]);
+            data = &data[wanted..];
+            if self.buffer.len() == batch {
+                let code = self.encode_chunks(&self.buffer);
+                self.emit(&code)?;
+                self.buffer.clear();
+            }
+        }
+        // whole batches can be encoded without copying them into the buffer
+        let whole = data.len() / batch * batch;
+        if whole > 0 {
+            let code = self.encode_chunks(&data[..whole]);
+            self.emit(&code)?;
+        }
+        self.buffer.extend_from_slice(&data[whole..]);
+        self.length += buf.len() as u64;
+        self.data_checksum = crc::update(self.data_checksum, buf);
+        Ok(buf.len())
+    }
+
+    /// Flushes the inner writer. Data in a partial batch can't be written until
+    /// the batch is full or the stream is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decodes data produced by an `EncodeWriter` or by `protect` from `inner`,
+/// correcting what errors it can.
+///
+/// Blocks with errors that can't be corrected are passed through as they are,
+/// so check `report` once everything has been read.
+pub struct DecodeReader<R: Read> {
+    inner: R,
+    codec: Box<dyn Codec>,
+    header: Header,
+    /// Encoded bytes that have been read but not decoded yet.
+    input: Vec<u8>,
+    /// Decoded bytes waiting to be read, from `position` on.
+    output: Vec<u8>,
+    position: usize,
+    /// The number of decoded bytes so far.
+    decoded: u64,
+    checksum: u32,
+    data_checksum: u32,
+    report: Report,
+    header_bits: usize,
+    threads: usize,
+    eof: bool,
+    finished: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+    /// Reads the header from `inner`.
+    pub fn new(mut inner: R) -> Result<DecodeReader<R>, Error> {
+        let (header, bytes) = next_header(&mut inner)?;
+        let codec = Config::from_header(&header)?.codec;
+        Ok(DecodeReader {
+            inner,
+            codec,
+            header,
+            input: Vec::new(),
+            output: Vec::new(),
+            position: 0,
+            decoded: 0,
+            checksum: 0,
+            data_checksum: 0,
+            report: Report::new(0),
+            header_bits: bytes.len() * 8,
+            threads: 1,
+            eof: false,
+            finished: false,
+        })
+    }
+
+    /// Decodes chunks on up to `threads` threads (at least one). The output
+    /// and the report are the same whatever the number of threads.
+    pub fn threads(mut self, threads: usize) -> DecodeReader<R> {
+        self.threads = cmp::max(1, threads);
+        self
+    }
+
+    pub fn codec(&self) -> &dyn Codec {
+        self.codec.as_ref()
+    }
+
+    /// The header of the stream. For streamed data, the length and checksums
+    /// are only filled in from the trailer once everything has been read.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// What has been corrected so far. Locations are relative to the start of the stream.
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    /// Whether the encoded data has changed since it was written. This is
+    /// only known once everything has been read.
+    pub fn damaged(&self) -> bool {
+        self.finished && self.checksum != self.header.checksum
+    }
+
+    /// Whether no errors were found that couldn't be corrected, but the data
+    /// doesn't match the checksum of the original, so some were corrected
+    /// wrongly. This is only known once everything has been read, and never
+    /// for files with a version 2 header, which have no such checksum.
+    pub fn miscorrected(&self) -> bool {
+        self.finished && self.report.uncorrectable.is_empty()
+            && self.header.data_checksum.is_some_and(|checksum| checksum != self.data_checksum)
+    }
+
+    // Decodes the next batch of chunks, or the end of the payload, into `output`.
+    fn fill(&mut self) -> io::Result<()> {
+        let chunk = self.codec.encoded_chunk_len();
+        let batch = chunk * parallel::batch(self.threads);
+        let trailer = if self.header.is_streamed() { self.header.trailer_len() } else { 0 };
+        // the end of the payload has to be decoded differently from a whole
+        // chunk, so a chunk isn't decoded until it's certain something follows it
+        while !self.eof && self.input.len() <= batch + trailer {
+            let start = self.input.len();
+            self.input.resize(batch + trailer + 1, 0);
+            let result = self.inner.read(&mut self.input[start..]);
+            let read = *result.as_ref().unwrap_or(&0);
+            self.input.truncate(start + read);
+            match result {
+                Ok(0) => self.eof = true,
+                Ok(_) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.output.clear();
+        self.position = 0;
+        if self.input.len() > chunk + trailer {
+            let chunks = cmp::min((self.input.len() - trailer - 1) / chunk, batch / chunk);
+            let code: Vec<u8> = self.input.drain(..chunks * chunk).collect();
+            let pieces: Vec<&[u8]> = code.chunks(chunk).collect();
+            let codec = self.codec.as_ref();
+            let results = parallel::map(&pieces, self.threads, |piece| codec.decode_chunk(piece));
+            for (piece, result) in pieces.iter().zip(results) {
+                self.accept(piece, result);
+            }
+            return Ok(());
+        }
+
+        if self.input.len() < trailer {
+            return Err(invalid_data(HeaderError::MissingTrailer));
+        }
+        let end = self.input.len() - trailer;
+        if self.header.is_streamed() {
+            let trailer = Trailer::parse(&self.input[end..]).map_err(invalid_data)?;
+            self.header.complete(&trailer);
+        }
+        let code: Vec<u8> = self.input.drain(..end).collect();
+        let result = self.codec.decode(&code);
+        self.accept(&code, result);
+        self.input.clear();
+        self.finished = true;
+        Ok(())
+    }
+
+    fn accept(&mut self, code: &[u8], result: Result<DecodeOutput, DecodeError>) {
+        self.checksum = crc::update(self.checksum, code);
+        let mut output = match result {
+            Ok(output) => output,
+            Err(DecodeError::Uncorrectable { output, .. }) => output,
+        };
+        if !self.header.is_streamed() {
+            let remaining = self.header.length.saturating_sub(self.decoded);
+            output.data.truncate(cmp::min(remaining, output.data.len() as u64) as usize);
+        }
+        self.decoded += output.data.len() as u64;
+        self.data_checksum = crc::update(self.data_checksum, &output.data);
+        output.report.offset(self.header_bits);
+        self.report.append(output.report);
+        self.output.extend(output.data);
+    }
+}
+
+fn read_exact<R: Read>(inner: &mut R, bytes: &mut [u8], eof: HeaderError) -> Result<(), Error> {
+    inner.read_exact(bytes).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Header(eof),
+        _ => Error::Io(err),
+    })
+}
+
+// Reads a header from `inner`, returning it and the bytes it was read from.
+fn next_header<R: Read>(inner: &mut R) -> Result<(Header, Vec<u8>), Error> {
+    // the start of the header says how long it is
+    let mut bytes = vec![0; header::PREFIX_LEN];
+    read_exact(inner, &mut bytes, HeaderError::Truncated)?;
+    let len = header::encoded_len(&bytes)?;
+    bytes.resize(len, 0);
+    read_exact(inner, &mut bytes[header::PREFIX_LEN..], HeaderError::Truncated)?;
+    Ok((Header::parse(&bytes)?, bytes))
+}
+
+/// Reads the header at the start of `inner` without reading the payload. If
+/// the data was streamed, the length and checksums are read from the trailer.
+pub fn read_header<R: Read + Seek>(inner: &mut R) -> Result<Header, Error> {
+    inner.seek(SeekFrom::Start(0))?;
+    let (mut header, bytes) = next_header(inner)?;
+    if header.is_streamed() {
+        let trailer_len = header.trailer_len();
+        let end = inner.seek(SeekFrom::End(0))?;
+        if end < (bytes.len() + trailer_len) as u64 {
+            return Err(Error::Header(HeaderError::MissingTrailer));
+        }
+        inner.seek(SeekFrom::End(-(trailer_len as i64)))?;
+        let mut bytes = vec![0; trailer_len];
+        read_exact(inner, &mut bytes, HeaderError::MissingTrailer)?;
+        let trailer = Trailer::parse(&bytes)?;
+        header.complete(&trailer);
+    }
+    Ok(header)
+}
+
+/// What `scrub` found and fixed.
+#[derive(Debug)]
+pub struct Scrub {
+    /// The errors in the payload. Locations are relative to the start of the file.
+    pub report: Report,
+    /// The number of bytes that were written back, including any in the header and trailer.
+    pub rewritten: u64,
+    /// Whether the payload still differs from what was written, because some
+    /// of it couldn't be corrected.
+    pub damaged: bool,
+    /// Whether the data doesn't match the checksum of the original, though no
+    /// errors were found that couldn't be corrected. The payload is then left
+    /// as it is, since encoding what was corrected wrongly would hide the damage.
+    pub miscorrected: bool,
+}
+
+impl Scrub {
+    // Writes the runs of bytes in `fixed` that differ from `stored`, which was read from `offset`.
+    fn rewrite<F: Write + Seek>(&mut self, file: &mut F, offset: u64, stored: &[u8], fixed: &[u8]) -> io::Result<()> {
+        let mut i = 0;
+        while i < stored.len() {
+            let run = stored[i..].iter().zip(&fixed[i..]).take_while(|&(a, b)| a != b).count();
+            if run == 0 {
+                i += 1;
+                continue;
+            }
+            file.seek(SeekFrom::Start(offset + i as u64))?;
+            file.write_all(&fixed[i..i + run])?;
+            self.rewritten += run as u64;
+            i += run;
+        }
+        Ok(())
+    }
+}
+
+// What a pass over the payload found.
+struct Pass {
+    report: Report,
+    /// The checksum of the payload as it is once the pass has been written back.
+    checksum: u32,
+    data_checksum: u32,
+    /// Whether anything differs from what encoding the data again gives.
+    differs: bool,
+}
+
+/// Corrects the errors in encoded data in place, so that they don't pile up
+/// until there are more than the code can correct.
+///
+/// The payload is decoded a chunk at a time and encoded again, and only the
+/// bytes that differ from what is stored are written back. A codeword with
+/// errors that can't be corrected is left as it is, so that nothing is lost,
+/// as is the whole frame it is in if codewords are interleaved. When the
+/// header has a checksum of the data, the payload is decoded once to check it
+/// before anything in it is written.
+pub fn scrub<F: Read + Write + Seek>(file: &mut F) -> Result<Scrub, Error> {
+    let end = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    let (mut header, stored) = next_header(file)?;
+    let mut scrub = Scrub { report: Report::new(0), rewritten: 0, damaged: false, miscorrected: false };
+    // a version 1 header is left as it is rather than upgraded
+    let fixed = header.to_bytes();
+    if stored.len() == fixed.len() {
+        scrub.rewrite(file, 0, &stored, &fixed)?;
+    }
+    let start = stored.len() as u64;
+
+    let mut payload_end = end;
+    if header.is_streamed() {
+        let trailer_len = header.trailer_len() as u64;
+        if end < start + trailer_len {
+            return Err(Error::Header(HeaderError::MissingTrailer));
+        }
+        payload_end = end - trailer_len;
+        file.seek(SeekFrom::Start(payload_end))?;
+        let mut stored = vec![0; trailer_len as usize];
+        read_exact(file, &mut stored, HeaderError::MissingTrailer)?;
+        let trailer = Trailer::parse(&stored)?;
+        scrub.rewrite(file, payload_end, &stored, &trailer.to_bytes())?;
+        header.complete(&trailer);
+    }
+
+    let config = Config::from_header(&header)?;
+    let payload = start..payload_end;
+    // without a checksum of the data, the payload is written back as it is decoded
+    let checked = header.data_checksum.is_some();
+    let mut pass = scrub_payload(file, &config, &header, payload.clone(), !checked, &mut scrub)?;
+    if let Some(checksum) = header.data_checksum {
+        scrub.miscorrected = pass.report.uncorrectable.is_empty() && pass.data_checksum != checksum;
+        if !scrub.miscorrected && pass.differs {
+            pass = scrub_payload(file, &config, &header, payload, true, &mut scrub)?;
+        }
+    }
+    file.flush()?;
+    scrub.report = pass.report;
+    scrub.damaged = scrub.miscorrected || pass.checksum != header.checksum;
+    Ok(scrub)
+}
+
+// Decodes the payload in `range` a chunk at a time and encodes each chunk
+// again, and if `write` is set writes back what differs.
+fn scrub_payload<F: Read + Write + Seek>(file: &mut F, config: &Config, header: &Header, range: Range<u64>,
+    write: bool, scrub: &mut Scrub) -> Result<Pass, Error>
+{
+    let codec = config.codec();
+    let mut pass = Pass { report: Report::new(0), checksum: 0, data_checksum: 0, differs: false };
+    // the chunks are the same as `DecodeReader` decodes, so encoding them
+    // again gives back exactly what `EncodeWriter` wrote
+    let chunk = codec.encoded_chunk_len() as u64;
+    let mut position = range.start;
+    let mut decoded = 0;
+    while position < range.end {
+        let last = range.end - position <= chunk;
+        let mut code = vec![0; cmp::min(range.end - position, chunk) as usize];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut code)?;
+        let result = if last { codec.decode(&code) } else { codec.decode_chunk(&code) };
+        let mut output = match result {
+            Ok(output) => output,
+            Err(DecodeError::Uncorrectable { output, .. }) => output,
+        };
+        // codes that work on bits decode the padding of their last block too
+        let remaining = header.length.saturating_sub(decoded);
+        output.data.truncate(cmp::min(remaining, output.data.len() as u64) as usize);
+        decoded += output.data.len() as u64;
+        pass.data_checksum = crc::update(pass.data_checksum, &output.data);
+        let mut fixed = if last { codec.encode(&output.data) } else { codec.encode_chunk(&output.data) };
+        // a payload that has been cut short can't be encoded the same way again
+        if fixed.len() == code.len() {
+            for location in &output.report.uncorrectable {
+                keep_bits(&mut fixed, &code, untouched(config, location.bit, code.len() * 8));
+            }
+            if fixed != code {
+                pass.differs = true;
+                if write {
+                    scrub.rewrite(file, position, &code, &fixed)?;
+                }
+                code = fixed;
+            }
+        }
+        pass.checksum = crc::update(pass.checksum, &code);
+        output.report.offset(range.start as usize * 8);
+        pass.report.append(output.report);
+        position += code.len() as u64;
+    }
+    Ok(pass)
+}
+
+// The bits of a chunk of `code_bits` to leave alone for an uncorrectable block
+// starting at `bit`: the codeword, or the frame it is interleaved in.
+fn untouched(config: &Config, bit: usize, code_bits: usize) -> Range<usize> {
+    let capability = config.codec().correction_capability();
+    let unit = match capability.unit {
+        Unit::Bit => 1,
+        Unit::Byte => 8,
+    };
+    let (start, len) = match config.interleaving() {
+        Some((_, depth)) => {
+            let frame = capability.block * unit * depth;
+            (bit / frame * frame, frame)
+        }
+        None => (bit, capability.block * unit),
+    };
+    start..cmp::min(start + len, code_bits)
+}
+
+// Copies the bits in `range` of `stored` over those of `fixed`.
+fn keep_bits(fixed: &mut [u8], stored: &[u8], range: Range<usize>) {
+    for bit in range {
+        let mask = 0x80 >> (bit % 8);
+        fixed[bit / 8] = (fixed[bit / 8] & !mask) | (stored[bit / 8] & mask);
+    }
+}
+
+fn invalid_data(err: HeaderError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Error::Header(err))
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let len = cmp::min(buf.len(), self.output.len() - self.position);
+        buf[..len].copy_from_slice(&self.output[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {protect, recover};
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn stream(data: &[u8], config: Config, write_len: usize) -> Vec<u8> {
+        let mut writer = EncodeWriter::new(Vec::new(), config).unwrap();
+        for piece in data.chunks(write_len) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn unstream(encoded: &[u8]) -> (Vec<u8>, Report, bool) {
+        let mut reader = DecodeReader::new(encoded).unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        (data, reader.report().clone(), reader.damaged())
+    }
+
+    #[test]
+    fn inverse() {
+        for &(name, n) in &[("multiple", 3), ("hamming", 3), ("reed-solomon", 10), ("extended-hamming", 5)] {
+            let chunk = Config::new(name, &[n]).unwrap().codec().chunk_len();
+            for &len in &[0, 1, chunk - 1, chunk, chunk * 2, chunk * 2 + 5] {
+                let test = sample(len);
+                for &write_len in &[1000, chunk + 1] {
+                    let encoded = stream(&test, Config::new(name, &[n]).unwrap(), write_len);
+                    let (data, report, damaged) = unstream(&encoded);
+                    assert_eq!(data, test, "{} with {} bytes", name, len);
+                    assert!(report.corrected_bits.is_empty());
+                    assert!(!damaged);
+                    // the streamed format can also be read in one go
+                    assert_eq!(recover(&encoded).unwrap().data, test);
+                    assert_eq!(::inspect(&encoded).unwrap().header.length, len as u64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_payload() {
+        // streaming only changes the header and adds a trailer
+        let test = sample(100_000);
+        let config = Config::new("hamming", &[4]).unwrap();
+        let whole = protect(&test, &config);
+        let streamed = stream(&test, config, 4096);
+        let end = streamed.len() - header::TRAILER_LEN;
+        assert_eq!(&streamed[header::ENCODED_LEN..end], &whole[header::ENCODED_LEN..]);
+        assert_eq!(unstream(&whole).0, test);
+    }
+
+    #[test]
+    fn error_correction() {
+        let test = sample(200_000);
+        let mut encoded = stream(&test, Config::new("hamming", &[3]).unwrap(), 8192);
+        let errors = [header::ENCODED_LEN + 5, 100_000, encoded.len() - header::TRAILER_LEN - 1];
+        for &i in &errors {
+            encoded[i] ^= 0x08;
+        }
+        let (data, report, damaged) = unstream(&encoded);
+        assert_eq!(data, test);
+        assert!(damaged);
+        let bits: Vec<usize> = errors.iter().map(|&i| i * 8 + 4).collect();
+        assert_eq!(report.corrected_bits, bits);
+        assert_eq!(report, recover(&encoded).unwrap().report);
+    }
+
+    #[test]
+    fn threads() {
+        // the output, and what is corrected, doesn't depend on the number of threads
+        let config = || Config::new("extended-hamming", &[4]).unwrap();
+        let chunk = config().codec().chunk_len();
+        for &len in &[0, 100, chunk * 3, chunk * 40 + 17] {
+            let test = sample(len);
+            let mut encoded = stream(&test, config(), 10_000);
+            for i in (header::ENCODED_LEN..encoded.len() - header::TRAILER_LEN).step_by(997) {
+                encoded[i] ^= 0x40;
+            }
+            // a double error that can't be corrected
+            if len > 100 {
+                encoded[header::ENCODED_LEN + 1] ^= 0x03;
+            }
+            let (data, report, damaged) = unstream(&encoded);
+            for &threads in &[2, 3, 8] {
+                assert_eq!(stream(&test, config().threads(threads), 10_000), stream(&test, config(), 10_000));
+                assert_eq!(protect(&test, &config().threads(threads)), protect(&test, &config()));
+                let mut reader = DecodeReader::new(&encoded[..]).unwrap().threads(threads);
+                let mut decoded = Vec::new();
+                reader.read_to_end(&mut decoded).unwrap();
+                assert_eq!((&decoded, reader.report(), reader.damaged()), (&data, &report, damaged), "{} threads", threads);
+            }
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let test = sample(1000);
+        let config = Config::new("multiple", &[3]).unwrap().interleave(::interleave::Kind::Random, 5).unwrap();
+        let streamed = stream(&test, config, 100);
+        let header = read_header(&mut io::Cursor::new(&streamed)).unwrap();
+        assert_eq!(header.length, 1000);
+        assert_eq!(header.depth, 5);
+        assert_eq!(header, ::inspect(&streamed).unwrap().header);
+
+        let whole = protect(&test, &Config::default());
+        assert_eq!(read_header(&mut io::Cursor::new(&whole)).unwrap().length, 1000);
+
+        let cut = &streamed[..streamed.len() - 1];
+        assert!(matches!(read_header(&mut io::Cursor::new(cut)), Err(Error::Header(HeaderError::MissingTrailer))));
+    }
+
+    #[test]
+    fn scrubbing() {
+        let test = sample(300_000);
+        let configs = || vec![
+            Config::new("multiple", &[3]).unwrap(),
+            Config::new("hamming", &[4]).unwrap(),
+            Config::new("extended-hamming", &[3]).unwrap(),
+            Config::new("reed-solomon", &[8]).unwrap(),
+            Config::new("hamming", &[3]).unwrap().interleave(::interleave::Kind::Block, 8).unwrap(),
+        ];
+        for (config, other) in configs().into_iter().zip(configs()) {
+            let name = config.codec().name();
+            let whole = protect(&test, &other);
+            let streamed = stream(&test, config, 10_000);
+            for encoded in [whole, streamed].iter() {
+                let mut damaged = encoded.clone();
+                // the header, the first and last bytes of the payload and the trailer or the end
+                let errors = [40, header::ENCODED_LEN, 150_000, encoded.len() - header::TRAILER_LEN - 1, encoded.len() - 1];
+                for &i in &errors {
+                    damaged[i] ^= 0x04;
+                }
+                let mut file = io::Cursor::new(damaged);
+                let result = scrub(&mut file).unwrap();
+                assert_eq!(file.get_ref(), encoded, "{}", name);
+                assert_eq!(result.rewritten, errors.len() as u64, "{}", name);
+                assert!(result.report.uncorrectable.is_empty());
+                assert!(!result.damaged);
+
+                // nothing is written to an intact file
+                let result = scrub(&mut file).unwrap();
+                assert_eq!(result.rewritten, 0);
+                assert!(result.report.corrected_bits.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn scrubbing_uncorrectable() {
+        let test = sample(300_000);
+        let encoded = stream(&test, Config::new("extended-hamming", &[3]).unwrap(), 10_000);
+        let mut damaged = encoded.clone();
+        damaged[1000] ^= 0x01;
+        // two errors in a block are detected but can't be corrected
+        damaged[200_000] ^= 0x03;
+        damaged[200_500] ^= 0x01;
+        let mut file = io::Cursor::new(damaged.clone());
+        let result = scrub(&mut file).unwrap();
+        assert!(result.damaged);
+        assert_eq!(result.report.uncorrectable.len(), 1);
+        assert_eq!(result.rewritten, 2);
+        assert_eq!(file.get_ref()[1000], encoded[1000]);
+        // only the block that couldn't be corrected is left alone, not the rest of its chunk
+        assert_eq!(file.get_ref()[200_000], damaged[200_000]);
+        assert_eq!(file.get_ref()[200_500], encoded[200_500]);
+
+        let cut = encoded[..encoded.len() - 1].to_vec();
+        assert!(matches!(scrub(&mut io::Cursor::new(cut)), Err(Error::Header(HeaderError::MissingTrailer))));
+    }
+
+    #[test]
+    fn scrubbing_around_uncorrectable() {
+        let test = sample(100_000);
+        let start = header::ENCODED_LEN;
+        // a block interleaver 8 deep puts the nth bits of a frame's 8 codewords in the nth byte
+        let config = || Config::new("extended-hamming", &[3]).unwrap().interleave(::interleave::Kind::Block, 8).unwrap();
+        let encoded = protect(&test, &config());
+        let mut damaged = encoded.clone();
+        damaged[start + 800] ^= 0x80;
+        damaged[start + 801] ^= 0x80;
+        damaged[start + 805] ^= 0x01;
+        damaged[start + 808] ^= 0x01;
+        let mut file = io::Cursor::new(damaged.clone());
+        let result = scrub(&mut file).unwrap();
+        assert_eq!(result.report.uncorrectable.len(), 1);
+        assert_eq!(result.rewritten, 1);
+        // the frame with the uncorrectable codeword is left as it is
+        assert_eq!(file.get_ref()[start + 800..start + 808], damaged[start + 800..start + 808]);
+        assert_eq!(file.get_ref()[start + 808], encoded[start + 808]);
+
+        // Reed-Solomon blocks of 255 bytes, with 9 errors in the second and 1 in the third
+        let encoded = stream(&test, Config::new("reed-solomon", &[16]).unwrap(), 10_000);
+        let mut damaged = encoded.clone();
+        for i in 0..9 {
+            damaged[start + 255 + i * 20] ^= 0xFF;
+        }
+        damaged[start + 600] ^= 0x10;
+        let mut file = io::Cursor::new(damaged.clone());
+        let result = scrub(&mut file).unwrap();
+        assert_eq!(result.report.uncorrectable.len(), 1);
+        assert_eq!(result.rewritten, 1);
+        assert_eq!(file.get_ref()[start + 255..start + 510], damaged[start + 255..start + 510]);
+        assert_eq!(file.get_ref()[start + 600], encoded[start + 600]);
+        assert_eq!(scrub(&mut file).unwrap().rewritten, 0);
+    }
+
+    #[test]
+    fn miscorrection() {
+        let test = sample(100_000);
+        let encoded = stream(&test, Config::new("hamming", &[3]).unwrap(), 10_000);
+        let mut damaged = encoded.clone();
+        damaged[header::ENCODED_LEN + 20] ^= 0x01;
+        // two errors in a block are taken for a different single error
+        damaged[header::ENCODED_LEN + 50_000] ^= 0x03;
+        let mut reader = DecodeReader::new(&damaged[..]).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert!(reader.miscorrected());
+        assert!(matches!(recover(&damaged), Err(Error::Miscorrected(_))));
+
+        // nothing is written back until the data matches its checksum
+        let mut file = io::Cursor::new(damaged.clone());
+        let result = scrub(&mut file).unwrap();
+        assert!(result.miscorrected && result.damaged);
+        assert_eq!(result.rewritten, 0);
+        assert_eq!(file.get_ref(), &damaged);
+
+        let mut reader = DecodeReader::new(&encoded[..]).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert!(!reader.miscorrected());
+    }
+
+    #[test]
+    fn version_2() {
+        // files written before there was a checksum of the data can still be read and scrubbed
+        let test = sample(10_000);
+        let encoded = stream(&test, Config::default(), 1000);
+        let end = encoded.len() - header::TRAILER_LEN;
+        let mut header = Header::parse(&encoded).unwrap();
+        let mut trailer = Trailer::parse(&encoded[end..]).unwrap();
+        header.data_checksum = None;
+        trailer.data_checksum = None;
+        let mut old = header.to_bytes();
+        old.extend_from_slice(&encoded[header::ENCODED_LEN..end]);
+        old.extend_from_slice(&trailer.to_bytes());
+        let (data, _, damaged) = unstream(&old);
+        assert_eq!(data, test);
+        assert!(!damaged);
+        assert_eq!(recover(&old).unwrap().data, test);
+
+        let mut file = io::Cursor::new(old.clone());
+        file.get_mut()[1000] ^= 0x10;
+        let result = scrub(&mut file).unwrap();
+        assert_eq!(result.rewritten, 1);
+        assert_eq!(file.get_ref(), &old);
+    }
+
+    #[test]
+    fn error_detection() {
+        let test = sample(1000);
+        let encoded = stream(&test, Config::new("reed-solomon", &[4]).unwrap(), 100);
+        assert!(matches!(DecodeReader::new(&encoded[..10]), Err(Error::Header(HeaderError::Truncated))));
+
+        // the trailer is missing
+        let mut reader = DecodeReader::new(&encoded[..encoded.len() - 100]).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(::recover(&encoded[..encoded.len() - 100]), Err(Error::Header(HeaderError::MissingTrailer))));
+
+        let mut damaged = encoded.clone();
+        for b in &mut damaged[header::ENCODED_LEN + 300..header::ENCODED_LEN + 310] {
+            *b = !*b;
+        }
+        let (data, report, _) = unstream(&damaged);
+        assert_eq!(data.len(), test.len());
+        assert_eq!(report.uncorrectable.len(), 1);
+        assert_eq!(report.uncorrectable[0].block, 1);
+    }
+}
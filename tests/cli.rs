@@ -0,0 +1,190 @@
+// Runs the command line program, to check what scripts rely on.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn eccfile(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_eccfile")).args(args).output().expect("the program runs")
+}
+
+// A directory of its own for each test, emptied first.
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("eccfile-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn exit_statuses() {
+    let dir = scratch("exit-statuses");
+    let plain = dir.join("plain");
+    fs::write(&plain, "not an encoded file, just some text\n".repeat(10)).unwrap();
+    let plain = plain.to_str().unwrap();
+
+    // a file that was never encoded is a mistake rather than damage
+    assert_eq!(eccfile(&["decode", plain, "-o", "-"]).status.code(), Some(2));
+    assert_eq!(eccfile(&["verify", plain]).status.code(), Some(2));
+    assert_eq!(eccfile(&["info", plain]).status.code(), Some(2));
+    assert_eq!(eccfile(&["decode", "--nonsense"]).status.code(), Some(2));
+    let missing = dir.join("missing");
+    assert_eq!(eccfile(&["decode", missing.to_str().unwrap(), "-o", "-"]).status.code(), Some(3));
+
+    assert_eq!(eccfile(&["encode", "-r", "-n", "4", plain]).status.code(), Some(0));
+    let encoded = format!("{}.ecc", plain);
+    let mut damaged = fs::read(&encoded).unwrap();
+    let end = damaged.len() - 12;
+    for byte in &mut damaged[end - 20..end] {
+        *byte ^= 0xFF;
+    }
+    fs::write(&encoded, &damaged).unwrap();
+    assert_eq!(eccfile(&["decode", &encoded, "-o", "-"]).status.code(), Some(1));
+
+    // two errors in a Hamming(7,4) block are taken for a different single error,
+    // which only the checksum of the data, after the 245 byte header, shows up
+    let hamming = format!("{}.hamming", plain);
+    assert_eq!(eccfile(&["encode", plain, "-o", &hamming]).status.code(), Some(0));
+    let mut damaged = fs::read(&hamming).unwrap();
+    damaged[245 + 10] ^= 0x03;
+    fs::write(&hamming, &damaged).unwrap();
+    assert_eq!(eccfile(&["decode", &hamming, "-o", "-"]).status.code(), Some(1));
+    assert_eq!(eccfile(&["verify", &hamming]).status.code(), Some(1));
+    assert_eq!(eccfile(&["repair", &hamming]).status.code(), Some(1));
+    assert_eq!(fs::read(&hamming).unwrap(), damaged);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn json_report() {
+    let dir = scratch("json-report");
+    let plain = dir.join("plain");
+    fs::write(&plain, "some text to encode\n".repeat(50)).unwrap();
+    let plain = plain.to_str().unwrap();
+    let encoded = format!("{}.ecc", plain);
+    assert_eq!(eccfile(&["encode", "-e", "-n", "4", plain]).status.code(), Some(0));
+    let mut damaged = fs::read(&encoded).unwrap();
+    damaged[300] ^= 0x01;
+    damaged[500] ^= 0x03;
+    fs::write(&encoded, &damaged).unwrap();
+
+    // nothing but the report is written to stderr, whatever the outcome
+    for args in [&["decode", "--report", "json", &encoded, "-o", "-"][..], &["verify", "--report", "json", &encoded]] {
+        let output = eccfile(args);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+        assert!(stderr.starts_with('{') && stderr.trim_end().ends_with('}'), "{}", stderr);
+        assert!(stderr.contains("\"damaged\":true"), "{}", stderr);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bench_ber_needs_frames() {
+    let output = eccfile(&["bench-ber", "--frames", "0", "--steps", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let output = eccfile(&["bench-ber", "--frames", "2", "--steps", "1", "--frame-length", "16"]);
+    assert_eq!(output.status.code(), Some(0));
+    let csv = String::from_utf8(output.stdout).unwrap();
+    assert!(!csv.contains("NaN"));
+
+    // the CSV only replaces the output once it is complete, and never without --force
+    let dir = scratch("bench-ber");
+    let file = dir.join("ber.csv");
+    let args = ["bench-ber", "--frames", "2", "--steps", "1", "--frame-length", "16", "-o", file.to_str().unwrap()];
+    assert_eq!(eccfile(&args).status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&file).unwrap(), csv);
+    assert_eq!(eccfile(&args).status.code(), Some(2));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_lt_round_trip() {
+    let dir = scratch("empty-lt");
+    let empty = dir.join("empty");
+    fs::write(&empty, "").unwrap();
+    let packets = dir.join("packets");
+    let output = eccfile(&["lt-encode", empty.to_str().unwrap(), "-o", packets.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(fs::metadata(&packets).unwrap().len() > 0);
+
+    let output = eccfile(&["lt-decode", packets.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    // with no packets at all there is nothing to say the file was empty
+    let output = eccfile(&["lt-decode", empty.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lt_decode_needs_the_right_code() {
+    let dir = scratch("lt-code");
+    let file = dir.join("file");
+    fs::write(&file, "some text to send\n".repeat(200)).unwrap();
+    let file = file.to_str().unwrap();
+    let raptorq = dir.join("raptorq");
+    let raptorq = raptorq.to_str().unwrap();
+    assert_eq!(eccfile(&["lt-encode", "--raptorq", file, "-o", raptorq]).status.code(), Some(0));
+    let output = eccfile(&["lt-decode", raptorq]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("try --raptorq"));
+    assert_eq!(eccfile(&["lt-decode", "--raptorq", raptorq]).status.code(), Some(0));
+
+    // and the other way round
+    let lt = dir.join("lt");
+    let lt = lt.to_str().unwrap();
+    assert_eq!(eccfile(&["lt-encode", file, "-o", lt]).status.code(), Some(0));
+    let output = eccfile(&["lt-decode", "--raptorq", lt]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("leave out --raptorq"));
+
+    // RaptorQ can't describe an empty file
+    let empty = dir.join("empty");
+    fs::write(&empty, "").unwrap();
+    assert_eq!(eccfile(&["lt-encode", "--raptorq", empty.to_str().unwrap(), "-o", "-"]).status.code(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn shards() {
+    let dir = scratch("shards");
+    let file = dir.join("file");
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(&file, &data).unwrap();
+    let other = dir.join("other");
+    fs::write(&other, "another file\n".repeat(100)).unwrap();
+    for path in &[&file, &other] {
+        let output = eccfile(&["encode", "-r", "-n", "8", "--shards", "5", "--required", "2", path.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(0));
+    }
+    let shard = |path: &PathBuf, index: usize| format!("{}.ecc.{}", path.display(), index);
+    assert_eq!(eccfile(&["encode", "--shards", "3", "--required", "2", "-o", &shard(&file, 9), "-"]).status.code(),
+        Some(2));
+
+    // a stripe of one shard too damaged to correct, a shard of another file,
+    // two missing and one given that isn't there
+    let mut damaged = fs::read(shard(&file, 1)).unwrap();
+    for byte in &mut damaged[1000..2000] {
+        *byte ^= 0xFF;
+    }
+    fs::write(shard(&file, 1), &damaged).unwrap();
+    let recovered = dir.join("recovered");
+    let output = eccfile(&["decode", "--shards", "-o", recovered.to_str().unwrap(), &shard(&other, 2),
+        &shard(&file, 1), &shard(&file, 4), &shard(&file, 5), &shard(&file, 6)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(&recovered).unwrap(), data);
+    let messages = String::from_utf8(output.stderr).unwrap();
+    assert!(messages.contains("belongs to a different file"), "{}", messages);
+    assert!(messages.contains("left out 1 damaged stripes"), "{}", messages);
+    assert!(messages.contains(&format!("{}: skipped", shard(&file, 6))), "{}", messages);
+
+    let output = eccfile(&["decode", "--shards", "-o", "-", &shard(&file, 1), &shard(&file, 4)]);
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}